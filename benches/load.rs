@@ -0,0 +1,131 @@
+//! Measures how many rows per second qsv loads from CSV files, both overall and for inserting
+//! into SQLite alone, so regressions in loading show up. Run with `cargo bench`, setting
+//! `QSV_BENCH_ROWS` to change the number of rows in each file.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const DEFAULT_ROWS: usize = 200_000;
+///Each scenario is run this many times, keeping the fastest run
+const RUNS: usize = 3;
+
+///A file to load, and the extra arguments to load it with
+struct Scenario {
+    name: &'static str,
+    columns: usize,
+    args: &'static [&'static str],
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "typed",
+        columns: 4,
+        args: &[],
+    },
+    Scenario {
+        name: "textonly",
+        columns: 4,
+        args: &["--textonly"],
+    },
+    Scenario {
+        name: "wide",
+        columns: 200,
+        args: &[],
+    },
+];
+
+///The time taken by the fastest run of a scenario
+struct Timing {
+    total: Duration,
+    insert: Duration,
+}
+
+fn main() {
+    let rows = std::env::var("QSV_BENCH_ROWS")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(DEFAULT_ROWS);
+    let directory = std::env::temp_dir().join(format!("qsv_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).expect("should create a directory for the benchmarks");
+    println!(
+        "{:<10} {:>8} {:>8} {:>12} {:>12}",
+        "scenario", "columns", "rows", "total/sec", "insert/sec"
+    );
+    for scenario in SCENARIOS.iter() {
+        // wide files get fewer rows, so every scenario has about the same number of values
+        let rows = (rows * 4 / scenario.columns).max(1);
+        let path = directory.join(format!("{}.csv", scenario.name));
+        write_csv(&path, scenario.columns, rows).expect("should write the benchmark's CSV");
+        let timing = (0..RUNS)
+            .map(|_| run(&path, scenario.args))
+            .min_by_key(|timing| timing.total)
+            .expect("should run at least once");
+        println!(
+            "{:<10} {:>8} {:>8} {:>12.0} {:>12.0}",
+            scenario.name,
+            scenario.columns,
+            rows,
+            rows as f64 / timing.total.as_secs_f64(),
+            rows as f64 / timing.insert.as_secs_f64()
+        );
+    }
+    std::fs::remove_dir_all(&directory).expect("should clean up the benchmarks' files");
+}
+
+///Writes a CSV with integer, float and text columns in turn
+fn write_csv(path: &Path, columns: usize, rows: usize) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let headers: Vec<String> = (0..columns).map(|i| format!("column_{}", i)).collect();
+    writeln!(writer, "{}", headers.join(","))?;
+    for row in 0..rows {
+        for column in 0..columns {
+            if column > 0 {
+                write!(writer, ",")?;
+            }
+            match column % 3 {
+                0 => write!(writer, "{}", row * (column + 1))?,
+                1 => write!(writer, "{}.{}", row, column)?,
+                _ => write!(writer, "value {}", row % 1000)?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+///Loads every row and column of a file, timing the whole run and the inserts reported in the
+///debug output
+fn run(path: &Path, args: &[&str]) -> Timing {
+    let query = format!("select count(*) from {}", path.display());
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_qsv"))
+        .env("RUST_LOG", "debug")
+        .arg("query")
+        .arg(query)
+        .arg("--no-pushdown")
+        .args(args)
+        .output()
+        .expect("should run qsv");
+    let total = start.elapsed();
+    assert!(
+        output.status.success(),
+        "qsv failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // the debug output is logged to stdout, along with the result of the query
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let insert_ms: u64 = stdout
+        .lines()
+        .filter_map(|line| {
+            let wrote = &line[line.find("wrote ")?..];
+            let ms = wrote.split(" in ").nth(1)?.trim_end_matches(" ms");
+            ms.parse::<u64>().ok()
+        })
+        .sum();
+    Timing {
+        total,
+        insert: Duration::from_millis(insert_ms.max(1)),
+    }
+}
@@ -1,10 +1,12 @@
 use csv::{Position, Reader, StringRecord, Trim};
 use log::debug;
-use std::error::Error;
+use rayon::prelude::*;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::{Read, Seek};
 
+use crate::error::{QsvError, Result};
+
 #[derive(PartialEq, Debug)]
 pub enum CsvWrapper {
     Integer(i64),
@@ -27,6 +29,19 @@ pub enum CsvType {
     Float,
     String,
 }
+impl CsvType {
+    ///The narrowest type which can hold values of both types, used when the same column has
+    ///been inferred differently in several files
+    pub fn widen(self, other: CsvType) -> CsvType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (CsvType::Integer, CsvType::Float) | (CsvType::Float, CsvType::Integer) => {
+                CsvType::Float
+            }
+            _ => CsvType::String,
+        }
+    }
+}
 impl Display for CsvType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -42,16 +57,82 @@ pub struct CsvData {
     pub headers: StringRecord,
     pub filename: String,
 }
+///Files are split into chunks of about this many bytes, which are parsed on separate threads
+const CHUNK_SIZE: usize = 4 << 20;
+
 impl CsvData {
-    ///Load CSVData from a filename
-    pub fn from_filename(
+    ///Load CSVData from a filename. Files of more than one chunk are read into memory and parsed
+    ///a chunk at a time in parallel
+    pub fn from_filename(filename: &str, delimiter: char, trim: bool) -> Result<CsvData> {
+        debug!("Trying to load CSV from filename {}", filename);
+        let mut file = File::open(filename).map_err(|e| QsvError::file_load(filename, e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| QsvError::file_load(filename, e))?
+            .len() as usize;
+        if len <= CHUNK_SIZE || rayon::current_num_threads() == 1 {
+            return CsvData::from_reader(file, filename, delimiter, trim);
+        }
+        let mut bytes = Vec::with_capacity(len);
+        file.read_to_end(&mut bytes)
+            .map_err(|e| QsvError::file_load(filename, e))?;
+        CsvData::from_bytes(&bytes, filename, delimiter, trim, CHUNK_SIZE)
+    }
+
+    ///Parses CSV held in memory by splitting it into chunks at record boundaries and parsing
+    ///the chunks in parallel. Chunks are found by assuming quotes only surround fields, so if any
+    ///chunk fails to parse, the whole file is parsed again in one go to be sure of the result
+    fn from_bytes(
+        bytes: &[u8],
         filename: &str,
         delimiter: char,
         trim: bool,
-    ) -> Result<CsvData, Box<dyn Error>> {
-        debug!("Trying to load CSV from filename {}", filename);
-        let file = File::open(filename)?;
-        CsvData::from_reader(file, filename, delimiter, trim)
+        chunk_size: usize,
+    ) -> Result<CsvData> {
+        let boundaries = record_boundaries(bytes, chunk_size);
+        debug!(
+            "Parsing {} in {} chunks",
+            filename,
+            boundaries.len().saturating_sub(1)
+        );
+        let chunks: Vec<csv::Result<Vec<StringRecord>>> = boundaries
+            .par_windows(2)
+            .map(|window| {
+                let mut reader = csv::ReaderBuilder::new()
+                    .buffer_capacity(16 * (1 << 10))
+                    .delimiter(delimiter as u8)
+                    .trim(if trim { Trim::All } else { Trim::None })
+                    .has_headers(false)
+                    .from_reader(&bytes[window[0]..window[1]]);
+                reader.records().collect()
+            })
+            .collect();
+        let mut chunks = chunks.into_iter();
+        let mut records = match chunks.next() {
+            Some(Ok(records)) if !records.is_empty() => records,
+            _ => return CsvData::from_reader(bytes, filename, delimiter, trim),
+        };
+        let headers = records.remove(0);
+        for chunk in chunks {
+            match chunk {
+                Ok(chunk) if chunk.first().is_none_or(|r| r.len() == headers.len()) => {
+                    records.extend(chunk)
+                }
+                _ => {
+                    debug!(
+                        "Unable to parse {} in chunks, parsing it in one go instead",
+                        filename
+                    );
+                    return CsvData::from_reader(bytes, filename, delimiter, trim);
+                }
+            }
+        }
+        debug!("Filename has headers: {:?}", headers);
+        Ok(CsvData {
+            records,
+            headers,
+            filename: String::from(filename),
+        })
     }
 
     pub fn from_reader<R: std::io::Read>(
@@ -59,7 +140,7 @@ impl CsvData {
         filename: &str,
         delimiter: char,
         trim: bool,
-    ) -> Result<CsvData, Box<dyn Error>> {
+    ) -> Result<CsvData> {
         let mut records = Vec::with_capacity(10000);
         let trim = if trim { Trim::All } else { Trim::None };
         let mut rdr = csv::ReaderBuilder::new()
@@ -69,10 +150,12 @@ impl CsvData {
             .from_reader(reader);
 
         for result in rdr.records() {
-            let record = result?;
+            let record = result.map_err(|e| QsvError::file_load(filename, e))?;
             records.push(record);
         }
-        let headers = rdr.headers()?;
+        let headers = rdr
+            .headers()
+            .map_err(|e| QsvError::file_load(filename, e))?;
         debug!("Filename has headers: {:?}", headers);
         Ok(CsvData {
             records,
@@ -81,6 +164,48 @@ impl CsvData {
         })
     }
 }
+///Splits CSV into chunks of about `chunk_size` bytes which start and end on record boundaries,
+///returning the offset each chunk starts at followed by the length of the CSV. A newline only ends
+///a record outside quotes, which depends on how many quotes come before it, so the quotes in each
+///chunk are counted in parallel before the boundaries are found
+fn record_boundaries(bytes: &[u8], chunk_size: usize) -> Vec<usize> {
+    let starts: Vec<usize> = (0..bytes.len()).step_by(chunk_size.max(1)).collect();
+    let quotes: Vec<usize> = starts
+        .par_iter()
+        .map(|&start| {
+            let end = (start + chunk_size).min(bytes.len());
+            bytes[start..end].iter().filter(|&&b| b == b'"').count()
+        })
+        .collect();
+    let mut quoted = Vec::with_capacity(starts.len());
+    let mut in_quotes = false;
+    for count in quotes.iter() {
+        quoted.push(in_quotes);
+        in_quotes ^= count % 2 == 1;
+    }
+    let mut boundaries: Vec<usize> = starts
+        .par_iter()
+        .zip(quoted.par_iter())
+        .skip(1)
+        .filter_map(|(&start, &quoted)| {
+            let mut in_quotes = quoted;
+            for (i, b) in bytes[start..].iter().enumerate() {
+                match b {
+                    b'"' => in_quotes = !in_quotes,
+                    b'\n' if !in_quotes => return Some(start + i + 1),
+                    _ => {}
+                }
+            }
+            None
+        })
+        .collect();
+    boundaries.insert(0, 0);
+    boundaries.dedup();
+    boundaries.retain(|&boundary| boundary < bytes.len() || boundary == 0);
+    boundaries.push(bytes.len());
+    boundaries
+}
+
 pub struct CsvStream<R: std::io::Read + std::io::Seek> {
     pub headers: StringRecord,
     pub filename: String,
@@ -92,7 +217,7 @@ impl<R: std::io::Read + std::io::Seek> CsvStream<R> {
         filename: &str,
         delimiter: char,
         trim: bool,
-    ) -> Result<CsvStream<R>, Box<dyn Error>> {
+    ) -> Result<CsvStream<R>> {
         let trim = if trim { Trim::All } else { Trim::None };
         let mut stream: Reader<R> = csv::ReaderBuilder::new()
             .buffer_capacity(16 * (1 << 10))
@@ -100,7 +225,9 @@ impl<R: std::io::Read + std::io::Seek> CsvStream<R> {
             .trim(trim)
             .from_reader(reader);
 
-        let headers = stream.headers()?;
+        let headers = stream
+            .headers()
+            .map_err(|e| QsvError::file_load(filename, e))?;
         let csv_stream: CsvStream<R> = CsvStream {
             headers: headers.clone(),
             filename: String::from(filename),
@@ -111,7 +238,7 @@ impl<R: std::io::Read + std::io::Seek> CsvStream<R> {
 }
 
 ///Resets a CSV stream by seeking to the first row after the headers
-pub fn reset_stream<A: Read + Seek>(csv_stream: &mut CsvStream<A>) -> Result<(), Box<dyn Error>> {
+pub fn reset_stream<A: Read + Seek>(csv_stream: &mut CsvStream<A>) -> csv::Result<()> {
     let mut beginning = Position::new();
     beginning.set_line(1);
     csv_stream.stream.seek(beginning)?;
@@ -129,6 +256,50 @@ mod tests {
         assert_eq!(csv.records, vec!(StringRecord::from(vec!("bar", "13"))))
     }
 
+    #[test]
+    fn it_finds_record_boundaries_outside_quotes() {
+        let bytes = b"a,b\n1,\"x\ny\"\n2,z\n3,\"\"\"\"\n";
+        assert_eq!(record_boundaries(bytes, 5), vec![0, 12, 16, 23]);
+        assert_eq!(record_boundaries(bytes, 100), vec![0, 23]);
+        assert_eq!(record_boundaries(b"", 5), vec![0, 0]);
+    }
+
+    #[test]
+    fn it_parses_chunks_in_parallel() {
+        let bytes = std::fs::read("testdata/people.csv").unwrap();
+        let whole = CsvData::from_reader(&bytes[..], "people.csv", DELIMITER, false).unwrap();
+        for chunk_size in 1..bytes.len() {
+            let chunked =
+                CsvData::from_bytes(&bytes, "people.csv", DELIMITER, false, chunk_size).unwrap();
+            assert_eq!(chunked.headers, whole.headers);
+            assert_eq!(chunked.records, whole.records);
+        }
+    }
+
+    #[test]
+    fn it_parses_in_one_go_when_chunks_dont_line_up() {
+        // the stray quote in an unquoted field makes the second chunk start mid-record
+        let bytes = b"a,b\n1,x\"y\n2,z\n3,\"w\"\n";
+        let csv = CsvData::from_bytes(bytes, "stray.csv", DELIMITER, false, 8).unwrap();
+        assert_eq!(
+            csv.records,
+            vec![
+                StringRecord::from(vec!["1", "x\"y"]),
+                StringRecord::from(vec!["2", "z"]),
+                StringRecord::from(vec!["3", "w"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_widens_types() {
+        assert_eq!(CsvType::Integer.widen(CsvType::Integer), CsvType::Integer);
+        assert_eq!(CsvType::Integer.widen(CsvType::Float), CsvType::Float);
+        assert_eq!(CsvType::Float.widen(CsvType::Integer), CsvType::Float);
+        assert_eq!(CsvType::Float.widen(CsvType::String), CsvType::String);
+        assert_eq!(CsvType::String.widen(CsvType::Integer), CsvType::String);
+    }
+
     #[test]
     fn it_can_load_file_with_alternate_delimiter() {
         let csv = CsvData::from_filename("testdata/slash_as_separator.csv", '/', true).unwrap();
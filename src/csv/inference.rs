@@ -2,6 +2,7 @@ use crate::csv::csv_data::{reset_stream, CsvData, CsvStream, CsvType, CsvWrapper
 use csv::StringRecord;
 use indexmap::map::IndexMap;
 use log::debug;
+use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::io::{Read, Seek};
@@ -45,12 +46,15 @@ impl Display for ColumnInferences {
 }
 
 impl ColumnInference {
-    /// build inference from a CSV
+    /// build inference from a CSV, inferring each column on its own thread
     pub fn from_csv(csv: &CsvData) -> ColumnInference {
         let mut columns_to_types = IndexMap::with_capacity(8);
         let mut columns_to_indexes = IndexMap::with_capacity(8);
-        for (i, header) in csv.headers.iter().enumerate() {
-            let t = get_type_of_column(&mut csv.records.iter(), i);
+        let types: Vec<CsvType> = (0..csv.headers.len())
+            .into_par_iter()
+            .map(|i| get_type_of_column(&mut csv.records.iter(), i))
+            .collect();
+        for (i, (header, t)) in csv.headers.iter().zip(types).enumerate() {
             columns_to_types.insert(String::from(header), t);
             columns_to_indexes.insert(String::from(header), i);
         }
@@ -71,7 +75,7 @@ impl ColumnInference {
         let mut columns_to_indexes = IndexMap::with_capacity(8);
         let headers: Vec<String> = csv.headers.iter().map(String::from).collect();
         for (i, header) in headers.iter().enumerate() {
-            reset_stream(csv).unwrap();
+            reset_stream(csv)?;
             let mut records = csv.stream.records();
             let t = get_type_of_column_stream(&mut records, i)?;
             columns_to_types.insert(String::from(header), t);
@@ -87,6 +91,30 @@ impl ColumnInference {
         })
     }
 
+    /// build inference from records read one at a time, in a single pass over them
+    pub fn from_records<I: Iterator<Item = csv::Result<StringRecord>>>(
+        headers: &StringRecord,
+        records: I,
+    ) -> csv::Result<ColumnInference> {
+        let mut distinct_types = vec![HashSet::with_capacity(3); headers.len()];
+        for record in records {
+            let record = record?;
+            for (types, value) in distinct_types.iter_mut().zip(record.iter()) {
+                types.insert(parse(value).get_type());
+            }
+        }
+        let mut columns_to_types = IndexMap::with_capacity(headers.len());
+        let mut columns_to_indexes = IndexMap::with_capacity(headers.len());
+        for (i, (header, types)) in headers.iter().zip(distinct_types.iter()).enumerate() {
+            columns_to_types.insert(String::from(header), type_of_distinct_types(types));
+            columns_to_indexes.insert(String::from(header), i);
+        }
+        Ok(ColumnInference {
+            columns_to_types,
+            columns_to_indexes,
+        })
+    }
+
     /// build column 'inference' with every column artificially inferred as a String
     pub fn default_inference(headers: &StringRecord) -> ColumnInference {
         let mut columns_to_types = IndexMap::with_capacity(8);
@@ -101,12 +129,29 @@ impl ColumnInference {
         }
     }
 
+    /// merge the inference of another file into this one, widening the types of shared columns
+    /// and adding any columns this one doesn't have yet
+    pub fn merge(&mut self, other: &ColumnInference) {
+        for (column, other_type) in other.columns_to_types.iter() {
+            match self.columns_to_types.get_mut(column) {
+                Some(existing) => *existing = existing.widen(*other_type),
+                None => {
+                    let index = self.columns_to_types.len();
+                    self.columns_to_types.insert(column.clone(), *other_type);
+                    self.columns_to_indexes.insert(column.clone(), index);
+                }
+            }
+        }
+    }
+
     /// get the type of a column, referenced by its string name
+    #[cfg(test)]
     pub fn get_type(&self, s: String) -> Option<&CsvType> {
         self.columns_to_types.get(s.as_str())
     }
 }
-fn parse(s: &str) -> CsvWrapper {
+///Parses a CSV cell as the narrowest type which can represent it
+pub fn parse(s: &str) -> CsvWrapper {
     let is_integer: Result<i64, ParseIntError> = s.parse();
     let is_float: Result<f64, ParseFloatError> = s.parse();
     let is_integer = is_integer.map(CsvWrapper::Integer);
@@ -125,6 +170,11 @@ fn get_type_of_column<'a, I: Iterator<Item = &'a StringRecord>>(
         let parsed_type = parse(record.get(index).unwrap()).get_type();
         distinct_types.insert(parsed_type);
     }
+    type_of_distinct_types(&distinct_types)
+}
+
+/// the narrowest type which can hold every type of value found in a column
+fn type_of_distinct_types(distinct_types: &HashSet<CsvType>) -> CsvType {
     if distinct_types.contains(&CsvType::String) {
         CsvType::String
     } else if distinct_types.contains(&CsvType::Integer) && distinct_types.contains(&CsvType::Float)
@@ -283,4 +333,54 @@ mod test {
             Some(&CsvType::String)
         );
     }
+
+    #[test]
+    fn it_should_infer_from_records_in_a_single_pass() {
+        let headers = StringRecord::from(vec!["foo", "bar", "baz"]);
+        let records = vec![
+            Ok(StringRecord::from(vec!["1", "1", "a"])),
+            Ok(StringRecord::from(vec!["2", "2.5", "3"])),
+        ];
+        let inference = ColumnInference::from_records(&headers, records.into_iter()).unwrap();
+        assert_eq!(
+            inference.get_type(String::from("foo")),
+            Some(&CsvType::Integer)
+        );
+        assert_eq!(
+            inference.get_type(String::from("bar")),
+            Some(&CsvType::Float)
+        );
+        assert_eq!(
+            inference.get_type(String::from("baz")),
+            Some(&CsvType::String)
+        );
+    }
+
+    #[test]
+    fn it_should_merge_inferences_from_several_files() {
+        let mut inference = ColumnInference::from_csv(&CsvData {
+            records: vec![StringRecord::from(vec!["1", "2"])],
+            headers: StringRecord::from(vec!["foo", "bar"]),
+            filename: String::from("a.csv"),
+        });
+        inference.merge(&ColumnInference::from_csv(&CsvData {
+            records: vec![StringRecord::from(vec!["1.5", "baz"])],
+            headers: StringRecord::from(vec!["foo", "qux"]),
+            filename: String::from("b.csv"),
+        }));
+        let columns: Vec<&String> = inference.columns_to_types.keys().collect();
+        assert_eq!(columns, vec!["foo", "bar", "qux"]);
+        assert_eq!(
+            inference.get_type(String::from("foo")),
+            Some(&CsvType::Float)
+        );
+        assert_eq!(
+            inference.get_type(String::from("bar")),
+            Some(&CsvType::Integer)
+        );
+        assert_eq!(
+            inference.get_type(String::from("qux")),
+            Some(&CsvType::String)
+        );
+    }
 }
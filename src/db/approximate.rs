@@ -0,0 +1,509 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{Connection, Result};
+
+use crate::db::functions::{finite_number, user_error};
+
+///HyperLogLog registers are indexed by this many bits of a hash, giving 2^14 registers and a
+///standard error of 0.8%
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+///Distinct hashes are kept until there are this many, so small counts are exact, and use no more
+///memory than the registers would
+const EXACT_LIMIT: usize = REGISTERS / 8;
+///How finely a t-digest divides values. Larger digests are more accurate and use more memory
+const COMPRESSION: f64 = 100.0;
+///Values added to a t-digest are buffered and merged into it this many at a time
+const DIGEST_BUFFER: usize = 500;
+///Each of the k values approx_top_k is asked for has this many counters, to count the values
+///competing for a place
+const COUNTERS_PER_VALUE: usize = 10;
+///The name of approx_top_k, which returns JSON
+pub(crate) const APPROX_TOP_K: &str = "approx_top_k";
+///The largest k approx_top_k accepts
+const MAX_TOP_K: i64 = 10_000;
+
+///Registers aggregates which summarize many rows in a bounded amount of memory, at the cost of
+///being approximate
+pub(crate) fn add_approximate_functions(connection: &Connection) -> Result<()> {
+    connection.create_aggregate_function(
+        "approx_count_distinct",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        ApproxCountDistinct,
+    )?;
+    connection.create_aggregate_function(
+        "approx_median",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        ApproxPercentile {
+            fraction: Some(0.5),
+        },
+    )?;
+    connection.create_aggregate_function(
+        "approx_percentile",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        ApproxPercentile { fraction: None },
+    )?;
+    connection.create_aggregate_function(
+        APPROX_TOP_K,
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        ApproxTopK,
+    )?;
+    Ok(())
+}
+
+///A hash of a value which is the same for integers and reals which are equal, as they're the
+///same value to `COUNT(DISTINCT ...)`
+fn hash_value(value: ValueRef) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    match value {
+        ValueRef::Null => return None,
+        ValueRef::Integer(i) => (0u8, i).hash(&mut hasher),
+        ValueRef::Real(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+            (0u8, f as i64).hash(&mut hasher)
+        }
+        ValueRef::Real(f) => (1u8, f.to_bits()).hash(&mut hasher),
+        ValueRef::Text(t) => (2u8, t).hash(&mut hasher),
+        ValueRef::Blob(b) => (3u8, b).hash(&mut hasher),
+    }
+    Some(hasher.finish())
+}
+
+///Counts distinct hashes exactly while there are few of them, then with a HyperLogLog
+pub enum DistinctCounter {
+    Exact(HashSet<u64>),
+    Registers(Vec<u8>),
+}
+
+impl DistinctCounter {
+    pub fn new() -> DistinctCounter {
+        DistinctCounter::Exact(HashSet::new())
+    }
+
+    pub fn add(&mut self, hash: u64) {
+        match self {
+            DistinctCounter::Exact(hashes) => {
+                hashes.insert(hash);
+                if hashes.len() > EXACT_LIMIT {
+                    let mut registers = vec![0; REGISTERS];
+                    for hash in hashes.iter() {
+                        add_to_registers(&mut registers, *hash);
+                    }
+                    *self = DistinctCounter::Registers(registers);
+                }
+            }
+            DistinctCounter::Registers(registers) => add_to_registers(registers, hash),
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        match self {
+            DistinctCounter::Exact(hashes) => hashes.len() as u64,
+            DistinctCounter::Registers(registers) => {
+                let m = REGISTERS as f64;
+                let alpha = 0.7213 / (1.0 + 1.079 / m);
+                let sum: f64 = registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+                let estimate = alpha * m * m / sum;
+                let zeros = registers.iter().filter(|r| **r == 0).count();
+                // small counts are estimated better from how many registers are still empty
+                if estimate <= 2.5 * m && zeros > 0 {
+                    (m * (m / zeros as f64).ln()).round() as u64
+                } else {
+                    estimate.round() as u64
+                }
+            }
+        }
+    }
+}
+
+///The first bits of a hash choose a register, which keeps the longest run of leading zeros seen
+///in the rest of the hash
+fn add_to_registers(registers: &mut [u8], hash: u64) {
+    let index = (hash >> (64 - PRECISION)) as usize;
+    let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+    let rank = rest.leading_zeros() as u8 + 1;
+    registers[index] = registers[index].max(rank);
+}
+
+pub struct ApproxCountDistinct;
+
+impl Aggregate<DistinctCounter, i64> for ApproxCountDistinct {
+    fn init(&self, _: &mut Context<'_>) -> Result<DistinctCounter> {
+        Ok(DistinctCounter::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, counter: &mut DistinctCounter) -> Result<()> {
+        if let Some(hash) = hash_value(ctx.get_raw(0)) {
+            counter.add(hash);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, counter: Option<DistinctCounter>) -> Result<i64> {
+        Ok(counter.map_or(0, |counter| counter.count() as i64))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+///A merging t-digest, which summarizes values as centroids which are smaller towards either end,
+///so extreme percentiles are as accurate as the median
+pub struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new() -> TDigest {
+        TDigest {
+            centroids: vec![],
+            buffer: Vec::with_capacity(DIGEST_BUFFER),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push(x);
+        if self.buffer.len() >= DIGEST_BUFFER {
+            self.compress();
+        }
+    }
+
+    ///Merges the buffered values into the centroids, combining neighbouring centroids for as
+    ///long as the scale function allows
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all: Vec<Centroid> = self.centroids.drain(..).collect();
+        all.extend(
+            self.buffer
+                .drain(..)
+                .map(|mean| Centroid { mean, weight: 1.0 }),
+        );
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let total: f64 = all.iter().map(|c| c.weight).sum();
+        let scale = |q: f64| COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin();
+        let inverse = |k: f64| ((k * 2.0 * std::f64::consts::PI / COMPRESSION).sin() + 1.0) / 2.0;
+        let mut merged = Vec::new();
+        let mut before = 0.0;
+        let mut current = all[0];
+        let mut limit = inverse(scale(0.0) + 1.0);
+        for next in all.into_iter().skip(1) {
+            if (before + current.weight + next.weight) / total <= limit {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                before += current.weight;
+                merged.push(current);
+                limit = inverse(scale(before / total) + 1.0);
+                current = next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    ///The value a fraction of the way through the values added, interpolating between centroids
+    ///as `percentile` interpolates between values, so digests of few values are exact
+    pub fn quantile(&mut self, fraction: f64) -> Option<f64> {
+        self.compress();
+        let total: f64 = self.centroids.iter().map(|c| c.weight).sum();
+        if total == 0.0 {
+            return None;
+        }
+        // each centroid is centred on the middle of the ranks it covers
+        let mut centres = Vec::with_capacity(self.centroids.len() + 2);
+        centres.push((0.5, self.min));
+        let mut before = 0.0;
+        for centroid in self.centroids.iter() {
+            centres.push((before + centroid.weight / 2.0, centroid.mean));
+            before += centroid.weight;
+        }
+        centres.push((total - 0.5, self.max));
+        let rank = fraction * (total - 1.0) + 0.5;
+        let above = centres
+            .iter()
+            .position(|(centre, _)| *centre >= rank)
+            .unwrap_or(centres.len() - 1);
+        if above == 0 {
+            return Some(self.min);
+        }
+        let (low_rank, low) = centres[above - 1];
+        let (high_rank, high) = centres[above];
+        if high_rank <= low_rank {
+            return Some(high);
+        }
+        Some(low + (high - low) * (rank - low_rank) / (high_rank - low_rank))
+    }
+}
+
+///Percentiles estimated from a t-digest. `approx_median` is the value half way through
+pub struct ApproxPercentile {
+    ///The fraction for `approx_median`, or None to take it from the second argument
+    fraction: Option<f64>,
+}
+
+impl Aggregate<(TDigest, f64), Option<f64>> for ApproxPercentile {
+    fn init(&self, _: &mut Context<'_>) -> Result<(TDigest, f64)> {
+        Ok((TDigest::new(), self.fraction.unwrap_or_default()))
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, state: &mut (TDigest, f64)) -> Result<()> {
+        if self.fraction.is_none() {
+            let fraction: f64 = ctx.get(1)?;
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(user_error("percentile must be between 0 and 1"));
+            }
+            state.1 = fraction;
+        }
+        let function = match self.fraction {
+            Some(_) => "approx_median",
+            None => "approx_percentile",
+        };
+        // infinite values would make the centroids' means NaN, so they're rejected as `percentile`
+        // rejects them
+        if let Some(x) = finite_number(ctx, 0, function)? {
+            state.0.add(x);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, state: Option<(TDigest, f64)>) -> Result<Option<f64>> {
+        Ok(state.and_then(|(mut digest, fraction)| digest.quantile(fraction)))
+    }
+}
+
+struct Counter {
+    value: Value,
+    count: u64,
+    ///When the value was first counted, to order values which are as common as each other
+    seen: u64,
+}
+
+///The Space-Saving algorithm: a fixed number of counters, where a value without a counter takes
+///over the smallest, along with its count. Any value more common than one in every `capacity`
+///values keeps its counter, and counts are never lower than the true count
+pub struct TopK {
+    k: usize,
+    capacity: usize,
+    counters: HashMap<String, Counter>,
+    ///The counters ordered from smallest to largest count
+    by_count: BTreeSet<(u64, u64, String)>,
+    seen: u64,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            capacity: k * COUNTERS_PER_VALUE,
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+            seen: 0,
+        }
+    }
+
+    pub fn add(&mut self, value: Value) {
+        // values of different types are different, even if they look the same
+        let key = format!("{:?}", value);
+        let (count, seen) = match self.counters.get_mut(&key) {
+            Some(counter) => {
+                self.by_count
+                    .remove(&(counter.count, counter.seen, key.clone()));
+                counter.count += 1;
+                (counter.count, counter.seen)
+            }
+            None => {
+                let mut count = 1;
+                if self.counters.len() >= self.capacity {
+                    let smallest = self
+                        .by_count
+                        .iter()
+                        .next()
+                        .cloned()
+                        .expect("there are as many counts as counters");
+                    self.by_count.remove(&smallest);
+                    self.counters.remove(&smallest.2);
+                    count += smallest.0;
+                }
+                self.seen += 1;
+                let counter = Counter {
+                    value,
+                    count,
+                    seen: self.seen,
+                };
+                self.counters.insert(key.clone(), counter);
+                (count, self.seen)
+            }
+        };
+        self.by_count.insert((count, seen, key));
+    }
+
+    ///The k most common values, most common first, as a JSON array of objects with the value and
+    ///its count
+    pub fn json(&self) -> String {
+        let mut counters: Vec<&Counter> = self.counters.values().collect();
+        counters.sort_by(|a, b| b.count.cmp(&a.count).then(a.seen.cmp(&b.seen)));
+        let top: Vec<serde_json::Value> = counters
+            .into_iter()
+            .take(self.k)
+            .map(|counter| {
+                let value = match &counter.value {
+                    Value::Integer(i) => serde_json::Value::from(*i),
+                    Value::Real(f) => serde_json::Value::from(*f),
+                    Value::Text(s) => serde_json::Value::from(s.as_str()),
+                    Value::Blob(b) => serde_json::Value::from(String::from_utf8_lossy(b)),
+                    Value::Null => serde_json::Value::Null,
+                };
+                serde_json::json!({"value": value, "count": counter.count})
+            })
+            .collect();
+        serde_json::Value::Array(top).to_string()
+    }
+}
+
+///`approx_top_k(value, k)`: the k most common values with their approximate counts
+pub struct ApproxTopK;
+
+impl Aggregate<Option<TopK>, Option<String>> for ApproxTopK {
+    fn init(&self, _: &mut Context<'_>) -> Result<Option<TopK>> {
+        Ok(None)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, top: &mut Option<TopK>) -> Result<()> {
+        if top.is_none() {
+            let k: i64 = ctx.get(1)?;
+            if !(1..=MAX_TOP_K).contains(&k) {
+                return Err(user_error(format!(
+                    "approx_top_k must be asked for between 1 and {} values",
+                    MAX_TOP_K
+                )));
+            }
+            *top = Some(TopK::new(k as usize));
+        }
+        let value = Value::from(ctx.get_raw(0));
+        if let (Some(top), false) = (top, value == Value::Null) {
+            top.add(value);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, top: Option<Option<TopK>>) -> Result<Option<String>> {
+        Ok(top.flatten().map(|top| top.json()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::FromSql;
+
+    use super::*;
+
+    fn query<T: FromSql>(sql: &str) -> T {
+        let connection = Connection::open_in_memory().unwrap();
+        add_approximate_functions(&connection).unwrap();
+        connection.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    ///A table of the numbers from 1 to n
+    fn numbers(n: usize) -> String {
+        format!(
+            "with recursive n(x) as (select 1 union all select x + 1 from n where x < {}) ",
+            n
+        )
+    }
+
+    #[test]
+    fn it_counts_few_distinct_values_exactly() {
+        let count: i64 = query(
+            "select approx_count_distinct(column1) from (values (1), (1.0), ('1'), (2), (null), ('a'))",
+        );
+        assert_eq!(count, 4);
+        let none: i64 = query("select approx_count_distinct(x) from (select 1 as x) where x > 1");
+        assert_eq!(none, 0);
+    }
+
+    #[test]
+    fn it_estimates_many_distinct_values() {
+        let count: i64 = query(&format!(
+            "{}select approx_count_distinct(x % 50000) from n",
+            numbers(200_000)
+        ));
+        assert!((count - 50_000).abs() < 50_000 / 50, "estimated {}", count);
+    }
+
+    #[test]
+    fn it_estimates_percentiles() {
+        let exact: f64 = query("select approx_median(column1) from (values (1), (2), (3), (10))");
+        assert_eq!(exact, 2.5);
+        let (median, p99): (f64, f64) = {
+            let connection = Connection::open_in_memory().unwrap();
+            add_approximate_functions(&connection).unwrap();
+            connection
+                .query_row(
+                    &format!(
+                        "{}select approx_median(x), approx_percentile(x, 0.99) from n",
+                        numbers(100_000)
+                    ),
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap()
+        };
+        assert!((median - 50_000.5).abs() < 500.0, "median {}", median);
+        assert!((p99 - 99_000.0).abs() < 100.0, "99th percentile {}", p99);
+        let extremes: f64 = query(&format!(
+            "{}select approx_percentile(x, 1) - approx_percentile(x, 0) from n",
+            numbers(10_000)
+        ));
+        assert_eq!(extremes, 9_999.0);
+    }
+
+    #[test]
+    fn it_rejects_infinite_values() {
+        let connection = Connection::open_in_memory().unwrap();
+        add_approximate_functions(&connection).unwrap();
+        // enough values for the digest to merge them into centroids
+        for function in ["approx_median(x)", "approx_percentile(x, 0.9)"].iter() {
+            let sql = format!(
+                "{}select {} from (select case when x = 1500 then 9e999 else x end as x from n)",
+                numbers(3_000),
+                function
+            );
+            let error = connection
+                .query_row(&sql, [], |row| row.get::<_, Option<f64>>(0))
+                .unwrap_err();
+            assert!(error.to_string().contains("infinite value"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn it_finds_the_most_common_values() {
+        let top: String = query(&format!(
+            "{}select approx_top_k(case when x % 10 = 0 then 'ten' when x % 7 = 0 then 'seven' else x end, 2) from n",
+            numbers(1_000)
+        ));
+        assert_eq!(
+            top,
+            r#"[{"value":"seven","count":128},{"value":"ten","count":100}]"#
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+};
+use chrono_tz::Tz;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, Result};
+
+use crate::db::functions::{text, user_error};
+
+///Timestamps are written the way SQLite's own date functions write them, so the two can be mixed
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+///Formats timestamps without an explicit format are read in, after RFC 3339
+const TIMESTAMP_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+const OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+///Registers functions for parsing, converting, truncating and comparing timestamps. Timestamps
+///are text such as `2026-10-18 09:30:00`, in UTC unless a function is told otherwise, and text
+///which isn't a timestamp gives NULL, as it does with SQLite's date functions
+pub(crate) fn add_date_functions(connection: &Connection) -> Result<()> {
+    let deterministic = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    connection.create_scalar_function("parse_date", 1, deterministic, |ctx| {
+        Ok(timestamp(ctx, 0)?.map(format_timestamp))
+    })?;
+    connection.create_scalar_function("parse_date", 2, deterministic, |ctx| {
+        match (text(ctx, 0)?, text(ctx, 1)?) {
+            (Some(value), Some(format)) => {
+                Ok(parse_with_format(&value, &format).map(format_timestamp))
+            }
+            _ => Ok(None),
+        }
+    })?;
+    connection.create_scalar_function("convert_tz", 3, deterministic, |ctx| {
+        let from = time_zone(ctx, 1)?;
+        let to = time_zone(ctx, 2)?;
+        Ok(timestamp(ctx, 0)?
+            .zip(from.zip(to))
+            .and_then(|(timestamp, (from, to))| convert_tz(timestamp, from, to))
+            .map(format_timestamp))
+    })?;
+    connection.create_scalar_function("date_trunc", 2, deterministic, |ctx| {
+        let unit = unit(ctx, 0)?;
+        Ok(timestamp(ctx, 1)?
+            .zip(unit)
+            .and_then(|(timestamp, unit)| truncate(timestamp, unit))
+            .map(format_timestamp))
+    })?;
+    connection.create_scalar_function("date_diff", 3, deterministic, |ctx| {
+        let unit = unit(ctx, 0)?;
+        let (start, end) = (timestamp(ctx, 1)?, timestamp(ctx, 2)?);
+        Ok(match (unit, start, end) {
+            (Some(unit), Some(start), Some(end)) => Some(difference(unit, start, end)),
+            _ => None,
+        })
+    })?;
+    connection.create_scalar_function("to_epoch", 1, deterministic, |ctx| {
+        Ok(timestamp(ctx, 0)?.map(|timestamp| timestamp.timestamp()))
+    })?;
+    connection.create_scalar_function("from_epoch", 1, deterministic, |ctx| {
+        let seconds = match ctx.get_raw(0) {
+            ValueRef::Integer(i) => i as f64,
+            ValueRef::Real(f) => f,
+            _ => return Ok(None),
+        };
+        let mut whole = seconds.floor();
+        let mut nanos = ((seconds - whole) * 1e9).round();
+        // a fraction just below a whole second rounds up to it, rather than to a leap second
+        if nanos >= 1e9 {
+            whole += 1.0;
+            nanos -= 1e9;
+        }
+        Ok(NaiveDateTime::from_timestamp_opt(whole as i64, nanos as u32).map(format_timestamp))
+    })?;
+    connection.create_scalar_function("iso_week", 1, deterministic, |ctx| {
+        Ok(timestamp(ctx, 0)?.map(|timestamp| timestamp.iso_week().week() as i64))
+    })?;
+    connection.create_scalar_function("iso_year", 1, deterministic, |ctx| {
+        Ok(timestamp(ctx, 0)?.map(|timestamp| timestamp.iso_week().year() as i64))
+    })?;
+    Ok(())
+}
+
+fn timestamp(ctx: &Context, i: usize) -> Result<Option<NaiveDateTime>> {
+    Ok(text(ctx, i)?.and_then(|value| parse_timestamp(&value)))
+}
+
+///Writes a timestamp with milliseconds, but only when it has any
+fn format_timestamp(timestamp: NaiveDateTime) -> String {
+    if timestamp.nanosecond() >= 1_000_000 {
+        timestamp.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+    } else {
+        timestamp.format(TIMESTAMP_FORMAT).to_string()
+    }
+}
+
+///Reads an RFC 3339 timestamp, a timestamp in one of the formats SQLite writes, with or without
+///an offset, or a date. Timestamps with an offset are converted to UTC
+fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.naive_utc());
+    }
+    TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            OFFSET_FORMATS
+                .iter()
+                .find_map(|format| DateTime::parse_from_str(value, format).ok())
+                .map(|timestamp| timestamp.naive_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::from_hms(0, 0, 0)))
+        })
+}
+
+///Reads a timestamp in a strftime-style format. Formats with an offset are converted to UTC, and
+///formats with only a date are midnight
+fn parse_with_format(value: &str, format: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, format)
+        .ok()
+        .or_else(|| {
+            DateTime::parse_from_str(value, format)
+                .ok()
+                .map(|timestamp| timestamp.naive_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, format)
+                .ok()
+                .map(|date| date.and_time(NaiveTime::from_hms(0, 0, 0)))
+        })
+}
+
+fn time_zone(ctx: &Context, i: usize) -> Result<Option<Tz>> {
+    match text(ctx, i)? {
+        Some(name) => name
+            .parse::<Tz>()
+            .map(Some)
+            .map_err(|_| user_error(format!("Unknown time zone {}", name))),
+        None => Ok(None),
+    }
+}
+
+///The local time in one time zone of a local time in another. Times skipped when clocks go
+///forward don't exist, so they're NULL, and times repeated when they go back are the earlier one
+fn convert_tz(timestamp: NaiveDateTime, from: Tz, to: Tz) -> Option<NaiveDateTime> {
+    from.from_local_datetime(&timestamp)
+        .earliest()
+        .map(|local| local.with_timezone(&to).naive_local())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+fn unit(ctx: &Context, i: usize) -> Result<Option<Unit>> {
+    let name = match text(ctx, i)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let unit = match name.to_lowercase().trim_end_matches('s') {
+        "year" => Unit::Year,
+        "quarter" => Unit::Quarter,
+        "month" => Unit::Month,
+        "week" => Unit::Week,
+        "day" => Unit::Day,
+        "hour" => Unit::Hour,
+        "minute" => Unit::Minute,
+        "second" => Unit::Second,
+        _ => return Err(user_error(format!("Unknown unit of time {}", name))),
+    };
+    Ok(Some(unit))
+}
+
+///The start of the year, quarter, month, ISO week (starting on Monday), day, hour, minute or
+///second a timestamp is in, or None if that's before the earliest date chrono can represent
+fn truncate(timestamp: NaiveDateTime, unit: Unit) -> Option<NaiveDateTime> {
+    let date = timestamp.date();
+    let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0);
+    match unit {
+        Unit::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).and_then(midnight),
+        Unit::Quarter => NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1)
+            .and_then(midnight),
+        Unit::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).and_then(midnight),
+        Unit::Week => date
+            .checked_sub_signed(Duration::days(date.weekday().num_days_from_monday() as i64))
+            .and_then(midnight),
+        Unit::Day => midnight(date),
+        Unit::Hour => date.and_hms_opt(timestamp.hour(), 0, 0),
+        Unit::Minute => date.and_hms_opt(timestamp.hour(), timestamp.minute(), 0),
+        Unit::Second => date.and_hms_opt(timestamp.hour(), timestamp.minute(), timestamp.second()),
+    }
+}
+
+///The number of whole units from `start` to `end`, which is negative when `end` is earlier.
+///Months, quarters and years are calendar months, so January 31st to February 28th is 0 months
+fn difference(unit: Unit, start: NaiveDateTime, end: NaiveDateTime) -> i64 {
+    let duration = end - start;
+    match unit {
+        Unit::Second => duration.num_seconds(),
+        Unit::Minute => duration.num_minutes(),
+        Unit::Hour => duration.num_hours(),
+        Unit::Day => duration.num_days(),
+        Unit::Week => duration.num_weeks(),
+        Unit::Month => months_between(start, end),
+        Unit::Quarter => months_between(start, end) / 3,
+        Unit::Year => months_between(start, end) / 12,
+    }
+}
+
+fn months_between(start: NaiveDateTime, end: NaiveDateTime) -> i64 {
+    let months =
+        (end.year() as i64 - start.year() as i64) * 12 + end.month() as i64 - start.month() as i64;
+    // a month only counts once the day and time of the start have been reached again
+    let position = |t: NaiveDateTime| (t.day(), t.time());
+    if months > 0 && position(end) < position(start) {
+        months - 1
+    } else if months < 0 && position(end) > position(start) {
+        months + 1
+    } else {
+        months
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::FromSql;
+
+    use super::*;
+
+    fn query<T: FromSql>(sql: &str) -> T {
+        let connection = Connection::open_in_memory().unwrap();
+        add_date_functions(&connection).unwrap();
+        connection.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn it_parses_dates_in_any_format() {
+        let parsed: String = query("select parse_date('18/10/2026 09:30', '%d/%m/%Y %H:%M')");
+        assert_eq!(parsed, "2026-10-18 09:30:00");
+        let date_only: String = query("select parse_date('Oct 18, 2026', '%b %d, %Y')");
+        assert_eq!(date_only, "2026-10-18 00:00:00");
+        let offset: String = query("select parse_date('2026-10-18T09:30:00.250+02:00')");
+        assert_eq!(offset, "2026-10-18 07:30:00.250");
+        let invalid: Option<String> = query("select parse_date('yesterday')");
+        assert_eq!(invalid, None);
+    }
+
+    #[test]
+    fn it_converts_between_time_zones() {
+        let converted: String =
+            query("select convert_tz('2026-07-01 12:00:00', 'UTC', 'America/New_York')");
+        assert_eq!(converted, "2026-07-01 08:00:00");
+        let winter: String =
+            query("select convert_tz('2026-12-01 12:00', 'Europe/London', 'Asia/Tokyo')");
+        assert_eq!(winter, "2026-12-01 21:00:00");
+        // clocks in London went forward an hour at 1am on March 29th
+        let skipped: Option<String> =
+            query("select convert_tz('2026-03-29 01:30', 'Europe/London', 'UTC')");
+        assert_eq!(skipped, None);
+    }
+
+    #[test]
+    fn it_truncates_timestamps() {
+        let truncate = |unit: &str| -> String {
+            query(&format!(
+                "select date_trunc('{}', '2026-10-18 09:30:45')",
+                unit
+            ))
+        };
+        assert_eq!(truncate("year"), "2026-01-01 00:00:00");
+        assert_eq!(truncate("quarter"), "2026-10-01 00:00:00");
+        assert_eq!(truncate("month"), "2026-10-01 00:00:00");
+        assert_eq!(truncate("week"), "2026-10-12 00:00:00");
+        assert_eq!(truncate("day"), "2026-10-18 00:00:00");
+        assert_eq!(truncate("hour"), "2026-10-18 09:00:00");
+        assert_eq!(truncate("minutes"), "2026-10-18 09:30:00");
+        // the Monday before the earliest date chrono can represent doesn't exist
+        let earliest: Option<String> = query("select date_trunc('week', '-262144-01-01')");
+        assert_eq!(earliest, None);
+    }
+
+    #[test]
+    fn it_counts_whole_units_between_timestamps() {
+        let diff = |unit: &str, start: &str, end: &str| -> i64 {
+            query(&format!(
+                "select date_diff('{}', '{}', '{}')",
+                unit, start, end
+            ))
+        };
+        assert_eq!(diff("day", "2026-10-01", "2026-10-18 12:00"), 17);
+        assert_eq!(diff("hour", "2026-10-18 12:00", "2026-10-18 09:30"), -2);
+        assert_eq!(diff("week", "2026-10-01", "2026-10-18"), 2);
+        assert_eq!(diff("month", "2026-01-31", "2026-02-28"), 0);
+        assert_eq!(diff("month", "2026-01-31", "2026-03-31"), 2);
+        assert_eq!(diff("month", "2026-03-31", "2026-01-31"), -2);
+        assert_eq!(diff("year", "2024-02-29", "2026-10-18"), 2);
+        assert_eq!(diff("quarter", "2026-01-15", "2026-10-14"), 2);
+    }
+
+    #[test]
+    fn it_converts_to_and_from_epoch_seconds() {
+        let epoch: i64 = query("select to_epoch('2026-10-18 00:00:00')");
+        assert_eq!(epoch, 1_792_281_600);
+        let timestamp: String = query("select from_epoch(1792281600)");
+        assert_eq!(timestamp, "2026-10-18 00:00:00");
+        let fractional: String = query("select from_epoch(1792281600.5)");
+        assert_eq!(fractional, "2026-10-18 00:00:00.500");
+        let almost: String = query("select from_epoch(-0.0000000001)");
+        assert_eq!(almost, "1970-01-01 00:00:00");
+    }
+
+    #[test]
+    fn it_finds_iso_weeks() {
+        let week: i64 = query("select iso_week('2026-10-18')");
+        assert_eq!(week, 42);
+        // January 1st 2027 is a Friday, so it's in the last week of 2026
+        let (week, year): (i64, i64) = {
+            let connection = Connection::open_in_memory().unwrap();
+            add_date_functions(&connection).unwrap();
+            connection
+                .query_row(
+                    "select iso_week('2027-01-01'), iso_year('2027-01-01')",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap()
+        };
+        assert_eq!((week, year), (53, 2026));
+    }
+}
@@ -1,31 +1,356 @@
-use rusqlite::functions::{Aggregate, Context, FunctionFlags};
-use rusqlite::types::ValueRef;
-use rusqlite::{Connection, Result};
-use stats::OnlineStats;
+use indexmap::IndexMap;
+use regex::Regex;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags, WindowAggregate};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{Connection, Error, Result};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
+use crate::db::approximate::add_approximate_functions;
+use crate::db::dates::add_date_functions;
+
+type MathFunction = fn(f64) -> f64;
+
+///The name of json_flatten, which returns JSON
+pub(crate) const JSON_FLATTEN: &str = "json_flatten";
+
+///Math functions of a single number. Results which aren't a number, such as `ln(-1)`, are NULL
+const MATH_FUNCTIONS: &[(&str, MathFunction)] = &[
+    ("ln", f64::ln),
+    ("log10", f64::log10),
+    ("log2", f64::log2),
+    ("exp", f64::exp),
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("asin", f64::asin),
+    ("acos", f64::acos),
+    ("atan", f64::atan),
+    ("degrees", f64::to_degrees),
+    ("radians", f64::to_radians),
+];
+
+///Aggregates of a column's moments. mean, stddev and variance are of the whole population
+const MOMENTS: &[(&str, Statistic)] = &[
+    ("mean", Statistic::Mean),
+    ("stddev", Statistic::Stddev { sample: false }),
+    ("stddev_pop", Statistic::Stddev { sample: false }),
+    ("stddev_samp", Statistic::Stddev { sample: true }),
+    ("variance", Statistic::Variance { sample: false }),
+    ("var_pop", Statistic::Variance { sample: false }),
+    ("var_samp", Statistic::Variance { sample: true }),
+];
+
+///Registers qsv's functions, on top of SQLite's own
 pub(crate) fn add_udfs(connection: &Connection) -> Result<()> {
+    let deterministic = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
     connection.create_scalar_function(
         "md5",
         1,
         FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
         move |ctx| calculate_md5(ctx).map_err(|e| rusqlite::Error::UserFunctionError(e.into())),
     )?;
+    connection.create_scalar_function("sha1", 1, deterministic, hash::<Sha1>)?;
+    connection.create_scalar_function("sha256", 1, deterministic, hash::<Sha256>)?;
     connection.create_scalar_function(
         "sqrt",
         1,
         FunctionFlags::SQLITE_DETERMINISTIC,
         move |ctx| calculate_sqrt(ctx).map_err(|e| rusqlite::Error::UserFunctionError(e.into())),
     )?;
+    for (name, function) in MATH_FUNCTIONS.iter() {
+        let function = *function;
+        connection.create_scalar_function(name, 1, deterministic, move |ctx| {
+            Ok(not_nan(number(ctx, 0, name)?.map(function)))
+        })?;
+    }
+    // log(x) is the base 10 logarithm, and log(b, x) the base b logarithm, as in SQLite
+    connection.create_scalar_function("log", 1, deterministic, |ctx| {
+        Ok(not_nan(number(ctx, 0, "log")?.map(f64::log10)))
+    })?;
+    connection.create_scalar_function("log", 2, deterministic, |ctx| {
+        let base = number(ctx, 0, "log")?;
+        let x = number(ctx, 1, "log")?;
+        Ok(not_nan(base.zip(x).map(|(base, x)| x.ln() / base.ln())))
+    })?;
+    for name in ["pow", "power"].iter() {
+        connection.create_scalar_function(name, 2, deterministic, move |ctx| {
+            let x = number(ctx, 0, name)?;
+            let y = number(ctx, 1, name)?;
+            Ok(not_nan(x.zip(y).map(|(x, y)| x.powf(y))))
+        })?;
+    }
+    connection.create_scalar_function("atan2", 2, deterministic, |ctx| {
+        let y = number(ctx, 0, "atan2")?;
+        let x = number(ctx, 1, "atan2")?;
+        Ok(not_nan(y.zip(x).map(|(y, x)| y.atan2(x))))
+    })?;
+    connection.create_scalar_function("pi", 0, deterministic, |_| Ok(std::f64::consts::PI))?;
+    // SQLite runs `x REGEXP y` as `regexp(y, x)`
+    connection.create_scalar_function("regexp", 2, deterministic, |ctx| {
+        let text = text(ctx, 1)?;
+        match text {
+            Some(text) => Ok(Some(pattern(ctx, 0)?.is_match(&text))),
+            None => Ok(None),
+        }
+    })?;
+    connection.create_scalar_function("regexp_replace", 3, deterministic, |ctx| {
+        let (text, replacement) = (text(ctx, 0)?, text(ctx, 2)?);
+        match (text, replacement) {
+            (Some(text), Some(replacement)) => Ok(Some(
+                pattern(ctx, 1)?
+                    .replace_all(&text, replacement.as_str())
+                    .into_owned(),
+            )),
+            _ => Ok(None),
+        }
+    })?;
+    for arguments in 2..=3 {
+        connection.create_scalar_function("regexp_extract", arguments, deterministic, |ctx| {
+            regexp_extract(ctx)
+        })?;
+    }
+    connection.create_scalar_function("split_part", 3, deterministic, split_part)?;
+    connection.create_scalar_function("levenshtein", 2, deterministic, |ctx| {
+        match (text(ctx, 0)?, text(ctx, 1)?) {
+            (Some(a), Some(b)) => Ok(Some(strsim::levenshtein(&a, &b) as i64)),
+            _ => Ok(None),
+        }
+    })?;
+    connection.create_scalar_function(JSON_FLATTEN, 1, deterministic, json_flatten)?;
+    connection.create_scalar_function(JSON_FLATTEN, 2, deterministic, json_flatten)?;
+    for (name, statistic) in MOMENTS.iter() {
+        connection.create_window_function(
+            name,
+            1,
+            FunctionFlags::SQLITE_DETERMINISTIC,
+            Moment {
+                name,
+                statistic: *statistic,
+            },
+        )?;
+    }
     connection.create_aggregate_function(
-        "stddev",
+        "median",
         1,
         FunctionFlags::SQLITE_DETERMINISTIC,
-        Stddev,
+        Percentile {
+            name: "median",
+            fraction: Some(0.5),
+            scale: 1.0,
+        },
+    )?;
+    connection.create_aggregate_function(
+        "percentile",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        Percentile {
+            name: "percentile",
+            fraction: None,
+            scale: 100.0,
+        },
+    )?;
+    connection.create_aggregate_function(
+        "percentile_cont",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        Percentile {
+            name: "percentile_cont",
+            fraction: None,
+            scale: 1.0,
+        },
+    )?;
+    connection.create_aggregate_function("mode", 1, FunctionFlags::SQLITE_DETERMINISTIC, Mode)?;
+    connection.create_aggregate_function(
+        "covar",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        Covariance,
+    )?;
+    connection.create_aggregate_function(
+        "corr",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        Correlation,
     )?;
-    connection.create_aggregate_function("mean", 1, FunctionFlags::SQLITE_DETERMINISTIC, Mean)?;
+    add_date_functions(connection)?;
+    add_approximate_functions(connection)?;
     Ok(())
 }
 
+pub(crate) fn user_error<S: Into<String>>(message: S) -> Error {
+    Error::UserFunctionError(message.into().into())
+}
+
+///A numeric argument, or None if it's NULL or blank. Text which is a number, as every value is
+///with `--textonly`, is taken as that number
+pub(crate) fn number(ctx: &Context, i: usize, function: &str) -> Result<Option<f64>> {
+    let error = || user_error(format!("can't take {} of this value", function));
+    match ctx.get_raw(i) {
+        ValueRef::Null => Ok(None),
+        ValueRef::Integer(i) => Ok(Some(i as f64)),
+        ValueRef::Real(f) => Ok(Some(f)),
+        ValueRef::Text(text) => {
+            let text = std::str::from_utf8(text).map_err(|_| error())?.trim();
+            if text.is_empty() {
+                return Ok(None);
+            }
+            match text.parse::<f64>() {
+                Ok(f) if f.is_finite() => Ok(Some(f)),
+                _ => Err(error()),
+            }
+        }
+        ValueRef::Blob(_) => Err(error()),
+    }
+}
+
+///A numeric argument to a statistic, or None if it's NULL or blank. Infinite values, which SQLite
+///reals can be, are an error, since they leave means, deviations and percentiles meaningless
+pub(crate) fn finite_number(ctx: &Context, i: usize, function: &str) -> Result<Option<f64>> {
+    match number(ctx, i, function)? {
+        Some(x) if !x.is_finite() => Err(user_error(format!(
+            "can't take {} of an infinite value",
+            function
+        ))),
+        x => Ok(x),
+    }
+}
+
+///SQLite has no NaN, so results which aren't a number are NULL
+fn not_nan(result: Option<f64>) -> Option<f64> {
+    result.filter(|result| !result.is_nan())
+}
+
+///A text argument, or None if it's NULL. Numbers are converted to text, as SQLite does
+pub(crate) fn text(ctx: &Context, i: usize) -> Result<Option<String>> {
+    match ctx.get_raw(i) {
+        ValueRef::Null => Ok(None),
+        ValueRef::Integer(i) => Ok(Some(i.to_string())),
+        ValueRef::Real(f) => Ok(Some(f.to_string())),
+        ValueRef::Text(t) => Ok(Some(String::from_utf8_lossy(t).into_owned())),
+        ValueRef::Blob(_) => Err(user_error("expected text but got a blob")),
+    }
+}
+
+///The regular expression an argument holds, compiled only once for each statement
+fn pattern(ctx: &Context, i: usize) -> Result<std::sync::Arc<Regex>> {
+    ctx.get_or_create_aux(i as i32, |value| -> std::result::Result<Regex, Error> {
+        let pattern = value
+            .as_str()
+            .map_err(|_| user_error("pattern must be text"))?;
+        Regex::new(pattern).map_err(|e| user_error(e.to_string()))
+    })
+}
+
+///The hex digest of a text or blob value
+fn hash<D: Digest>(ctx: &Context) -> Result<Option<String>> {
+    let bytes = match ctx.get_raw(0) {
+        ValueRef::Null => return Ok(None),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes.to_vec(),
+        ValueRef::Integer(i) => i.to_string().into_bytes(),
+        ValueRef::Real(f) => f.to_string().into_bytes(),
+    };
+    let digest = D::digest(&bytes);
+    Ok(Some(digest.iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+///`regexp_extract(text, pattern[, group])`: the first match of a pattern, or one of its capture
+///groups, which is NULL if there's no match
+fn regexp_extract(ctx: &Context) -> Result<Option<String>> {
+    let text = match text(ctx, 0)? {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    let group = if ctx.len() > 2 { ctx.get::<i64>(2)? } else { 0 };
+    if group < 0 {
+        return Err(user_error("regexp_extract group can't be negative"));
+    }
+    let regex = pattern(ctx, 1)?;
+    Ok(regex
+        .captures(&text)
+        .and_then(|captures| captures.get(group as usize))
+        .map(|found| String::from(found.as_str())))
+}
+
+///`split_part(text, delimiter, n)`: the nth part of text split on a delimiter, counting from 1.
+///Negative parts count from the end, and parts past the end are empty
+fn split_part(ctx: &Context) -> Result<Option<String>> {
+    let (text, delimiter) = match (text(ctx, 0)?, text(ctx, 1)?) {
+        (Some(text), Some(delimiter)) => (text, delimiter),
+        _ => return Ok(None),
+    };
+    let n: i64 = ctx.get(2)?;
+    if n == 0 {
+        return Err(user_error("split_part parts are counted from 1"));
+    }
+    let parts: Vec<&str> = if delimiter.is_empty() {
+        vec![text.as_str()]
+    } else {
+        text.split(delimiter.as_str()).collect()
+    };
+    let index = if n > 0 {
+        Some(n as usize - 1)
+    } else {
+        parts.len().checked_sub(n.unsigned_abs() as usize)
+    };
+    Ok(Some(String::from(
+        index.and_then(|i| parts.get(i)).copied().unwrap_or(""),
+    )))
+}
+
+///`json_flatten(json[, separator])`: an object with a key for every value nested in a JSON
+///object or array, which is the path to the value joined by the separator, `.` by default.
+///Empty objects and arrays are kept as values, and anything else is returned as it is
+fn json_flatten(ctx: &Context) -> Result<Option<String>> {
+    let json = match text(ctx, 0)? {
+        Some(json) => json,
+        None => return Ok(None),
+    };
+    let separator = if ctx.len() > 1 {
+        text(ctx, 1)?.unwrap_or_default()
+    } else {
+        String::from(".")
+    };
+    let value: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| user_error(format!("malformed JSON: {}", e)))?;
+    if !matches!(
+        value,
+        serde_json::Value::Object(_) | serde_json::Value::Array(_)
+    ) {
+        return Ok(Some(value.to_string()));
+    }
+    let mut flattened = serde_json::Map::new();
+    flatten_into(&mut flattened, None, value, &separator);
+    Ok(Some(serde_json::Value::Object(flattened).to_string()))
+}
+
+fn flatten_into(
+    flattened: &mut serde_json::Map<String, serde_json::Value>,
+    path: Option<String>,
+    value: serde_json::Value,
+    separator: &str,
+) {
+    let key = |name: String| match &path {
+        Some(path) => format!("{}{}{}", path, separator, name),
+        None => name,
+    };
+    match value {
+        serde_json::Value::Object(object) if !object.is_empty() => {
+            for (name, value) in object {
+                flatten_into(flattened, Some(key(name)), value, separator);
+            }
+        }
+        serde_json::Value::Array(array) if !array.is_empty() => {
+            for (i, value) in array.into_iter().enumerate() {
+                flatten_into(flattened, Some(key(i.to_string())), value, separator);
+            }
+        }
+        value => {
+            flattened.insert(path.unwrap_or_default(), value);
+        }
+    }
+}
+
 pub(crate) fn calculate_md5(ctx: &Context) -> Result<String> {
     assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
     let str = ctx.get_raw(0).as_str()?;
@@ -44,58 +369,487 @@ pub(crate) fn calculate_sqrt(ctx: &Context) -> Result<f64> {
     }
 }
 
-pub struct Stddev;
+///What a `Moment` aggregate takes of the values it's given
+#[derive(Clone, Copy, Debug)]
+pub enum Statistic {
+    Mean,
+    ///The variance of the whole population, or of a sample, which divides by one less than the
+    ///number of values
+    Variance {
+        sample: bool,
+    },
+    Stddev {
+        sample: bool,
+    },
+}
+
+///The number of values, their mean and the sum of their squared differences from it, kept with
+///Welford's algorithm, which can also remove values as they leave a window
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    count: u64,
+    mean: f64,
+    squares: f64,
+}
+
+impl Moments {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        let difference = x - self.mean;
+        self.mean += difference / self.count as f64;
+        self.squares += difference * (x - self.mean);
+    }
+
+    fn remove(&mut self, x: f64) {
+        if self.count <= 1 {
+            *self = Moments::default();
+            return;
+        }
+        self.count -= 1;
+        let difference = x - self.mean;
+        self.mean -= difference / self.count as f64;
+        self.squares = (self.squares - difference * (x - self.mean)).max(0.0);
+    }
+
+    fn statistic(&self, statistic: Statistic) -> Option<f64> {
+        let n = self.count as f64;
+        let variance = |sample: bool| match (sample, self.count) {
+            (_, 0) | (true, 1) => None,
+            (true, _) => Some(self.squares / (n - 1.0)),
+            (false, _) => Some(self.squares / n),
+        };
+        match statistic {
+            Statistic::Mean if self.count > 0 => Some(self.mean),
+            Statistic::Mean => None,
+            Statistic::Variance { sample } => variance(sample),
+            Statistic::Stddev { sample } => variance(sample).map(f64::sqrt),
+        }
+    }
+}
+
+///The mean, variance or standard deviation of a column, skipping NULLs. It can be used as a
+///window function, such as `stddev(x) OVER (ORDER BY day ROWS 6 PRECEDING)`
+pub struct Moment {
+    name: &'static str,
+    statistic: Statistic,
+}
+
+impl Aggregate<Moments, Option<f64>> for Moment {
+    fn init(&self, _: &mut Context<'_>) -> Result<Moments> {
+        Ok(Moments::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, moments: &mut Moments) -> Result<()> {
+        if let Some(x) = finite_number(ctx, 0, self.name)? {
+            moments.add(x);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, moments: Option<Moments>) -> Result<Option<f64>> {
+        Ok(moments.and_then(|moments| moments.statistic(self.statistic)))
+    }
+}
+
+impl WindowAggregate<Moments, Option<f64>> for Moment {
+    fn value(&self, moments: Option<&Moments>) -> Result<Option<f64>> {
+        Ok(moments.and_then(|moments| moments.statistic(self.statistic)))
+    }
+
+    fn inverse(&self, ctx: &mut Context<'_>, moments: &mut Moments) -> Result<()> {
+        if let Some(x) = finite_number(ctx, 0, self.name)? {
+            moments.remove(x);
+        }
+        Ok(())
+    }
+}
+
+///The value a fraction of the way through the sorted values, interpolating between the values
+///either side of it. `median` is the value half way through
+pub struct Percentile {
+    name: &'static str,
+    ///The fraction for `median`, or None to take it from the second argument
+    fraction: Option<f64>,
+    ///What the second argument is out of: 100 for `percentile`, as in SQLite's extension of the
+    ///same name, or 1 for `percentile_cont`
+    scale: f64,
+}
 
-impl Aggregate<OnlineStats, Option<f64>> for Stddev {
-    fn init(&self, _: &mut Context<'_>) -> Result<OnlineStats> {
-        Ok(OnlineStats::new())
+impl Aggregate<(Vec<f64>, f64), Option<f64>> for Percentile {
+    fn init(&self, _: &mut Context<'_>) -> Result<(Vec<f64>, f64)> {
+        Ok((vec![], self.fraction.unwrap_or_default()))
     }
 
-    fn step(&self, ctx: &mut Context<'_>, stdev: &mut OnlineStats) -> Result<()> {
-        let value = ctx.get_raw(0);
-        match value {
-            ValueRef::Null => stdev.add_null(),
-            ValueRef::Integer(i) => stdev.add(i as f64),
-            ValueRef::Real(f) => stdev.add(f),
-            ValueRef::Text(_) | ValueRef::Blob(_) => {
-                return Result::Err(rusqlite::Error::UserFunctionError(
-                    "can't take stddev of this value".into(),
-                ));
+    fn step(&self, ctx: &mut Context<'_>, state: &mut (Vec<f64>, f64)) -> Result<()> {
+        if self.fraction.is_none() {
+            let percentile: f64 = ctx.get(1)?;
+            if !(0.0..=self.scale).contains(&percentile) {
+                return Err(user_error(format!(
+                    "the percentile of {} must be between 0 and {}",
+                    self.name, self.scale
+                )));
             }
+            state.1 = percentile / self.scale;
+        }
+        if let Some(x) = finite_number(ctx, 0, self.name)? {
+            state.0.push(x);
         }
         Ok(())
     }
 
-    fn finalize(&self, _: &mut Context<'_>, numbers: Option<OnlineStats>) -> Result<Option<f64>> {
-        let stddev = numbers.map(|n| n.stddev());
-        Ok(stddev)
+    fn finalize(&self, _: &mut Context<'_>, state: Option<(Vec<f64>, f64)>) -> Result<Option<f64>> {
+        let (mut values, fraction) = match state {
+            Some(state) if !state.0.is_empty() => state,
+            _ => return Ok(None),
+        };
+        values.sort_by(f64::total_cmp);
+        let rank = fraction * (values.len() - 1) as f64;
+        let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+        Ok(Some(
+            values[below] + (values[above] - values[below]) * (rank - below as f64),
+        ))
     }
 }
 
-pub struct Mean;
+///The most common value, or the first of them to be seen when several are as common
+pub struct Mode;
 
-impl Aggregate<OnlineStats, Option<f64>> for Mean {
-    fn init(&self, _: &mut Context<'_>) -> Result<OnlineStats> {
-        Ok(OnlineStats::new())
+impl Aggregate<IndexMap<String, (Value, usize)>, Option<Value>> for Mode {
+    fn init(&self, _: &mut Context<'_>) -> Result<IndexMap<String, (Value, usize)>> {
+        Ok(IndexMap::new())
+    }
+
+    fn step(
+        &self,
+        ctx: &mut Context<'_>,
+        counts: &mut IndexMap<String, (Value, usize)>,
+    ) -> Result<()> {
+        let value = Value::from(ctx.get_raw(0));
+        if value != Value::Null {
+            // values of different types are different, even if they look the same
+            let key = format!("{:?}", value);
+            counts.entry(key).or_insert((value, 0)).1 += 1;
+        }
+        Ok(())
     }
 
-    fn step(&self, ctx: &mut Context<'_>, mean: &mut OnlineStats) -> Result<()> {
-        let value = ctx.get_raw(0);
-        match value {
-            ValueRef::Null => mean.add_null(),
-            ValueRef::Integer(i) => mean.add(i as f64),
-            ValueRef::Real(f) => mean.add(f),
-            ValueRef::Text(_) | ValueRef::Blob(_) => {
-                return Result::Err(rusqlite::Error::UserFunctionError(
-                    "can't take mean of this value".into(),
-                ));
+    fn finalize(
+        &self,
+        _: &mut Context<'_>,
+        counts: Option<IndexMap<String, (Value, usize)>>,
+    ) -> Result<Option<Value>> {
+        let mut mode: Option<(Value, usize)> = None;
+        for (_, (value, count)) in counts.into_iter().flatten() {
+            if mode.as_ref().is_none_or(|(_, most)| count > *most) {
+                mode = Some((value, count));
             }
         }
+        Ok(mode.map(|(value, _)| value))
+    }
+}
+
+///Running means and co-moments of pairs of numbers, skipping pairs with a NULL
+#[derive(Default)]
+pub struct CoMoments {
+    n: f64,
+    mean_x: f64,
+    mean_y: f64,
+    ///The sum of the products of the deviations of x and y from their means
+    c: f64,
+    ///The sums of the squared deviations of x and y from their means
+    m2_x: f64,
+    m2_y: f64,
+}
+
+impl CoMoments {
+    fn step(&mut self, ctx: &Context<'_>, function: &str) -> Result<()> {
+        let (x, y) = match (
+            finite_number(ctx, 0, function)?,
+            finite_number(ctx, 1, function)?,
+        ) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Ok(()),
+        };
+        self.n += 1.0;
+        let (dx, dy) = (x - self.mean_x, y - self.mean_y);
+        self.mean_x += dx / self.n;
+        self.mean_y += dy / self.n;
+        self.c += dx * (y - self.mean_y);
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
         Ok(())
     }
+}
+
+///The population covariance of two columns
+pub struct Covariance;
+
+impl Aggregate<CoMoments, Option<f64>> for Covariance {
+    fn init(&self, _: &mut Context<'_>) -> Result<CoMoments> {
+        Ok(CoMoments::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, moments: &mut CoMoments) -> Result<()> {
+        moments.step(ctx, "covar")
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, moments: Option<CoMoments>) -> Result<Option<f64>> {
+        Ok(moments.filter(|m| m.n > 0.0).map(|m| m.c / m.n))
+    }
+}
+
+///The Pearson correlation coefficient of two columns, which is NULL when either doesn't vary
+pub struct Correlation;
+
+impl Aggregate<CoMoments, Option<f64>> for Correlation {
+    fn init(&self, _: &mut Context<'_>) -> Result<CoMoments> {
+        Ok(CoMoments::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, moments: &mut CoMoments) -> Result<()> {
+        moments.step(ctx, "corr")
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, moments: Option<CoMoments>) -> Result<Option<f64>> {
+        Ok(moments
+            .filter(|m| m.m2_x > 0.0 && m.m2_y > 0.0)
+            .map(|m| m.c / (m.m2_x * m.m2_y).sqrt()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::FromSql;
+
+    use super::*;
+
+    fn query<T: FromSql>(sql: &str) -> T {
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        connection.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    const VALUES: &str = "(values (1), (3), (2), (10), (3), (null))";
+
+    #[test]
+    fn it_takes_medians_and_percentiles() {
+        let median: f64 = query(&format!("select median(column1) from {}", VALUES));
+        assert_eq!(median, 3.0);
+        let even: f64 = query("select median(column1) from (values (1), (2), (4), (10))");
+        assert_eq!(even, 3.0);
+        let quartile: f64 = query(&format!("select percentile(column1, 25) from {}", VALUES));
+        assert_eq!(quartile, 2.0);
+        let quartile: f64 = query(&format!(
+            "select percentile_cont(column1, 0.25) from {}",
+            VALUES
+        ));
+        assert_eq!(quartile, 2.0);
+        let empty: Option<f64> = query("select median(column1) from (values (null))");
+        assert_eq!(empty, None);
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        for sql in ["select percentile(1, 150)", "select percentile_cont(1, 50)"].iter() {
+            let error = connection
+                .query_row(sql, [], |row| row.get::<_, f64>(0))
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("must be between 0 and"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_infinite_values_in_statistics() {
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        for function in ["median", "stddev", "var_samp", "mean"].iter() {
+            let sql = format!(
+                "select {}(column1) from (values (1), (pow(10, 400)), (3))",
+                function
+            );
+            let error = connection
+                .query_row(&sql, [], |row| row.get::<_, Option<f64>>(0))
+                .unwrap_err();
+            assert!(error.to_string().contains("infinite"), "{}", error);
+        }
+        assert!(connection
+            .query_row(
+                "select corr(column1, column2) from (values (1, -pow(10, 400)), (2, 3))",
+                [],
+                |row| row.get::<_, Option<f64>>(0)
+            )
+            .is_err());
+        let exp: f64 = query("select exp(pow(10, 400))");
+        assert!(exp.is_infinite());
+    }
+
+    #[test]
+    fn it_finds_the_most_common_value() {
+        let mode: i64 = query(&format!("select mode(column1) from {}", VALUES));
+        assert_eq!(mode, 3);
+        let first: String = query("select mode(column1) from (values ('b'), ('a'), ('a'), ('b'))");
+        assert_eq!(first, "b");
+    }
+
+    #[test]
+    fn it_takes_variances_covariances_and_correlations() {
+        let variance: f64 = query(&format!("select variance(column1) from {}", VALUES));
+        assert!((variance - 10.16).abs() < 1e-9);
+        let covariance: f64 = query(&format!(
+            "select covar(column1, column1 * 2) from {}",
+            VALUES
+        ));
+        assert!((covariance - 20.32).abs() < 1e-9);
+        let correlation: f64 =
+            query("select corr(column1, column2) from (values (1, 2), (2, 4), (3, 5), (4, 9))");
+        assert!((correlation - 11.0 / 130f64.sqrt()).abs() < 1e-9);
+        let constant: Option<f64> = query("select corr(column1, 1) from (values (1), (2), (3))");
+        assert_eq!(constant, None);
+    }
+
+    #[test]
+    fn it_takes_sample_and_population_moments_of_numeric_text() {
+        let values = "(values ('1'), (' 3 '), (2), (10.0), ('3'), (''), (null))";
+        let population: f64 = query(&format!("select var_pop(column1) from {}", values));
+        assert!((population - 10.16).abs() < 1e-9);
+        let sample: f64 = query(&format!("select var_samp(column1) from {}", values));
+        assert!((sample - 12.7).abs() < 1e-9);
+        let deviation: f64 = query(&format!("select stddev_samp(column1) from {}", values));
+        assert!((deviation - 12.7f64.sqrt()).abs() < 1e-9);
+        let mean: f64 = query(&format!("select mean(column1) from {}", values));
+        assert_eq!(mean, 3.8);
+        let single: Option<f64> = query("select stddev_samp(column1) from (values (1))");
+        assert_eq!(single, None);
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        assert!(connection
+            .query_row("select stddev('one')", [], |row| row.get::<_, f64>(0))
+            .is_err());
+    }
+
+    #[test]
+    fn it_takes_moments_over_windows() {
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        let mut statement = connection
+            .prepare(
+                "select stddev(column1) over (order by column1 rows between 1 preceding and current row), \
+                 mean(column1) over (order by column1 rows between 1 preceding and current row) \
+                 from (values (1), (3), (7), (null))",
+            )
+            .unwrap();
+        let rows: Vec<(Option<f64>, Option<f64>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (None, None),
+                (Some(0.0), Some(1.0)),
+                (Some(1.0), Some(2.0)),
+                (Some(2.0), Some(5.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_matches_replaces_and_extracts_regular_expressions() {
+        let matched: bool = query("select regexp('^a\\d+$', 'a123')");
+        assert!(matched);
+        let replaced: String = query("select regexp_replace('a1b22', '(\\d+)', '<$1>')");
+        assert_eq!(replaced, "a<1>b<22>");
+        let extracted: String = query("select regexp_extract('key=value', '(\\w+)=(\\w+)', 2)");
+        assert_eq!(extracted, "value");
+        let whole: String = query("select regexp_extract('id: 42', '\\d+')");
+        assert_eq!(whole, "42");
+        let missing: Option<String> = query("select regexp_extract('abc', '\\d+')");
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn it_hashes_text() {
+        let sha1: String = query("select sha1('abc')");
+        assert_eq!(sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        let sha256: String = query("select sha256('abc')");
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn it_splits_and_compares_text() {
+        let second: String = query("select split_part('a,b,c', ',', 2)");
+        assert_eq!(second, "b");
+        let last: String = query("select split_part('a,b,c', ',', -1)");
+        assert_eq!(last, "c");
+        let past_the_end: String = query("select split_part('a,b,c', ',', 4)");
+        assert_eq!(past_the_end, "");
+        let distance: i64 = query("select levenshtein('kitten', 'sitting')");
+        assert_eq!(distance, 3);
+    }
+
+    #[test]
+    fn it_flattens_json() {
+        let flattened: String = query(
+            r#"select json_flatten('{"user": {"name": "Ann", "tags": ["a", "b"]}, "empty": {}, "id": 1}')"#,
+        );
+        assert_eq!(
+            flattened,
+            r#"{"user.name":"Ann","user.tags.0":"a","user.tags.1":"b","empty":{},"id":1}"#
+        );
+        let separated: String = query(r#"select json_flatten('[{"a": null}]', '/')"#);
+        assert_eq!(separated, r#"{"0/a":null}"#);
+        let scalar: String = query("select json_flatten('2.5')");
+        assert_eq!(scalar, "2.5");
+        let connection = Connection::open_in_memory().unwrap();
+        add_udfs(&connection).unwrap();
+        let malformed: Result<String> =
+            connection.query_row("select json_flatten('{')", [], |row| row.get(0));
+        assert!(malformed.is_err());
+    }
+
+    #[test]
+    fn it_provides_sqlites_json_functions() {
+        let extracted: i64 = query(r#"select json_extract('{"a": [1, 2]}', '$.a[1]')"#);
+        assert_eq!(extracted, 2);
+        let unquoted: String = query(r#"select '{"a": {"b": "c"}}' -> 'a' ->> 'b'"#);
+        assert_eq!(unquoted, "c");
+        let each: i64 = query("select sum(value) from json_each('[1, 2, 3]')");
+        assert_eq!(each, 6);
+        let grouped: String = query("select json_group_array(value) from json_each('[1, 2, 3]')");
+        assert_eq!(grouped, "[1,2,3]");
+    }
 
-    fn finalize(&self, _: &mut Context<'_>, numbers: Option<OnlineStats>) -> Result<Option<f64>> {
-        let mean = numbers.map(|n| n.mean());
-        Ok(mean)
+    #[test]
+    fn it_runs_math_functions() {
+        let results: (f64, f64, f64, f64, f64) = {
+            let connection = Connection::open_in_memory().unwrap();
+            add_udfs(&connection).unwrap();
+            connection
+                .query_row(
+                    "select log(100), log(2, 8), exp(0), pow(2, 10), degrees(pi())",
+                    [],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    },
+                )
+                .unwrap()
+        };
+        assert_eq!(results, (2.0, 3.0, 1.0, 1024.0, 180.0));
+        let not_a_number: Option<f64> = query("select ln(-1)");
+        assert_eq!(not_a_number, None);
+        let angle: f64 = query("select atan2(1, 1) * 4");
+        assert!((angle - std::f64::consts::PI).abs() < 1e-12);
     }
 }
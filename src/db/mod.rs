@@ -1,103 +1,503 @@
-use std::error::Error;
-use std::str;
+use std::panic::RefUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
 use log::debug;
-use rusqlite::types::ValueRef;
-use rusqlite::{CachedStatement, Connection, Result};
+use rusqlite::limits::Limit;
+use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use rusqlite::{CachedStatement, Connection, Error, ErrorCode, Result, Statement};
+use uuid::Uuid;
 
-use crate::db::utils::{escape_fields, escape_table, repeat_vars};
+use crate::csv::csv_data::CsvType;
+use crate::db::plugins::{add_sql_functions, load_extensions, SqlFunction};
+use crate::db::utils::{create_table_statement, escape_fields, escape_table, repeat_rows};
 
+mod approximate;
+mod dates;
 mod functions;
+pub mod plugins;
 pub mod utils;
+mod vtab;
+
+///The most rows inserted by a single statement. Larger statements don't insert any faster, and
+///take longer to prepare
+const MAX_ROWS_PER_INSERT: usize = 64;
+///How many of SQLite's virtual machine instructions run between checks for whether to interrupt
+///the running statement
+const INTERRUPT_CHECK_OPS: i32 = 10_000;
+
+///qsv's own functions which return JSON, so their results are written as JSON rather than as
+///strings
+pub const JSON_FUNCTIONS: &[&str] = &[functions::JSON_FLATTEN, approximate::APPROX_TOP_K];
+
+///Decides whether the running statement should be interrupted
+pub type InterruptCheck = Arc<dyn Fn() -> bool + Send + Sync + RefUnwindSafe>;
+
+///How the database is set up, on top of qsv's defaults
+#[derive(Clone, Debug, Default)]
+pub struct DbOptions {
+    ///Pragmas to set once the database is opened, overriding the defaults
+    pub pragmas: Vec<(String, Value)>,
+    ///The most bytes the database can use in memory. Loading more data than this moves the
+    ///database to a temporary file. The limit is approximate: it's checked against the database's
+    ///pages before and after each step which loads data, so a single step can go over it, and
+    ///SQLite's caches and the memory used while running a statement aren't counted
+    pub memory_limit: Option<u64>,
+    ///Where the temporary file is created. SQLite's own temporary files, such as for large sorts,
+    ///only go here once `use_temp_dir` has been called
+    pub temp_dir: Option<PathBuf>,
+    ///SQLite extensions to load, which can add functions, virtual tables and collations
+    pub extensions: Vec<PathBuf>,
+    ///Functions defined in SQL, added after qsv's own functions and those of the extensions
+    pub functions: Vec<SqlFunction>,
+}
+
+///Makes SQLite create its own temporary files, such as for large sorts, in a directory. SQLite
+///reads this from the environment, which is shared by the whole process, so it's only set once at
+///startup, before any threads are started
+pub fn use_temp_dir(temp_dir: &Path) {
+    std::env::set_var("SQLITE_TMPDIR", temp_dir);
+}
 
 pub struct Db {
     pub connection: Connection,
+    options: DbOptions,
+    ///The temporary file the database was moved to once it outgrew its memory limit
+    file: Option<PathBuf>,
+    interrupt: Option<InterruptCheck>,
+}
+
+///A step of SQLite's plan for running a statement, from `EXPLAIN QUERY PLAN`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanStep {
+    pub id: i64,
+    ///The id of the step this is part of, or 0 for a top level step
+    pub parent: i64,
+    pub detail: String,
 }
 
 pub type Header = Vec<String>;
-pub type Rows = Vec<Vec<String>>;
+pub type Rows = Vec<Vec<Value>>;
+///Named parameters, such as `:name`, along with the values to bind to them
+pub type Parameters = Vec<(String, Value)>;
 impl Db {
+    #[cfg(test)]
     pub fn open_in_memory() -> Result<Db> {
+        Db::open(DbOptions::default())
+    }
+
+    ///Opens an in-memory database, which moves to a temporary file if it outgrows its memory limit
+    pub fn open(options: DbOptions) -> Result<Db> {
         let connection = Connection::open_in_memory()?;
-        let mmap_size: u32 = 0;
-        connection.pragma_update(None, "mmap_size", &mmap_size)?;
-        connection.pragma_update(None, "journal_mode", &"off")?;
-        connection.pragma_update(None, "synchronous", &"off")?;
-        connection.pragma_update(None, "cache_size", &-16_000i32)?;
-        connection.pragma_update(None, "read_uncommitted", &"true")?;
-        connection.pragma_update(None, "wal_autocheckpoint", &0u32)?;
-        connection.pragma_update(None, "threads", &8u32)?;
-        functions::add_udfs(&connection)?;
-        Ok(Db { connection })
-    }
-
-    pub fn create_table(&mut self, table_name: &str, fields: &[&str]) -> Result<usize> {
+        configure(&connection, &options)?;
+        Ok(Db {
+            connection,
+            options,
+            file: None,
+            interrupt: None,
+        })
+    }
+
+    ///Interrupts any running statement once `check` returns true. A SELECT which is interrupted
+    ///returns the rows it found before then, while other statements fail
+    pub fn interrupt_when(&mut self, check: InterruptCheck) {
+        self.interrupt = Some(check);
+        self.set_progress_handler();
+    }
+
+    fn set_progress_handler(&self) {
+        if let Some(ref check) = self.interrupt {
+            let check = check.clone();
+            self.connection
+                .progress_handler(INTERRUPT_CHECK_OPS, Some(move || check()));
+        }
+    }
+
+    ///The number of bytes the database takes up
+    fn size(&self) -> Result<u64> {
+        let (page_count, page_size): (i64, i64) = self.connection.query_row(
+            "SELECT page_count, page_size FROM pragma_page_count, pragma_page_size",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((page_count * page_size) as u64)
+    }
+
+    ///Moves the database from memory to a temporary file if adding `incoming` bytes would take it
+    ///over its memory limit. The file is deleted once the database is dropped
+    fn spill_if_needed(&mut self, incoming: u64) -> Result<()> {
+        let limit = match self.options.memory_limit {
+            Some(limit) if self.file.is_none() => limit,
+            _ => return Ok(()),
+        };
+        let size = self.size()?;
+        if size + incoming <= limit {
+            return Ok(());
+        }
+        let directory = self
+            .options
+            .temp_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let path = directory.join(format!("qsv-{}.db", Uuid::new_v4()));
+        debug!(
+            "Loading {} more bytes would take the database over its limit of {} bytes, moving it to {}",
+            incoming,
+            limit,
+            path.display()
+        );
+        self.connection
+            .execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+        self.file = Some(path.clone());
+        let connection = Connection::open(&path)?;
+        configure(&connection, &self.options)?;
+        self.connection = connection;
+        self.set_progress_handler();
+        Ok(())
+    }
+
+    ///Moves the database to a temporary file if it's already over its memory limit, once a step
+    ///which can add to it has run
+    fn check_memory_limit(&mut self) -> Result<()> {
+        self.spill_if_needed(0)
+    }
+
+    ///Creates a table with the given columns. Every identifier is quoted, and column types only
+    ///come from CsvType, so nothing from a file can change the statement
+    pub fn create_table(&mut self, table_name: &str, columns: &[(&str, CsvType)]) -> Result<usize> {
+        let string = create_table_statement(table_name, columns);
+        self.connection.execute(string.as_str(), [])
+    }
+
+    ///Creates a virtual table which reads a CSV file in place whenever it's queried, rather than
+    ///importing its rows
+    pub fn create_csv_table(
+        &mut self,
+        table_name: &str,
+        filename: &str,
+        delimiter: char,
+        trim: bool,
+        textonly: bool,
+        normalize_headers: bool,
+    ) -> Result<usize> {
+        let yes_or_no = |b: bool| if b { "yes" } else { "no" };
         let string = format!(
-            "create table {} ({});",
+            "create virtual table {} using {}(filename={}, delimiter={}, trim={}, textonly={}, normalize_headers={});",
             escape_table(table_name),
-            fields.join(", ")
+            vtab::MODULE_NAME,
+            vtab::quote_argument(filename),
+            vtab::quote_argument(delimiter.to_string().as_str()),
+            yes_or_no(trim),
+            yes_or_no(textonly),
+            yes_or_no(normalize_headers)
         );
-        self.connection.execute(string.as_str(), [])
+        let created = self.connection.execute(string.as_str(), [])?;
+        self.check_memory_limit()?;
+        Ok(created)
     }
 
-    pub fn insert(&mut self, table_name: &str, fields: &[&str], values: Vec<Vec<&str>>) {
-        let fields_len = fields.len();
+    ///Creates an index on columns of a table, unless it already has one on exactly those columns.
+    ///Returns the name of the index if it was created
+    pub fn create_index(&mut self, table_name: &str, columns: &[&str]) -> Result<Option<String>> {
+        if self.has_index(table_name, columns)? {
+            return Ok(None);
+        }
+        // names made of the table and columns can be the same for different ones, such as `a`
+        // with `b_c` and `a_b` with `c`, so a number is added to a name which is already taken
+        let name = format!("qsv_{}_{}", table_name, columns.join("_"));
+        let mut index_name = name.clone();
+        let mut number = 1;
+        while self.has_name(&index_name)? {
+            number += 1;
+            index_name = format!("{}_{}", name, number);
+        }
         let string = format!(
-            "INSERT INTO {} ({}) values ({})",
+            "create index {} on {} ({});",
+            escape_table(&index_name),
             escape_table(table_name),
-            escape_fields(fields).join(", "),
-            repeat_vars(fields_len)
+            escape_fields(columns).join(", ")
         );
-        let mut stmt = self.connection.prepare_cached(string.as_str()).unwrap();
-        self.connection.execute_batch("BEGIN TRANSACTION").unwrap();
-        let now = Instant::now();
+        self.connection.execute(string.as_str(), [])?;
+        self.check_memory_limit()?;
+        Ok(Some(index_name))
+    }
 
-        for value in values.iter() {
-            let params_from_iter = rusqlite::params_from_iter(value);
-            stmt.execute(params_from_iter).unwrap();
+    ///Whether a table has an index on exactly these columns, in this order
+    fn has_index(&self, table_name: &str, columns: &[&str]) -> Result<bool> {
+        let mut statement = self.connection.prepare(
+            "SELECT il.name, ii.name FROM pragma_index_list(?1) il, pragma_index_info(il.name) ii ORDER BY il.name, ii.seqno",
+        )?;
+        let mut indexes: Vec<(String, Vec<String>)> = vec![];
+        let rows = statement.query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (index, column): (String, Option<String>) = row?;
+            match indexes.last_mut() {
+                Some((name, indexed)) if *name == index => indexed.extend(column),
+                _ => indexes.push((index, column.into_iter().collect())),
+            }
+        }
+        Ok(indexes.iter().any(|(_, indexed)| {
+            indexed.len() == columns.len()
+                && indexed
+                    .iter()
+                    .zip(columns.iter())
+                    .all(|(a, b)| a.eq_ignore_ascii_case(b))
+        }))
+    }
+
+    ///Whether a table, view, index or trigger has a name, which they can't share
+    fn has_name(&self, name: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE name = ?1 COLLATE NOCASE",
+            [name],
+            |row| row.get(0),
+        )
+    }
+
+    ///The names of a table's columns, in order
+    pub fn columns(&self, table_name: &str) -> Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name FROM pragma_table_info(?1)")?;
+        let names = statement.query_map([table_name], |row| row.get(0))?;
+        names.collect()
+    }
+
+    ///The names of a table's columns along with their declared types, in order
+    pub fn column_types(&self, table_name: &str) -> Result<Vec<(String, String)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, lower(type) FROM pragma_table_info(?1)")?;
+        let columns = statement.query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        columns.collect()
+    }
+
+    ///Whether a table or view has a name, ignoring case as SQLite does
+    pub fn has_table(&self, table_name: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE type IN ('table', 'view') AND name = ?1 COLLATE NOCASE",
+            [table_name],
+            |row| row.get(0),
+        )
+    }
+
+    ///Whether SQLite has the `->` and `->>` operators, which were added in 3.38 along with JSON
+    ///being built in unless left out with `SQLITE_OMIT_JSON`
+    pub fn supports_json_operators(&self) -> Result<bool> {
+        if rusqlite::version_number() < 3_038_000 {
+            return Ok(false);
+        }
+        self.connection.query_row(
+            "SELECT NOT sqlite_compileoption_used('OMIT_JSON')",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    ///Whether a table is a virtual table, which SQLite can't index
+    pub fn is_virtual_table(&self, table_name: &str) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1 AND sql LIKE 'create virtual table%'",
+            [table_name],
+            |row| row.get(0),
+        )
+    }
+
+    ///Inserts rows into a table, binding each value as its column's type so SQLite doesn't need to
+    ///convert it. Rows are inserted several at a time, as many as fit in a statement without going
+    ///over SQLite's limit on the number of variables
+    pub fn insert(
+        &mut self,
+        table_name: &str,
+        fields: &[&str],
+        types: &[CsvType],
+        values: Vec<Vec<&str>>,
+    ) -> Result<()> {
+        let incoming: usize = values.iter().flatten().map(|value| value.len()).sum();
+        self.spill_if_needed(incoming as u64)?;
+        let fields_len = fields.len();
+        let max_variables = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let rows_per_insert = (max_variables / fields_len.max(1)).clamp(1, MAX_ROWS_PER_INSERT);
+        let insert = |rows: usize| {
+            format!(
+                "INSERT INTO {} ({}) values {}",
+                escape_table(table_name),
+                escape_fields(fields).join(", "),
+                repeat_rows(fields_len, rows)
+            )
+        };
+        let transaction = self.connection.transaction()?;
+        let now = Instant::now();
+        {
+            let mut batches = values.chunks_exact(rows_per_insert);
+            if batches.len() > 0 {
+                let mut stmt = transaction.prepare_cached(insert(rows_per_insert).as_str())?;
+                for batch in batches.by_ref() {
+                    bind_rows(&mut stmt, batch, types)?;
+                    stmt.raw_execute()?;
+                }
+            }
+            let remainder = batches.remainder();
+            if !remainder.is_empty() {
+                let mut stmt = transaction.prepare(insert(remainder.len()).as_str())?;
+                bind_rows(&mut stmt, remainder, types)?;
+                stmt.raw_execute()?;
+            }
         }
         let elapsed = now.elapsed().as_millis();
         debug!("wrote {} records in {} ms", values.len(), elapsed);
-        self.connection.execute_batch("END TRANSACTION").unwrap();
+        transaction.commit()?;
+        // the size of the rows is only estimated from their text, so check what they really took
+        self.check_memory_limit()
+    }
+
+    ///Executes a statement which doesn't return rows, such as CREATE VIEW or CREATE TABLE ... AS
+    pub fn execute(&mut self, statement: &str, parameters: &Parameters) -> Result<usize> {
+        debug!("Running statement: {:?}", statement);
+        let mut statement = self.connection.prepare(statement)?;
+        bind_parameters(&mut statement, parameters)?;
+        let changes = statement.raw_execute()?;
+        drop(statement);
+        self.check_memory_limit()?;
+        Ok(changes)
     }
 
-    pub fn select_statement(&self, query: &str) -> Result<(Header, Rows), Box<dyn Error>> {
+    ///SQLite's plan for running a statement, without running it
+    pub fn query_plan(&self, statement: &str, parameters: &Parameters) -> Result<Vec<PlanStep>> {
+        let query = format!("EXPLAIN QUERY PLAN {}", statement);
+        debug!("Running statement: {:?}", query);
+        let mut statement = self.connection.prepare(&query)?;
+        bind_parameters(&mut statement, parameters)?;
+        let mut rows = statement.raw_query();
+        let mut plan = vec![];
+        while let Some(row) = rows.next()? {
+            plan.push(PlanStep {
+                id: row.get(0)?,
+                parent: row.get(1)?,
+                detail: row.get(3)?,
+            });
+        }
+        Ok(plan)
+    }
+
+    pub fn select_statement(&self, query: &str, parameters: &Parameters) -> Result<(Header, Rows)> {
         debug!("Running select statement: {:?}", query);
 
-        let mut statement: CachedStatement = self.connection.prepare_cached(query).unwrap();
-        let results = statement
-            .query_map([], move |row| {
-                let mut vec = Vec::with_capacity(row.column_count());
-                for i in 0..row.column_count() {
-                    let value = row.get_ref_unwrap(i);
-                    let v = match value {
-                        ValueRef::Null => String::from("null"),
-                        ValueRef::Integer(i) => i.to_string(),
-                        ValueRef::Text(buf) => String::from(str::from_utf8(buf).unwrap()),
-                        ValueRef::Real(f) => f.to_string(),
-                        ValueRef::Blob(b) => String::from_utf8_lossy(b).to_string(),
-                    };
-                    vec.push(v);
-                }
-                Ok(vec)
-            })
-            .unwrap();
+        let mut statement: CachedStatement = self.connection.prepare_cached(query)?;
+        bind_parameters(&mut statement, parameters)?;
+        let header = statement
+            .column_names()
+            .iter()
+            .map(|s| String::from(*s))
+            .collect();
+        let mut results = statement.raw_query();
         let mut vec = Vec::with_capacity(1000);
-        for result in results {
-            vec.push(result?);
+        loop {
+            let row = match results.next() {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                    if e.code == ErrorCode::OperationInterrupted =>
+                {
+                    debug!("Interrupted after {} rows", vec.len());
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            let mut values = Vec::with_capacity(row.column_count());
+            for i in 0..row.column_count() {
+                let value = row.get_ref_unwrap(i);
+                let v = match value {
+                    ValueRef::Null => Value::Null,
+                    ValueRef::Integer(i) => Value::Integer(i),
+                    ValueRef::Text(buf) => Value::Text(String::from_utf8_lossy(buf).to_string()),
+                    ValueRef::Real(f) => Value::Real(f),
+                    ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+                };
+                values.push(v);
+            }
+            vec.push(values);
+        }
+        Ok((header, vec))
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.file {
+            if let Err(e) = std::fs::remove_file(path) {
+                debug!("Unable to remove {}: {}", path.display(), e);
+            }
         }
-        Ok((
-            statement
-                .column_names()
-                .iter()
-                .map(|s| String::from(*s))
-                .collect(),
-            vec,
-        ))
     }
 }
 
+///Sets the pragmas a database is opened with, then the user's own, and registers qsv's functions
+///and CSV module. Called again on the connection a database is moved to, since none of these are
+///stored in the database itself
+fn configure(connection: &Connection, options: &DbOptions) -> Result<()> {
+    let mmap_size: u32 = 0;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+    connection.pragma_update(None, "mmap_size", &mmap_size)?;
+    connection.pragma_update(None, "journal_mode", &"off")?;
+    connection.pragma_update(None, "synchronous", &"off")?;
+    connection.pragma_update(None, "cache_size", &-16_000i32)?;
+    connection.pragma_update(None, "read_uncommitted", &"true")?;
+    connection.pragma_update(None, "wal_autocheckpoint", &0u32)?;
+    connection.pragma_update(None, "threads", &threads)?;
+    for (name, value) in options.pragmas.iter() {
+        debug!("Setting pragma {} to {:?}", name, value);
+        connection.pragma_update(None, name, value)?;
+    }
+    functions::add_udfs(connection)?;
+    load_extensions(connection, &options.extensions)?;
+    add_sql_functions(connection, &options.functions, &options.extensions)?;
+    vtab::load_module(connection)
+}
+
+///Binds rows of values to the parameters of a multi-row INSERT, converting each value to the type
+///of its column. Values which don't parse as their column's type are bound as text, as are
+///infinite and NaN floats, which SQLite would otherwise store as a number or NULL
+fn bind_rows(statement: &mut Statement, rows: &[Vec<&str>], types: &[CsvType]) -> Result<()> {
+    let mut index = 1;
+    for row in rows.iter() {
+        for (i, value) in row.iter().enumerate() {
+            let text = ValueRef::Text(value.as_bytes());
+            let typed = match types.get(i) {
+                Some(CsvType::Integer) => value.parse().map_or(text, ValueRef::Integer),
+                Some(CsvType::Float) => match value.parse::<f64>() {
+                    Ok(float) if float.is_finite() => ValueRef::Real(float),
+                    _ => text,
+                },
+                _ => text,
+            };
+            statement.raw_bind_parameter(index, ToSqlOutput::Borrowed(typed))?;
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+///Binds named parameters to the placeholders of the same name in a statement.
+///Parameters which the statement doesn't use are ignored, but a placeholder without a parameter
+///is an error rather than NULL, so a misspelt name doesn't look like an empty result
+fn bind_parameters(statement: &mut Statement, parameters: &Parameters) -> Result<()> {
+    let mut bound = 0;
+    for (name, value) in parameters.iter() {
+        if let Some(index) = statement.parameter_index(name)? {
+            debug!("Binding parameter {} to {:?}", name, value);
+            statement.raw_bind_parameter(index, value)?;
+            bound += 1;
+        }
+    }
+    let count = statement.parameter_count();
+    if bound < count {
+        return Err(Error::InvalidParameterCount(bound, count));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -115,8 +515,11 @@ mod test {
     #[test]
     fn can_create_table() {
         let mut db = Db::open_in_memory().unwrap();
-        db.create_table("foobar", &vec!["id integer", "name text"])
-            .unwrap();
+        db.create_table(
+            "foobar",
+            &[("id", CsvType::Integer), ("name", CsvType::String)],
+        )
+        .unwrap();
         let result: usize = db
             .connection
             .execute(
@@ -136,15 +539,275 @@ mod test {
     }
 
     #[test]
-    fn can_insert() {
+    fn can_create_an_index_once() {
         let mut db = Db::open_in_memory().unwrap();
-        db.create_table("foobar", &vec!["id integer", "name text"])
+        db.create_table(
+            "foobar",
+            &[("id", CsvType::Integer), ("full name", CsvType::String)],
+        )
+        .unwrap();
+        assert_eq!(db.columns("foobar").unwrap(), vec!["id", "full name"]);
+        assert_eq!(
+            db.create_index("foobar", &["id", "full name"]).unwrap(),
+            Some(String::from("qsv_foobar_id_full name"))
+        );
+        assert_eq!(
+            db.create_index("foobar", &["id", "full name"]).unwrap(),
+            None
+        );
+        assert!(!db.is_virtual_table("foobar").unwrap());
+        assert!(db.has_table("FooBar").unwrap());
+        assert!(!db.has_table("foo").unwrap());
+    }
+
+    #[test]
+    fn indexes_different_columns_with_the_same_name_separately() {
+        let mut db = Db::open_in_memory().unwrap();
+        db.create_table("a", &[("b_c", CsvType::Integer), ("b", CsvType::Integer)])
             .unwrap();
+        db.create_table("a_b", &[("c", CsvType::Integer)]).unwrap();
+        assert_eq!(
+            db.create_index("a", &["b_c"]).unwrap(),
+            Some(String::from("qsv_a_b_c"))
+        );
+        assert_eq!(
+            db.create_index("a_b", &["c"]).unwrap(),
+            Some(String::from("qsv_a_b_c_2"))
+        );
+        assert_eq!(db.create_index("a_b", &["c"]).unwrap(), None);
+        // an index on more columns, starting with the same one, doesn't count
+        db.create_index("a", &["b", "b_c"]).unwrap();
+        assert_eq!(
+            db.create_index("a", &["b"]).unwrap(),
+            Some(String::from("qsv_a_b"))
+        );
+    }
+
+    #[test]
+    fn can_insert_typed_values_in_batches() {
+        let mut db = Db::open_in_memory().unwrap();
+        db.create_table(
+            "foobar",
+            &[
+                ("id", CsvType::Integer),
+                ("ratio", CsvType::Float),
+                ("name", CsvType::String),
+            ],
+        )
+        .unwrap();
+        let ids: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let mut rows: Vec<Vec<&str>> = ids.iter().map(|id| vec![id.as_str(), "0.5", "x"]).collect();
+        rows.push(vec!["oops", "inf", "007"]);
         db.insert(
             "foobar",
-            &vec!["id", "name"],
-            vec![vec!["42", "bar"], vec!["43", "baz"]],
+            &["id", "ratio", "name"],
+            &[CsvType::Integer, CsvType::Float, CsvType::String],
+            rows,
+        )
+        .unwrap();
+        let (count, sum): (i64, i64) = db
+            .connection
+            .query_row(
+                "select count(*), sum(id) from foobar where typeof(id) = 'integer' and typeof(ratio) = 'real'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((count, sum), (1000, 499500));
+        let last: (String, String, String) = db
+            .connection
+            .query_row(
+                "select id, ratio, name from foobar where rowid = 1001",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            last,
+            (
+                String::from("oops"),
+                String::from("inf"),
+                String::from("007")
+            )
+        );
+    }
+
+    #[test]
+    fn can_insert_more_than_1000_columns() {
+        let mut db = Db::open_in_memory().unwrap();
+        let fields: Vec<String> = (0..1500).map(|i| format!("c{}", i)).collect();
+        let fields: Vec<&str> = fields.iter().map(|f| f.as_str()).collect();
+        let columns: Vec<(&str, CsvType)> = fields.iter().map(|f| (*f, CsvType::Integer)).collect();
+        db.create_table("wide", &columns).unwrap();
+        db.insert(
+            "wide",
+            &fields,
+            &[CsvType::Integer; 1500],
+            vec![vec!["1"; 1500]; 50],
+        )
+        .unwrap();
+        let sum: i64 = db
+            .connection
+            .query_row("select sum(c0) + sum(c1499) from wide", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(sum, 100);
+    }
+
+    #[test]
+    fn can_set_pragmas() {
+        let db = Db::open(DbOptions {
+            pragmas: vec![(String::from("cache_size"), Value::Integer(-2000))],
+            ..DbOptions::default()
+        })
+        .unwrap();
+        let cache_size: i64 = db
+            .connection
+            .query_row("pragma cache_size", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cache_size, -2000);
+    }
+
+    #[test]
+    fn moves_to_a_temporary_file_over_its_memory_limit() {
+        let temp_dir = std::env::temp_dir().join(format!("qsv_db_test_{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let mut db = Db::open(DbOptions {
+            memory_limit: Some(1 << 10),
+            temp_dir: Some(temp_dir.clone()),
+            ..DbOptions::default()
+        })
+        .unwrap();
+        db.create_table(
+            "foobar",
+            &[("id", CsvType::Integer), ("name", CsvType::String)],
+        )
+        .unwrap();
+        assert!(db.file.is_none());
+        let ids: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        db.insert(
+            "foobar",
+            &["id", "name"],
+            &[CsvType::Integer, CsvType::String],
+            ids.iter().map(|id| vec![id.as_str(), "bar"]).collect(),
+        )
+        .unwrap();
+        assert!(db.file.is_some());
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 1);
+        // qsv's functions are registered on the new connection too
+        let (count, mean): (i64, f64) = db
+            .connection
+            .query_row("select count(*), mean(id) from foobar", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((count, mean), (1000, 499.5));
+        drop(db);
+        assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn moves_to_a_temporary_file_once_a_statement_takes_it_over_its_memory_limit() {
+        let mut db = Db::open(DbOptions {
+            memory_limit: Some(64 << 10),
+            ..DbOptions::default()
+        })
+        .unwrap();
+        db.execute(
+            "create table numbers as with recursive r(n) as (select 1 union all select n + 1 from r where n < 100) select n from r",
+            &vec![],
+        )
+        .unwrap();
+        assert!(db.file.is_none());
+        db.execute(
+            "create table more as with recursive r(n) as (select 1 union all select n + 1 from r where n < 100000) select n, printf('%08d', n) as padded from r",
+            &vec![],
+        )
+        .unwrap();
+        assert!(db.file.is_some());
+        let count: i64 = db
+            .connection
+            .query_row("select count(*) from more", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 100_000);
+    }
+
+    #[test]
+    fn returns_the_rows_found_before_being_interrupted() {
+        let mut db = Db::open_in_memory().unwrap();
+        let checks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = checks.clone();
+        db.interrupt_when(Arc::new(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 10
+        }));
+        let (_, rows) = db
+            .select_statement(
+                "with recursive r(n) as (select 1 union all select n + 1 from r) select n from r",
+                &vec![],
+            )
+            .unwrap();
+        assert!(!rows.is_empty());
+        assert_eq!(rows[0], vec![Value::Integer(1)]);
+        assert!(db
+            .execute(
+                "create table foo as with recursive r(n) as (select 1 union all select n + 1 from r) select n from r",
+                &vec![],
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn can_explain_a_query_plan() {
+        let mut db = Db::open_in_memory().unwrap();
+        db.create_table(
+            "foobar",
+            &[("id", CsvType::Integer), ("name", CsvType::String)],
+        )
+        .unwrap();
+        db.create_index("foobar", &["id"]).unwrap();
+        assert_eq!(
+            db.column_types("foobar").unwrap(),
+            vec![
+                (String::from("id"), String::from("integer")),
+                (String::from("name"), String::from("text"))
+            ]
         );
+        let plan = db
+            .query_plan(
+                "select name from foobar where id = :id",
+                &vec![(String::from(":id"), Value::Integer(1))],
+            )
+            .unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].parent, 0);
+        assert!(plan[0].detail.contains("USING INDEX qsv_foobar_id"));
+    }
+
+    #[test]
+    fn wont_run_a_query_with_an_unbound_parameter() {
+        let db = Db::open_in_memory().unwrap();
+        let parameters = vec![(String::from(":a"), Value::Integer(1))];
+        assert!(db.select_statement("select :a, :b", &parameters).is_err());
+        assert!(db.select_statement("select :a", &parameters).is_ok());
+    }
+
+    #[test]
+    fn can_insert() {
+        let mut db = Db::open_in_memory().unwrap();
+        db.create_table(
+            "foobar",
+            &[("id", CsvType::Integer), ("name", CsvType::String)],
+        )
+        .unwrap();
+        db.insert(
+            "foobar",
+            &["id", "name"],
+            &[CsvType::Integer, CsvType::String],
+            vec![vec!["42", "bar"], vec!["43", "baz"]],
+        )
+        .unwrap();
         let pair: (usize, String) = db
             .connection
             .query_row("select id, name from foobar where id = 42", [], |row| {
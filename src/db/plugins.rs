@@ -0,0 +1,301 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use log::debug;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, LoadExtensionGuard, Result};
+
+use crate::db::functions::add_udfs;
+use crate::db::utils::quote_identifier;
+
+///A function defined in SQL rather than compiled into qsv, such as one from the config file.
+///Its arguments are columns named after them, so `first || ' ' || last` is a function of `first`
+///and `last`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqlFunction {
+    pub name: String,
+    pub args: Vec<String>,
+    pub body: SqlFunctionBody,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlFunctionBody {
+    ///An expression of the arguments
+    Scalar(String),
+    ///An aggregate which keeps a value called `state` as it steps through rows
+    Aggregate {
+        ///The state before any rows are added
+        initial: String,
+        ///The next state, from the current `state` and a row's arguments
+        step: String,
+        ///The result, from the final `state`
+        result: String,
+    },
+}
+
+impl SqlFunction {
+    ///Checks the function and its arguments can be named in SQL without quoting, and that an
+    ///aggregate doesn't have an argument which would hide its state
+    pub fn new(name: &str, args: Vec<String>, body: SqlFunctionBody) -> Result<Self, String> {
+        let valid = |name: &str| {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if !valid(name) {
+            return Err(format!(
+                "Function names can only contain letters, digits and underscores: {}",
+                name
+            ));
+        }
+        if let Some(arg) = args.iter().find(|arg| !valid(arg)) {
+            return Err(format!(
+                "Arguments of {} can only contain letters, digits and underscores: {}",
+                name, arg
+            ));
+        }
+        let is_aggregate = matches!(body, SqlFunctionBody::Aggregate { .. });
+        if is_aggregate && args.iter().any(|arg| arg.eq_ignore_ascii_case("state")) {
+            return Err(format!(
+                "Aggregate {} can't have an argument called state",
+                name
+            ));
+        }
+        Ok(SqlFunction {
+            name: String::from(name),
+            args,
+            body,
+        })
+    }
+}
+
+///Loads SQLite extensions from shared libraries, which register their own functions, virtual
+///tables and collations
+pub(crate) fn load_extensions(connection: &Connection, extensions: &[PathBuf]) -> Result<()> {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+    let _guard = LoadExtensionGuard::new(connection)?;
+    for extension in extensions.iter() {
+        debug!("Loading extension {}", extension.display());
+        connection.load_extension(extension, None)?;
+    }
+    Ok(())
+}
+
+///Registers functions defined in SQL. Each is run on a connection of its own, which has qsv's
+///functions and the extensions, but not the other functions defined in SQL
+pub(crate) fn add_sql_functions(
+    connection: &Connection,
+    functions: &[SqlFunction],
+    extensions: &[PathBuf],
+) -> Result<()> {
+    for function in functions.iter() {
+        let evaluator = Connection::open_in_memory()?;
+        add_udfs(&evaluator)?;
+        load_extensions(&evaluator, extensions)?;
+        let arity = function.args.len() as i32;
+        match &function.body {
+            SqlFunctionBody::Scalar(sql) => {
+                let query = select(sql, &function.args, false);
+                evaluator.prepare(&query)?;
+                let evaluator = Mutex::new(evaluator);
+                connection.create_scalar_function(
+                    &function.name,
+                    arity,
+                    FunctionFlags::SQLITE_UTF8,
+                    move |ctx| evaluate(&evaluator, &query, arguments(ctx, None)),
+                )?;
+            }
+            SqlFunctionBody::Aggregate {
+                initial,
+                step,
+                result,
+            } => {
+                let aggregate = SqlAggregate {
+                    initial: select(initial, &[], false),
+                    step: select(step, &function.args, true),
+                    result: select(result, &[], true),
+                    evaluator: Mutex::new(evaluator),
+                };
+                aggregate.check()?;
+                connection.create_aggregate_function(
+                    &function.name,
+                    arity,
+                    FunctionFlags::SQLITE_UTF8,
+                    aggregate,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+///A SELECT of an expression, from a row of the arguments, which are bound in order after the
+///state if there is one
+fn select(expression: &str, args: &[String], with_state: bool) -> String {
+    let columns: Vec<String> = with_state
+        .then(|| String::from("state"))
+        .into_iter()
+        .chain(args.iter().cloned())
+        .enumerate()
+        .map(|(i, column)| format!("?{} AS {}", i + 1, quote_identifier(&column)))
+        .collect();
+    if columns.is_empty() {
+        format!("SELECT ({})", expression)
+    } else {
+        format!(
+            "SELECT ({}) FROM (SELECT {})",
+            expression,
+            columns.join(", ")
+        )
+    }
+}
+
+fn arguments(ctx: &Context, state: Option<&Value>) -> Vec<Value> {
+    state
+        .cloned()
+        .into_iter()
+        .chain((0..ctx.len()).map(|i| Value::from(ctx.get_raw(i))))
+        .collect()
+}
+
+fn evaluate(evaluator: &Mutex<Connection>, query: &str, arguments: Vec<Value>) -> Result<Value> {
+    let evaluator = evaluator.lock().expect("functions aren't run concurrently");
+    let mut statement = evaluator.prepare_cached(query)?;
+    statement.query_row(params_from_iter(arguments), |row| row.get(0))
+}
+
+struct SqlAggregate {
+    initial: String,
+    step: String,
+    result: String,
+    evaluator: Mutex<Connection>,
+}
+
+impl SqlAggregate {
+    fn check(&self) -> Result<()> {
+        let evaluator = self
+            .evaluator
+            .lock()
+            .expect("functions aren't run concurrently");
+        for query in [&self.initial, &self.step, &self.result].iter() {
+            evaluator.prepare(query)?;
+        }
+        Ok(())
+    }
+}
+
+impl Aggregate<Value, Value> for SqlAggregate {
+    fn init(&self, _: &mut Context<'_>) -> Result<Value> {
+        evaluate(&self.evaluator, &self.initial, vec![])
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, state: &mut Value) -> Result<()> {
+        *state = evaluate(&self.evaluator, &self.step, arguments(ctx, Some(state)))?;
+        Ok(())
+    }
+
+    ///Without any rows, the result is taken from the initial state
+    fn finalize(&self, _: &mut Context<'_>, state: Option<Value>) -> Result<Value> {
+        let state = match state {
+            Some(state) => state,
+            None => evaluate(&self.evaluator, &self.initial, vec![])?,
+        };
+        evaluate(&self.evaluator, &self.result, vec![state])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(functions: Vec<SqlFunction>) -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        add_sql_functions(&connection, &functions, &[]).unwrap();
+        connection
+    }
+
+    #[test]
+    fn it_runs_scalar_functions_defined_in_sql() {
+        let full_name = SqlFunction::new(
+            "full_name",
+            vec![String::from("first"), String::from("last")],
+            SqlFunctionBody::Scalar(String::from("first || ' ' || upper(last)")),
+        )
+        .unwrap();
+        let answer = SqlFunction::new(
+            "answer",
+            vec![],
+            SqlFunctionBody::Scalar(String::from("sqrt(1764)")),
+        )
+        .unwrap();
+        let connection = connection(vec![full_name, answer]);
+        let (name, answer): (String, f64) = connection
+            .query_row("select full_name('Ann', 'Lee'), answer()", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(name, "Ann LEE");
+        assert_eq!(answer, 42.0);
+    }
+
+    #[test]
+    fn it_runs_aggregates_defined_in_sql() {
+        let product = SqlFunction::new(
+            "product",
+            vec![String::from("x")],
+            SqlFunctionBody::Aggregate {
+                initial: String::from("1"),
+                step: String::from("state * coalesce(x, 1)"),
+                result: String::from("state"),
+            },
+        )
+        .unwrap();
+        let connection = connection(vec![product]);
+        let product: i64 = connection
+            .query_row(
+                "select product(column1) from (values (2), (null), (3), (7))",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(product, 42);
+        let empty: i64 = connection
+            .query_row(
+                "select product(column1) from (values (2)) where column1 > 2",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(empty, 1);
+    }
+
+    #[test]
+    fn it_rejects_invalid_functions() {
+        let scalar = |name: &str, arg: &str, sql: &str| {
+            SqlFunction::new(
+                name,
+                vec![String::from(arg)],
+                SqlFunctionBody::Scalar(String::from(sql)),
+            )
+        };
+        assert!(scalar("full name", "x", "x").is_err());
+        assert!(scalar("f", "x;", "x").is_err());
+        assert!(SqlFunction::new(
+            "f",
+            vec![String::from("State")],
+            SqlFunctionBody::Aggregate {
+                initial: String::from("0"),
+                step: String::from("state"),
+                result: String::from("state"),
+            },
+        )
+        .is_err());
+        let connection = Connection::open_in_memory().unwrap();
+        let unparseable = scalar("f", "x", "x +").unwrap();
+        assert!(add_sql_functions(&connection, &[unparseable], &[]).is_err());
+        let missing = vec![PathBuf::from("testdata/no_such_extension")];
+        assert!(load_extensions(&connection, &missing).is_err());
+    }
+}
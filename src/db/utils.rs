@@ -1,30 +1,50 @@
-use crate::csv::csv_data::{CsvData, CsvType};
+use std::collections::HashSet;
+
+use crate::csv::csv_data::CsvType;
 use crate::csv::inference::ColumnInference;
-use format_sql_query::{Column, Table};
-
-const INTEGER_STRING: &str = "integer";
-const TEXT_STRING: &str = "text";
-const FLOAT_STRING: &str = "real";
-pub fn to_table_parameters(csv_data: &CsvData, column_inference: &ColumnInference) -> Vec<String> {
-    let mut vec = Vec::with_capacity(csv_data.headers.len());
-    for header in csv_data.headers.iter() {
-        let column_type = column_inference.get_type(header.to_string()).unwrap();
-        let table_name = escape_table(header);
-        let string = match column_type {
-            CsvType::Integer => {
-                format!("{} {}", table_name, INTEGER_STRING)
-            }
-            CsvType::String => {
-                format!("{} {}", table_name, TEXT_STRING)
-            }
+use csv::StringRecord;
 
-            CsvType::Float => {
-                format!("{} {}", table_name, FLOAT_STRING)
-            }
-        };
-        vec.push(string);
+///The type a column is declared with. Types come from this fixed set, never from a file
+fn sql_type(column_type: CsvType) -> &'static str {
+    match column_type {
+        CsvType::Integer => "integer",
+        CsvType::Float => "real",
+        CsvType::String => "text",
     }
-    vec
+}
+
+///The columns of a table, in order, along with their types
+pub fn table_columns(column_inference: &ColumnInference) -> Vec<(&str, CsvType)> {
+    column_inference
+        .columns_to_types
+        .iter()
+        .map(|(column, column_type)| (column.as_str(), *column_type))
+        .collect()
+}
+
+///The column definitions of a CREATE TABLE statement, with every column name quoted
+/// ```
+/// use qsv::csv::csv_data::CsvType;
+/// use qsv::db::utils::column_definitions;
+/// assert_eq!(column_definitions(&[("id", CsvType::Integer)]), "\"id\" integer");
+/// ```
+pub fn column_definitions(columns: &[(&str, CsvType)]) -> String {
+    columns
+        .iter()
+        .map(|(column, column_type)| {
+            format!("{} {}", quote_identifier(column), sql_type(*column_type))
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+///A CREATE TABLE statement for a table and its columns, with every identifier quoted
+pub fn create_table_statement(table_name: &str, columns: &[(&str, CsvType)]) -> String {
+    format!(
+        "CREATE TABLE {} ({})",
+        quote_identifier(table_name),
+        column_definitions(columns)
+    )
 }
 
 /// repeat parameters a specific number of times for use in SQL interpolation
@@ -34,21 +54,295 @@ pub fn to_table_parameters(csv_data: &CsvData, column_inference: &ColumnInferenc
 /// ```
 pub fn repeat_vars(count: usize) -> String {
     assert_ne!(count, 0);
-    assert!(count <= 1000);
     let mut s = "?,".repeat(count);
     // Remove trailing comma
     s.pop();
     s
 }
 
+/// a row of parameters repeated for each row of a multi-row VALUES clause
+/// ```
+/// use qsv::db::utils::repeat_rows;
+/// assert_eq!(repeat_rows(2, 3), "(?,?),(?,?),(?,?)");
+/// ```
+pub fn repeat_rows(columns: usize, rows: usize) -> String {
+    assert_ne!(rows, 0);
+    let row = format!("({})", repeat_vars(columns));
+    let mut s = String::with_capacity((row.len() + 1) * rows);
+    for i in 0..rows {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&row);
+    }
+    s
+}
+
+///Quotes any name as an SQL identifier, so it can never end the identifier early whatever it
+///contains. Double quotes are doubled, and NUL characters, which would end the statement, are
+///replaced
+/// ```
+/// use qsv::db::utils::quote_identifier;
+/// assert_eq!(quote_identifier("a\"; drop table b"), "\"a\"\"; drop table b\"");
+/// ```
+pub fn quote_identifier(name: &str) -> String {
+    let mut quoted = String::with_capacity(name.len() + 2);
+    quoted.push('"');
+    for c in name.chars() {
+        match c {
+            '"' => quoted.push_str("\"\""),
+            '\0' => quoted.push(char::REPLACEMENT_CHARACTER),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 pub fn escape_fields(fields: &[&str]) -> Vec<String> {
-    fields
-        .iter()
-        .map(|&field| format!("{}", Column(field.to_string().as_str().into())))
-        .collect()
+    fields.iter().map(|field| quote_identifier(field)).collect()
 }
 pub fn escape_table(table_name: &str) -> String {
-    format!("{}", Table(table_name.to_string().as_str().into()))
+    quote_identifier(table_name)
+}
+
+///SQLite's keywords, which can't be used as identifiers without quoting them
+const SQLITE_KEYWORDS: &[&str] = &[
+    "abort",
+    "action",
+    "add",
+    "after",
+    "all",
+    "alter",
+    "always",
+    "analyze",
+    "and",
+    "as",
+    "asc",
+    "attach",
+    "autoincrement",
+    "before",
+    "begin",
+    "between",
+    "by",
+    "cascade",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "column",
+    "commit",
+    "conflict",
+    "constraint",
+    "create",
+    "cross",
+    "current",
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "database",
+    "default",
+    "deferrable",
+    "deferred",
+    "delete",
+    "desc",
+    "detach",
+    "distinct",
+    "do",
+    "drop",
+    "each",
+    "else",
+    "end",
+    "escape",
+    "except",
+    "exclude",
+    "exclusive",
+    "exists",
+    "explain",
+    "fail",
+    "filter",
+    "first",
+    "following",
+    "for",
+    "foreign",
+    "from",
+    "full",
+    "generated",
+    "glob",
+    "group",
+    "groups",
+    "having",
+    "if",
+    "ignore",
+    "immediate",
+    "in",
+    "index",
+    "indexed",
+    "initially",
+    "inner",
+    "insert",
+    "instead",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "key",
+    "last",
+    "left",
+    "like",
+    "limit",
+    "match",
+    "materialized",
+    "natural",
+    "no",
+    "not",
+    "nothing",
+    "notnull",
+    "null",
+    "nulls",
+    "of",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "others",
+    "outer",
+    "over",
+    "partition",
+    "plan",
+    "pragma",
+    "preceding",
+    "primary",
+    "query",
+    "raise",
+    "range",
+    "recursive",
+    "references",
+    "regexp",
+    "reindex",
+    "release",
+    "rename",
+    "replace",
+    "restrict",
+    "returning",
+    "right",
+    "rollback",
+    "row",
+    "rows",
+    "savepoint",
+    "select",
+    "set",
+    "table",
+    "temp",
+    "temporary",
+    "then",
+    "ties",
+    "to",
+    "transaction",
+    "trigger",
+    "unbounded",
+    "union",
+    "unique",
+    "update",
+    "using",
+    "vacuum",
+    "values",
+    "view",
+    "virtual",
+    "when",
+    "where",
+    "window",
+    "with",
+    "without",
+];
+
+///Whether a name is one of SQLite's keywords
+pub fn is_keyword(name: &str) -> bool {
+    SQLITE_KEYWORDS
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(name))
+}
+
+///Turns any name into an identifier which never needs quoting: letters, digits and underscores,
+///starting with a letter and never a keyword or one of SQLite's reserved `sqlite_` names. Other
+///characters become underscores, and names which would be invalid are given `prefix`
+/// ```
+/// use qsv::db::utils::sql_identifier;
+/// assert_eq!(sql_identifier("2026-10-01 (final)", "t"), "t_2026_10_01_final");
+/// ```
+pub fn sql_identifier(name: &str, prefix: &str) -> String {
+    let mut identifier = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            identifier.push(c);
+        } else if !identifier.is_empty() && !identifier.ends_with('_') {
+            identifier.push('_');
+        }
+    }
+    while identifier.ends_with('_') {
+        identifier.pop();
+    }
+    if identifier.is_empty() {
+        return prefix.to_string();
+    }
+    let reserved = identifier
+        .get(..7)
+        .is_some_and(|start| start.eq_ignore_ascii_case("sqlite_"));
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) || reserved {
+        identifier = format!("{}_{}", prefix, identifier);
+    }
+    if is_keyword(&identifier) {
+        identifier.push('_');
+    }
+    identifier
+}
+
+///Turns a column name into a lowercase snake_case identifier, splitting words on case changes
+/// ```
+/// use qsv::db::utils::snake_case;
+/// assert_eq!(snake_case("firstName"), "first_name");
+/// assert_eq!(snake_case("HTTP Status-Code"), "http_status_code");
+/// ```
+pub fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase());
+            if previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next_is_lower)
+            {
+                words.push('_');
+            }
+        }
+        words.push(c.to_ascii_lowercase());
+    }
+    sql_identifier(&words, "c")
+}
+
+///Normalizes every header to snake_case. Headers which end up empty are named after their
+///position, and headers which end up the same as an earlier one are numbered
+pub fn normalize_headers(headers: &StringRecord) -> StringRecord {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut normalized = StringRecord::new();
+    for (i, header) in headers.iter().enumerate() {
+        let mut name = snake_case(header);
+        if name == "c" && !header.chars().any(|c| c.is_ascii_alphanumeric()) {
+            name = format!("column_{}", i + 1);
+        }
+        let mut unique = name.clone();
+        let mut n = 2;
+        while seen.contains(&unique) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        seen.insert(unique.clone());
+        normalized.push_field(&unique);
+    }
+    normalized
 }
 
 #[cfg(test)]
@@ -69,9 +363,14 @@ mod tests {
         repeat_vars(0);
     }
     #[test]
-    #[should_panic]
-    fn it_fails_above_1000() {
-        repeat_vars(1001);
+    fn it_repeats_more_than_1000_variables() {
+        assert_eq!(repeat_vars(1001).matches('?').count(), 1001);
+    }
+
+    #[test]
+    fn it_repeats_rows_of_variables() {
+        assert_eq!(repeat_rows(1, 1), String::from("(?)"));
+        assert_eq!(repeat_rows(3, 2), String::from("(?,?,?),(?,?,?)"));
     }
 
     #[test]
@@ -83,6 +382,68 @@ mod tests {
         )
     }
 
+    #[test]
+    fn it_quotes_every_identifier() {
+        assert_eq!(quote_identifier("people"), "\"people\"");
+        assert_eq!(quote_identifier("a;b"), "\"a;b\"");
+        assert_eq!(quote_identifier("it's"), "\"it's\"");
+        assert_eq!(quote_identifier("back\\slash"), "\"back\\slash\"");
+        assert_eq!(quote_identifier("a\0b"), "\"a\u{fffd}b\"");
+        assert_eq!(quote_identifier(""), "\"\"");
+    }
+
+    #[test]
+    fn it_builds_create_table_statements_from_typed_columns() {
+        assert_eq!(
+            create_table_statement(
+                "x\"); drop table y; --",
+                &[("id", CsvType::Integer), ("a\" text, b", CsvType::Float)]
+            ),
+            "CREATE TABLE \"x\"\"); drop table y; --\" (\"id\" integer, \"a\"\" text, b\" real)"
+        );
+    }
+
+    #[test]
+    fn it_makes_identifiers_which_never_need_quoting() {
+        assert_eq!(sql_identifier("people", "t"), "people");
+        assert_eq!(sql_identifier("sales-2026 (copy)", "t"), "sales_2026_copy");
+        assert_eq!(sql_identifier("2026-10-01", "t"), "t_2026_10_01");
+        assert_eq!(sql_identifier("it's", "t"), "it_s");
+        assert_eq!(sql_identifier("café", "t"), "caf");
+        assert_eq!(sql_identifier("données", "t"), "donn_es");
+        assert_eq!(sql_identifier("日本", "t"), "t");
+        assert_eq!(sql_identifier("Order", "t"), "Order_");
+        assert_eq!(sql_identifier("sqlite_master", "t"), "t_sqlite_master");
+        assert_eq!(sql_identifier("__a__b__", "t"), "a_b");
+    }
+
+    #[test]
+    fn it_normalizes_headers_to_unique_snake_case() {
+        let headers = StringRecord::from(vec![
+            "First Name",
+            "lastName",
+            "ID",
+            "first_name",
+            "",
+            "2020",
+            "group",
+            "Price ($)",
+        ]);
+        assert_eq!(
+            normalize_headers(&headers),
+            StringRecord::from(vec![
+                "first_name",
+                "last_name",
+                "id",
+                "first_name_2",
+                "column_5",
+                "c_2020",
+                "group_",
+                "price",
+            ])
+        );
+    }
+
     #[test]
     fn it_escapes_fields() {
         assert_eq!(
@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+
+use csv::{Position, Reader, StringRecord, Trim};
+use rusqlite::types::Null;
+use rusqlite::vtab::{
+    read_only_module, Context, CreateVTab, IndexInfo, VTab, VTabConnection, VTabCursor, Values,
+};
+use rusqlite::{ffi, Connection, Error, Result};
+
+use crate::csv::csv_data::CsvType;
+use crate::csv::inference::ColumnInference;
+use crate::db::utils::{column_definitions, normalize_headers, table_columns};
+
+///The name of the module CSV virtual tables are created with
+pub const MODULE_NAME: &str = "qsv_csv";
+
+///Registers the module which reads CSV files in place rather than importing them:
+///```sql
+///CREATE VIRTUAL TABLE people USING qsv_csv(filename='people.csv', delimiter=',', trim=no, textonly=no, normalize_headers=no)
+///```
+///Column types are inferred when the table is created, in a single pass over the file
+pub fn load_module(connection: &Connection) -> Result<()> {
+    connection.create_module(MODULE_NAME, read_only_module::<CsvTable>(), None)
+}
+
+///Quotes a value for use as an argument of the module in a CREATE VIRTUAL TABLE statement
+pub fn quote_argument(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+///A CSV file exposed as a virtual table
+#[repr(C)]
+struct CsvTable {
+    ///Base class, which must come first
+    base: ffi::sqlite3_vtab,
+    filename: String,
+    delimiter: u8,
+    trim: bool,
+    types: Vec<CsvType>,
+    ///Where the first record after the headers starts
+    first_record: Position,
+}
+
+impl CsvTable {
+    fn reader(&self) -> Result<Reader<File>> {
+        let trim = if self.trim { Trim::All } else { Trim::None };
+        csv::ReaderBuilder::new()
+            .buffer_capacity(16 * (1 << 10))
+            .delimiter(self.delimiter)
+            .trim(trim)
+            .from_path(&self.filename)
+            .map_err(csv_error)
+    }
+}
+
+///Splits a `key=value` module argument, removing quotes around the value
+fn parameter(argument: &[u8]) -> Result<(&str, String)> {
+    let argument = std::str::from_utf8(argument)?.trim();
+    let mut split = argument.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(key), Some(value)) => {
+            let value = value.trim();
+            let value = match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                Some(quoted) => quoted.replace("''", "'"),
+                None => String::from(value),
+            };
+            Ok((key.trim(), value))
+        }
+        _ => Err(Error::ModuleError(format!(
+            "expected an argument of the form key=value: {}",
+            argument
+        ))),
+    }
+}
+
+fn boolean(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => Err(Error::ModuleError(format!(
+            "expected yes or no for {}: {}",
+            key, value
+        ))),
+    }
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::ModuleError(e.to_string())
+}
+
+unsafe impl<'vtab> VTab<'vtab> for CsvTable {
+    type Aux = ();
+    type Cursor = CsvCursor<'vtab>;
+
+    fn connect(
+        _: &mut VTabConnection,
+        _aux: Option<&()>,
+        args: &[&[u8]],
+    ) -> Result<(String, CsvTable)> {
+        let mut table = CsvTable {
+            base: ffi::sqlite3_vtab::default(),
+            filename: String::new(),
+            delimiter: b',',
+            trim: false,
+            types: vec![],
+            first_record: Position::new(),
+        };
+        let mut textonly = false;
+        let mut normalize = false;
+        // the first three arguments are the module, database and table names
+        for argument in args.iter().skip(3) {
+            let (key, value) = parameter(argument)?;
+            match key {
+                "filename" => table.filename = value,
+                "delimiter" if value.len() == 1 => table.delimiter = value.as_bytes()[0],
+                "trim" => table.trim = boolean(key, &value)?,
+                "textonly" => textonly = boolean(key, &value)?,
+                "normalize_headers" => normalize = boolean(key, &value)?,
+                _ => {
+                    return Err(Error::ModuleError(format!(
+                        "unrecognized argument {}={}",
+                        key, value
+                    )))
+                }
+            }
+        }
+        if table.filename.is_empty() {
+            return Err(Error::ModuleError(String::from("no CSV file specified")));
+        }
+        let mut reader = table.reader()?;
+        let mut headers = reader.headers().map_err(csv_error)?.clone();
+        if normalize {
+            headers = normalize_headers(&headers);
+        }
+        table.first_record = reader.position().clone();
+        let inference = if textonly {
+            ColumnInference::default_inference(&headers)
+        } else {
+            ColumnInference::from_records(&headers, reader.records()).map_err(csv_error)?
+        };
+        table.types = inference.columns_to_types.values().cloned().collect();
+        let schema = format!(
+            "CREATE TABLE x({})",
+            column_definitions(&table_columns(&inference))
+        );
+        Ok((schema, table))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        // every query is a full scan of the file
+        info.set_estimated_cost(1_000_000.);
+        Ok(())
+    }
+
+    fn open(&'vtab self) -> Result<CsvCursor<'vtab>> {
+        Ok(CsvCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            reader: self.reader()?,
+            record: StringRecord::new(),
+            row_number: 0,
+            eof: false,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl CreateVTab<'_> for CsvTable {}
+
+///A scan through the records of a CSV file, converting each value to its column's type
+#[repr(C)]
+struct CsvCursor<'vtab> {
+    ///Base class, which must come first
+    base: ffi::sqlite3_vtab_cursor,
+    reader: Reader<File>,
+    record: StringRecord,
+    row_number: i64,
+    eof: bool,
+    phantom: PhantomData<&'vtab CsvTable>,
+}
+
+impl CsvCursor<'_> {
+    fn table(&self) -> &CsvTable {
+        unsafe { &*(self.base.pVtab as *const CsvTable) }
+    }
+}
+
+unsafe impl VTabCursor for CsvCursor<'_> {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        _args: &Values<'_>,
+    ) -> Result<()> {
+        let first_record = self.table().first_record.clone();
+        self.reader.seek(first_record).map_err(csv_error)?;
+        self.row_number = 0;
+        self.next()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.eof = !self
+            .reader
+            .read_record(&mut self.record)
+            .map_err(csv_error)?;
+        self.row_number += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.eof
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> Result<()> {
+        let value = match self.record.get(i as usize) {
+            Some(value) => value,
+            None => return ctx.set_result(&Null),
+        };
+        // values which don't parse as their column's type are returned as text, as SQLite would
+        // store them in a regular table
+        match self.table().types.get(i as usize) {
+            Some(CsvType::Integer) => match value.parse::<i64>() {
+                Ok(integer) => ctx.set_result(&integer),
+                Err(_) => ctx.set_result(&value),
+            },
+            Some(CsvType::Float) => match value.parse::<f64>() {
+                Ok(float) => ctx.set_result(&float),
+                Err(_) => ctx.set_result(&value),
+            },
+            _ => ctx.set_result(&value),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.row_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        load_module(&connection).unwrap();
+        connection
+    }
+
+    #[test]
+    fn it_reads_typed_values_from_a_file() {
+        let connection = connection();
+        connection
+            .execute_batch(
+                "CREATE VIRTUAL TABLE people USING qsv_csv(filename='testdata/people.csv', delimiter=',', trim=no, textonly=no)",
+            )
+            .unwrap();
+        let (count, max_age, age_type): (i64, i64, String) = connection
+            .query_row(
+                "SELECT count(*), max(age), typeof(min(age)) FROM people",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((count, max_age, age_type.as_str()), (3, 62, "integer"));
+    }
+
+    #[test]
+    fn it_can_be_scanned_more_than_once() {
+        let connection = connection();
+        connection
+            .execute_batch(&format!(
+                "CREATE VIRTUAL TABLE people USING qsv_csv(filename={}, textonly=yes)",
+                quote_argument("testdata/people.csv")
+            ))
+            .unwrap();
+        let count: i64 = connection
+            .query_row(
+                "SELECT count(*) FROM people AS a JOIN people AS b ON a.name = b.name",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn it_can_normalize_headers() {
+        let connection = connection();
+        connection
+            .execute_batch(&format!(
+                "CREATE VIRTUAL TABLE signups USING qsv_csv(filename={}, normalize_headers=yes)",
+                quote_argument("testdata/Sign-ups (2026).csv")
+            ))
+            .unwrap();
+        let (name, score): (String, f64) = connection
+            .query_row(
+                "SELECT first_name, c_2026_score FROM signups WHERE age_years = 25",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((name.as_str(), score), ("Bob", 7.0));
+    }
+
+    #[test]
+    fn it_rejects_unknown_arguments() {
+        let connection = connection();
+        assert!(connection
+            .execute_batch(
+                "CREATE VIRTUAL TABLE foo USING qsv_csv(filename='testdata/people.csv', bar=baz)"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn it_unquotes_arguments() {
+        assert_eq!(
+            parameter(b" filename = 'it''s.csv' ").unwrap(),
+            ("filename", String::from("it's.csv"))
+        );
+        assert_eq!(quote_argument("it's.csv"), "'it''s.csv'");
+    }
+}
@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use sqlparser::parser::ParserError;
+
+use crate::qsv::Cancelled;
+
+pub type Result<T> = std::result::Result<T, QsvError>;
+
+///Everything which can go wrong while running qsv
+#[derive(Debug)]
+pub enum QsvError {
+    ///The SQL given could not be parsed
+    Parse(ParserError),
+    ///The SQL parsed, but contains a statement qsv can't run
+    UnsupportedStatement(String),
+    ///A file could not be read or loaded as CSV
+    FileLoad { filename: String, message: String },
+    ///A value could not be converted to the type it was inferred to be
+    TypeConversion(String),
+    ///SQLite failed to run a statement
+    Sqlite(rusqlite::Error),
+    ///Reading input or writing output failed
+    Io(std::io::Error),
+    ///Invalid arguments or shell commands
+    Usage(String),
+    ///The query was cancelled with Ctrl-C, or ran for longer than its timeout
+    Cancelled(Cancelled),
+}
+
+impl QsvError {
+    pub fn file_load<E: Display>(filename: &str, error: E) -> QsvError {
+        QsvError::FileLoad {
+            filename: String::from(filename),
+            message: error.to_string(),
+        }
+    }
+
+    ///The code the process exits with when this error occurs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            QsvError::Usage(_) => 2,
+            QsvError::Parse(_) => 3,
+            QsvError::UnsupportedStatement(_) => 4,
+            QsvError::FileLoad { .. } => 5,
+            QsvError::TypeConversion(_) => 6,
+            QsvError::Sqlite(_) => 7,
+            QsvError::Io(_) => 8,
+            QsvError::Cancelled(_) => 9,
+        }
+    }
+}
+
+impl Display for QsvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QsvError::Parse(e) => match e {
+                ParserError::TokenizerError(message) | ParserError::ParserError(message) => {
+                    write!(f, "Unable to parse SQL: {}", message)
+                }
+            },
+            QsvError::UnsupportedStatement(statement) => write!(
+                f,
+                "Unsupported statement, only SELECT, CREATE TABLE, CREATE VIEW and DROP can be run: {}",
+                statement
+            ),
+            QsvError::FileLoad { filename, message } => {
+                write!(f, "Unable to load file {}: {}", filename, message)
+            }
+            QsvError::TypeConversion(message) => write!(f, "Type conversion failed: {}", message),
+            QsvError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            QsvError::Io(e) => write!(f, "I/O error: {}", e),
+            QsvError::Usage(message) => f.write_str(message),
+            QsvError::Cancelled(cancelled) => cancelled.fmt(f),
+        }
+    }
+}
+
+impl Error for QsvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QsvError::Parse(e) => Some(e),
+            QsvError::Sqlite(e) => Some(e),
+            QsvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParserError> for QsvError {
+    fn from(e: ParserError) -> Self {
+        QsvError::Parse(e)
+    }
+}
+
+impl From<rusqlite::Error> for QsvError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::InvalidParameterName(name) => QsvError::Usage(format!(
+                "No value given for parameter {}, set it with --param {}=value",
+                name,
+                name.trim_start_matches(':')
+            )),
+            rusqlite::Error::InvalidParameterCount(bound, count) => QsvError::Usage(format!(
+                "Only {} of the {} parameters in the statement were given a value",
+                bound, count
+            )),
+            e => QsvError::Sqlite(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for QsvError {
+    fn from(e: std::io::Error) -> Self {
+        QsvError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_displays_clean_messages() {
+        let error = QsvError::file_load("foo.csv", "missing");
+        assert_eq!(error.to_string(), "Unable to load file foo.csv: missing");
+        let error = QsvError::from(ParserError::ParserError(String::from("Expected foo")));
+        assert_eq!(error.to_string(), "Unable to parse SQL: Expected foo");
+    }
+
+    #[test]
+    fn it_has_distinct_exit_codes() {
+        let errors = [
+            QsvError::Usage(String::new()),
+            QsvError::Parse(ParserError::ParserError(String::new())),
+            QsvError::UnsupportedStatement(String::new()),
+            QsvError::file_load("", ""),
+            QsvError::TypeConversion(String::new()),
+            QsvError::Sqlite(rusqlite::Error::InvalidQuery),
+            QsvError::Io(std::io::Error::from(std::io::ErrorKind::NotFound)),
+            QsvError::Cancelled(Cancelled::Interrupted),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|code| *code != 0));
+    }
+}
@@ -1,16 +1,23 @@
-use std::error::Error;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::{AppSettings, Clap};
+use rusqlite::types::Value;
 use simple_logger::SimpleLogger;
 
+use crate::db::{use_temp_dir, DbOptions};
+use crate::error::{QsvError, Result};
 use crate::qsv::{
-    execute_analysis, execute_query, execute_statistics, write_to_stdout,
-    write_to_stdout_with_header, Options,
+    catch_interrupts, execute_analysis, execute_explain, execute_query, execute_statistics,
+    parse_duration, parse_index, parse_parameter, parse_pragma, parse_size, parse_table, run_shell,
+    write_to_stdout, write_to_stdout_as_json, write_to_stdout_with_header, Config, Options,
+    OutputFormat,
 };
 
 mod csv;
 mod db;
+mod error;
 mod parser;
 mod qsv;
 
@@ -27,31 +34,156 @@ struct Opts {
 #[derive(Clap)]
 enum SubCommand {
     Query(Query),
+    Explain(Explain),
     Analyze(Analyze),
     FileType(FileType),
     Stats(Stats),
+    Shell(Shell),
 }
 
 #[derive(Clap)]
 struct Query {
-    query: String,
-    #[clap(short, long, default_value = ",")]
-    delimiter: char,
-    #[clap(long)]
+    #[clap(required_unless_present("file"))]
+    query: Option<String>,
+    /// Read the SQL script to run from a file instead, or from STDIN if the file is -
+    #[clap(short, long, conflicts_with("query"))]
+    file: Option<String>,
+    /// Bind a value to a named parameter in the query, e.g. `--param age=18` for `:age`
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_parameter))]
+    param: Vec<(String, Value)>,
+    /// Load whole files rather than only the columns and rows the query can use
+    #[clap(long, overrides_with("pushdown"))]
+    no_pushdown: bool,
+    /// Load only the columns and rows the query can use, even if the config file turns this off
+    #[clap(long, overrides_with("no_pushdown"))]
+    pushdown: bool,
+    #[clap(flatten)]
+    load: LoadArgs,
+    #[clap(flatten)]
+    run: RunArgs,
+}
+
+///Shows how a query runs: the statement SQLite runs once files are replaced by their tables, the
+///schema of each table and SQLite's query plan. SELECT statements aren't run
+#[derive(Clap)]
+struct Explain {
+    #[clap(required_unless_present("file"))]
+    query: Option<String>,
+    /// Read the SQL to explain from a file instead, or from STDIN if the file is -
+    #[clap(short, long, conflicts_with("query"))]
+    file: Option<String>,
+    /// Bind a value to a named parameter in the query, e.g. `--param age=18` for `:age`
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_parameter))]
+    param: Vec<(String, Value)>,
+    /// Load whole files rather than only the columns and rows the query can use
+    #[clap(long, overrides_with("pushdown"))]
+    no_pushdown: bool,
+    /// Load only the columns and rows the query can use, even if the config file turns this off
+    #[clap(long, overrides_with("no_pushdown"))]
+    pushdown: bool,
+    #[clap(flatten)]
+    load: LoadArgs,
+}
+
+///How files are loaded and the database is set up, for the subcommands which run queries
+#[derive(Clap)]
+struct LoadArgs {
+    /// The delimiter between fields, which defaults to ,
+    #[clap(short, long)]
+    delimiter: Option<char>,
+    #[clap(long, overrides_with("no_trim"))]
     trim: bool,
-    #[clap(long)]
+    /// Don't trim fields, even if the config file does
+    #[clap(long, overrides_with("trim"))]
+    no_trim: bool,
+    #[clap(long, overrides_with("no_textonly"))]
     textonly: bool,
-    #[clap(short, long("output-header"))]
+    /// Infer column types, even if the config file sets textonly
+    #[clap(long, overrides_with("textonly"))]
+    no_textonly: bool,
+    /// Add a _filename column to tables loaded from files, holding the file each row came from
+    #[clap(long, overrides_with("no_filename_column"))]
+    filename_column: bool,
+    /// Don't add a _filename column, even if the config file does
+    #[clap(long, overrides_with("filename_column"))]
+    no_filename_column: bool,
+    /// Read plain CSV files in place through a virtual table instead of importing them
+    #[clap(long, overrides_with("no_vtab"))]
+    vtab: bool,
+    /// Import files, even if the config file sets vtab
+    #[clap(long, overrides_with("vtab"))]
+    no_vtab: bool,
+    /// Don't index the columns compared by joins and WHERE clauses
+    #[clap(long, overrides_with("auto_index"))]
+    no_auto_index: bool,
+    /// Index the columns compared by joins and WHERE clauses, even if the config file turns this
+    /// off
+    #[clap(long, overrides_with("no_auto_index"))]
+    auto_index: bool,
+    /// Index columns of a table once it's loaded, e.g. `--index testdata/people.csv:name,age`
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_index))]
+    index: Vec<(String, Vec<String>)>,
+    /// Load files under a chosen table name, e.g. `--table people=testdata/people.csv`
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_table))]
+    table: Vec<(String, String)>,
+    /// Rename columns to snake_case identifiers which never need quoting, e.g. `First Name` to
+    /// `first_name`
+    #[clap(long, overrides_with("no_normalize_headers"))]
+    normalize_headers: bool,
+    /// Keep column names as they are, even if the config file sets normalize-headers
+    #[clap(long, overrides_with("normalize_headers"))]
+    no_normalize_headers: bool,
+    /// Set a pragma on the database, e.g. `--pragma cache_size=-64000`
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_pragma))]
+    pragma: Vec<(String, Value)>,
+    /// Move the database to a temporary file once loading would take it over this size, e.g. `4GB`
+    #[clap(long, parse(try_from_str = parse_size))]
+    memory_limit: Option<u64>,
+    /// Directory for temporary files, instead of the system's
+    #[clap(long)]
+    temp_dir: Option<PathBuf>,
+    /// Load a SQLite extension from a shared library, adding the functions it defines
+    #[clap(long, number_of_values = 1)]
+    load_extension: Vec<PathBuf>,
+    /// Use the extensions and functions of the project's .qsv.toml, which can run any code
+    #[clap(long)]
+    allow_project_extensions: bool,
+}
+
+///How queries run and their results are written, for the subcommands which write results
+#[derive(Clap)]
+struct RunArgs {
+    /// Write the original names of columns renamed by --normalize-headers in the output header
+    #[clap(long)]
+    original_headers: bool,
+    /// Cancel a query which runs for longer than this, e.g. `30s`, `500ms` or `5m`
+    #[clap(long, parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+    #[clap(short, long("output-header"), overrides_with("no_output_header"))]
     outputheader: bool,
+    /// Don't write the header, even if the config file sets output-header
+    #[clap(long, overrides_with("outputheader"))]
+    no_output_header: bool,
+    /// Write results as csv or json, which defaults to csv
+    #[clap(long)]
+    format: Option<OutputFormat>,
 }
 
 #[derive(Clap)]
 struct Analyze {
-    query: String,
-    #[clap(short, long, default_value = ",")]
-    delimiter: char,
-    #[clap(long)]
+    #[clap(required_unless_present("file"))]
+    query: Option<String>,
+    /// Read the SQL to analyze from a file instead, or from STDIN if the file is -
+    #[clap(short, long, conflicts_with("query"))]
+    file: Option<String>,
+    /// The delimiter between fields, which defaults to ,
+    #[clap(short, long)]
+    delimiter: Option<char>,
+    #[clap(long, overrides_with("no_trim"))]
     trim: bool,
+    /// Don't trim fields, even if the config file does
+    #[clap(long, overrides_with("trim"))]
+    no_trim: bool,
 }
 #[derive(Clap)]
 struct FileType {
@@ -61,42 +193,198 @@ struct FileType {
 #[derive(Clap)]
 struct Stats {
     filename: String,
-    #[clap(short, long, default_value = ",")]
-    delimiter: char,
-    #[clap(long)]
+    /// The delimiter between fields, which defaults to ,
+    #[clap(short, long)]
+    delimiter: Option<char>,
+    #[clap(long, overrides_with("no_trim"))]
     trim: bool,
-    #[clap(long)]
+    /// Don't trim fields, even if the config file does
+    #[clap(long, overrides_with("trim"))]
+    no_trim: bool,
+    #[clap(long, overrides_with("no_textonly"))]
     textonly: bool,
+    /// Infer column types, even if the config file sets textonly
+    #[clap(long, overrides_with("textonly"))]
+    no_textonly: bool,
+}
+#[derive(Clap)]
+struct Shell {
+    #[clap(flatten)]
+    load: LoadArgs,
+    #[clap(flatten)]
+    run: RunArgs,
+}
+///Gets the SQL to run, either given directly or from a file. A query or file of `-` reads
+///the SQL from STDIN
+fn read_query(query: Option<String>, file: Option<String>) -> Result<String> {
+    match (query, file) {
+        (Some(query), _) if query == "-" => read_stdin(),
+        (Some(query), _) => Ok(query),
+        (None, Some(file)) if file == "-" => read_stdin(),
+        (None, Some(file)) => {
+            std::fs::read_to_string(&file).map_err(|e| QsvError::file_load(&file, e))
+        }
+        (None, None) => Err(QsvError::Usage(String::from(
+            "Either a query or a file to read it from must be given",
+        ))),
+    }
+}
+
+fn read_stdin() -> Result<String> {
+    let mut query = String::new();
+    std::io::stdin().read_to_string(&mut query)?;
+    Ok(query)
 }
-fn main() -> Result<(), Box<dyn Error>> {
-    SimpleLogger::from_env().init()?;
+
+fn main() {
+    SimpleLogger::from_env()
+        .init()
+        .expect("logger should only be initialized once");
     let opts: Opts = Opts::parse();
+    if let Err(e) = run(opts) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+///A flag or its --no- form on the command line wins over the config, whichever came last
+fn toggle(on: bool, off: bool, configured: Option<bool>, default: bool) -> bool {
+    on || (!off && configured.unwrap_or(default))
+}
+
+fn flag(on: bool, off: bool, configured: Option<bool>) -> bool {
+    toggle(on, off, configured, false)
+}
+
+///Options for reading a single file, for the subcommands which don't run queries
+fn file_options(delimiter: char, trim: bool, textonly: bool) -> Options {
+    Options {
+        delimiter,
+        trim,
+        textonly,
+        filename_column: false,
+        pushdown: false,
+        vtab: false,
+        auto_index: false,
+        indexes: vec![],
+        tables: vec![],
+        normalize_headers: false,
+        original_headers: false,
+        database: DbOptions::default(),
+        timeout: None,
+    }
+}
+
+///Options for loading files and setting up the database, with files loaded in full and queries
+///left to run for as long as they take. SQLite's temporary files go in the temp dir from here on,
+///so this is called before any threads are started
+fn options(load: LoadArgs, config: &Config) -> Result<Options> {
+    let options = Options {
+        delimiter: load.delimiter.or(config.delimiter).unwrap_or(','),
+        trim: flag(load.trim, load.no_trim, config.trim),
+        textonly: flag(load.textonly, load.no_textonly, config.textonly),
+        filename_column: flag(
+            load.filename_column,
+            load.no_filename_column,
+            config.filename_column,
+        ),
+        pushdown: false,
+        vtab: flag(load.vtab, load.no_vtab, config.vtab),
+        auto_index: toggle(load.auto_index, load.no_auto_index, config.auto_index, true),
+        indexes: load.index,
+        tables: config.tables(load.table),
+        normalize_headers: flag(
+            load.normalize_headers,
+            load.no_normalize_headers,
+            config.normalize_headers,
+        ),
+        original_headers: false,
+        database: config.database(
+            load.pragma,
+            load.memory_limit,
+            load.temp_dir,
+            load.load_extension,
+            load.allow_project_extensions,
+        )?,
+        timeout: None,
+    };
+    if let Some(ref temp_dir) = options.database.temp_dir {
+        use_temp_dir(temp_dir);
+    }
+    Ok(options)
+}
+
+impl RunArgs {
+    ///Sets the options for running queries on top of those for loading files
+    fn options(&self, options: Options, config: &Config) -> Options {
+        Options {
+            original_headers: self.original_headers,
+            timeout: self.timeout.or(config.timeout),
+            ..options
+        }
+    }
+
+    fn output_header(&self, config: &Config) -> bool {
+        flag(
+            self.outputheader,
+            self.no_output_header,
+            config.output_header,
+        )
+    }
+
+    fn format(&self, config: &Config) -> OutputFormat {
+        self.format.or(config.format).unwrap_or(OutputFormat::Csv)
+    }
+}
+
+fn run(opts: Opts) -> Result<()> {
+    let config = Config::load()?;
+    let delimiter = |delimiter: Option<char>| delimiter.or(config.delimiter).unwrap_or(',');
     match opts.subcommand {
         SubCommand::Query(subcmd) => {
-            let delimiter = subcmd.delimiter;
-            let trim = subcmd.trim;
-            let textonly = subcmd.textonly;
             let options = Options {
-                delimiter,
-                trim,
-                textonly,
+                pushdown: toggle(subcmd.pushdown, subcmd.no_pushdown, config.pushdown, true),
+                ..subcmd.run.options(options(subcmd.load, &config)?, &config)
             };
-            let (header, results) = execute_query(subcmd.query.as_str(), &options)?;
-            if subcmd.outputheader {
-                write_to_stdout_with_header(results, &header)?;
-            } else {
-                write_to_stdout(results)?;
-            }
+            let query = read_query(subcmd.query, subcmd.file)?;
+            let output_header = subcmd.run.output_header(&config);
+            let format = subcmd.run.format(&config);
+            catch_interrupts();
+            execute_query(
+                query.as_str(),
+                &options,
+                subcmd.param,
+                &mut |header, results, json| match format {
+                    OutputFormat::Json => write_to_stdout_as_json(results, &header, json),
+                    OutputFormat::Csv if output_header => {
+                        write_to_stdout_with_header(results, &header)
+                    }
+                    OutputFormat::Csv => write_to_stdout(results),
+                },
+            )?;
         }
-        SubCommand::Analyze(subcmd) => {
-            let delimiter = subcmd.delimiter;
-            let trim = subcmd.trim;
+        SubCommand::Explain(subcmd) => {
             let options = Options {
-                delimiter,
-                trim,
-                textonly: false,
+                pushdown: toggle(subcmd.pushdown, subcmd.no_pushdown, config.pushdown, true),
+                ..options(subcmd.load, &config)?
             };
-            let results = execute_analysis(subcmd.query.as_str(), &options)?;
+            let query = read_query(subcmd.query, subcmd.file)?;
+            let explanations = execute_explain(query.as_str(), &options, subcmd.param)?;
+            for (i, explanation) in explanations.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print!("{}", explanation);
+            }
+        }
+        SubCommand::Analyze(subcmd) => {
+            let options = file_options(
+                delimiter(subcmd.delimiter),
+                flag(subcmd.trim, subcmd.no_trim, config.trim),
+                false,
+            );
+            let query = read_query(subcmd.query, subcmd.file)?;
+            let results = execute_analysis(query.as_str(), &options)?;
             println!("{}", results);
         }
 
@@ -107,17 +395,25 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
         SubCommand::Stats(subcmd) => {
             let filename = subcmd.filename;
-            let options = Options {
-                delimiter: subcmd.delimiter,
-                trim: subcmd.trim,
-                textonly: subcmd.textonly,
-            };
+            let options = file_options(
+                delimiter(subcmd.delimiter),
+                flag(subcmd.trim, subcmd.no_trim, config.trim),
+                flag(subcmd.textonly, subcmd.no_textonly, config.textonly),
+            );
             let stats = execute_statistics(&filename, &options)?;
             for (i, stat) in stats.iter().enumerate() {
                 println!("{}. '{}'", i, stat.column);
                 println!("{}", stat);
             }
         }
+        SubCommand::Shell(subcmd) => {
+            // tables are kept for later queries, so they're always loaded in full
+            let options = subcmd.run.options(options(subcmd.load, &config)?, &config);
+            let output_header = subcmd.run.output_header(&config);
+            let format = subcmd.run.format(&config);
+            catch_interrupts();
+            run_shell(&options, format, output_header)?;
+        }
     }
     Ok(())
 }
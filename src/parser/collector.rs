@@ -1,4 +1,7 @@
-use sqlparser::ast::{Query, Select, SetExpr, Statement, TableFactor, TableWithJoins};
+use sqlparser::ast::{ObjectName, Statement};
+
+use crate::error::Result;
+use crate::parser::visitor::{walk_statement, Visitor};
 
 /// Collection of table identifiers parsed from SQL
 pub struct Collector {
@@ -16,91 +19,16 @@ impl Collector {
         Self { table_identifiers }
     }
     /// Collect all the table identifiers in a statement
-    pub fn collect(&mut self, ast: &Statement) {
-        match ast {
-            Statement::Query(boxed) => self.recurse_query(boxed),
-
-            _ => {
-                panic!("unrecognized")
-            }
-        }
+    pub fn collect(&mut self, ast: &Statement) -> Result<()> {
+        let mut statement = ast.clone();
+        walk_statement(self, &mut statement)
     }
+}
 
-    fn recurse_query(&mut self, boxed: &Query) {
-        {
-            if let Some(with) = &boxed.with {
-                for cte in with.cte_tables.iter() {
-                    for ident in cte.from.iter() {
-                        self.table_identifiers.push(ident.value.to_string());
-                    }
-                    let query = &cte.query;
-                    self.recurse_query(query);
-                }
-            }
-            match &boxed.body {
-                SetExpr::Select(ref boxed) => {
-                    for from in boxed.from.iter() {
-                        self.recurse_table_with_joins(from);
-                    }
-                }
-                SetExpr::Query(boxed_query) => {
-                    self.recurse_query(boxed_query);
-                }
-                SetExpr::SetOperation { left, right, .. } => {
-                    match left.as_ref() {
-                        SetExpr::Select(select) => {
-                            self.recurse_select(select);
-                        }
-                        SetExpr::Query(query) => {
-                            self.recurse_query(query);
-                        }
-                        _ => (),
-                    }
-
-                    match right.as_ref() {
-                        SetExpr::Select(select) => {
-                            self.recurse_select(select);
-                        }
-                        SetExpr::Query(query) => {
-                            self.recurse_query(query);
-                        }
-                        _ => (),
-                    }
-                }
-                SetExpr::Values(_) => {}
-                SetExpr::Insert(_) => {}
-            }
-        }
-    }
-    fn recurse_select(&mut self, select: &Select) {
-        for from in select.from.iter() {
-            self.recurse_table_with_joins(from);
-        }
-    }
-    fn recurse_table_with_joins(&mut self, from: &TableWithJoins) {
-        match &from.relation {
-            TableFactor::Table { name, .. } => {
-                for ident in name.0.iter() {
-                    self.table_identifiers.push(ident.value.to_string());
-                }
-            }
-            TableFactor::Derived { subquery, .. } => self.recurse_query(&subquery),
-            TableFactor::TableFunction { .. } => {}
-            TableFactor::NestedJoin(_) => {
-                println!("nested join")
-            }
-        }
-        for join in from.joins.iter() {
-            match &join.relation {
-                TableFactor::Table { name, .. } => {
-                    for ident in name.0.iter() {
-                        self.table_identifiers.push(ident.value.to_string())
-                    }
-                }
-                TableFactor::Derived { .. } => {}
-                TableFactor::TableFunction { .. } => {}
-                TableFactor::NestedJoin(_) => {}
-            }
+impl Visitor for Collector {
+    fn visit_table_name(&mut self, name: &mut ObjectName) {
+        for ident in name.0.iter() {
+            self.table_identifiers.push(ident.value.to_string());
         }
     }
 }
@@ -108,6 +36,7 @@ impl Collector {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::QsvError;
     use crate::parser::CsvDialect;
     use sqlparser::parser::Parser;
 
@@ -116,8 +45,8 @@ mod tests {
         let dialect = CsvDialect;
         let mut collector = Collector::new();
         let sql = "select * from (select * from ./foo.csv)";
-        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        collector.collect(&mut ast[0]);
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
         assert_eq!(collector.table_identifiers, vec!(String::from("./foo.csv")));
     }
 
@@ -126,8 +55,8 @@ mod tests {
         let dialect = CsvDialect;
         let mut collector = Collector::new();
         let sql = "select * from (select * from (select * from ./foo.csv))";
-        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        collector.collect(&mut ast[0]);
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
         assert_eq!(collector.table_identifiers, vec!(String::from("./foo.csv")));
     }
 
@@ -136,8 +65,8 @@ mod tests {
         let dialect = CsvDialect;
         let mut collector = Collector::new();
         let sql = " with some_cte (age) as (select distinct(age) from testdata/people.csv) select * from testdata/occupations.csv occupation INNER JOIN foo on (occupation.minimum_age = foo.age)";
-        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        collector.collect(&mut ast[0]);
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
         assert_eq!(
             collector.table_identifiers,
             vec!(
@@ -153,8 +82,25 @@ mod tests {
         let dialect = CsvDialect;
         let mut collector = Collector::new();
         let sql = "select * from testdata/people.csv union select * from testdata/occupations.csv";
-        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        collector.collect(&mut ast[0]);
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
+        assert_eq!(
+            collector.table_identifiers,
+            vec!(
+                String::from("testdata/people.csv"),
+                String::from("testdata/occupations.csv")
+            )
+        );
+    }
+
+    #[test]
+    fn it_collects_filenames_from_create_table_and_view_statements() {
+        let dialect = CsvDialect;
+        let mut collector = Collector::new();
+        let sql = "create temp table adults as select * from testdata/people.csv where age > 17; create view names as select name from testdata/occupations.csv";
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
+        collector.collect(&ast[1]).unwrap();
         assert_eq!(
             collector.table_identifiers,
             vec!(
@@ -163,4 +109,67 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn it_returns_an_error_for_unsupported_statements() {
+        let dialect = CsvDialect;
+        let mut collector = Collector::new();
+        let sql = "insert into foo select * from testdata/people.csv";
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        assert!(matches!(
+            collector.collect(&ast[0]),
+            Err(QsvError::UnsupportedStatement(_))
+        ));
+    }
+
+    #[test]
+    fn it_collects_filenames_from_subqueries_in_expressions() {
+        let dialect = CsvDialect;
+        let mut collector = Collector::new();
+        let sql = "select (select max(age) from ./max.csv) from ./a.csv where id in (select id from ./in.csv) and exists (select 1 from ./exists.csv) order by (select 1 from ./order.csv)";
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
+        assert_eq!(
+            collector.table_identifiers,
+            vec!(
+                String::from("./max.csv"),
+                String::from("./a.csv"),
+                String::from("./in.csv"),
+                String::from("./exists.csv"),
+                String::from("./order.csv"),
+            )
+        );
+    }
+
+    #[test]
+    fn it_collects_filenames_from_nested_joins() {
+        let dialect = CsvDialect;
+        let mut collector = Collector::new();
+        let sql = "select * from ./a.csv a join (./b.csv b join ./c.csv c on b.id = c.id) on a.id = b.id left join ./d.csv d on d.id = (select min(id) from ./e.csv)";
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
+        assert_eq!(
+            collector.table_identifiers,
+            vec!(
+                String::from("./a.csv"),
+                String::from("./b.csv"),
+                String::from("./c.csv"),
+                String::from("./d.csv"),
+                String::from("./e.csv"),
+            )
+        );
+    }
+
+    #[test]
+    fn it_collects_filenames_from_table_function_arguments() {
+        let dialect = CsvDialect;
+        let mut collector = Collector::new();
+        let sql = "select * from json_each((select json_group_array(name) from ./a.csv))";
+        let ast = Parser::parse_sql(&dialect, sql).unwrap();
+        collector.collect(&ast[0]).unwrap();
+        assert_eq!(
+            collector.table_identifiers,
+            vec!(String::from("json_each"), String::from("./a.csv"))
+        );
+    }
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use sqlparser::ast::{
+    BinaryOperator, Expr, Ident, JoinConstraint, JoinOperator, ObjectName, Select, Statement,
+    TableFactor, TableWithJoins, UnaryOperator,
+};
+
+use crate::parser::visitor::{walk_statement, Visitor};
+
+///A table read by a SELECT, along with the alias it's referred to by
+struct ScopedTable {
+    identifier: String,
+    alias: Option<String>,
+}
+
+///The tables a SELECT reads, and the conditions which join and filter them
+#[derive(Default)]
+struct Scope<'a> {
+    tables: Vec<ScopedTable>,
+    conditions: Vec<&'a Expr>,
+    using: Vec<&'a Ident>,
+}
+
+impl<'a> Scope<'a> {
+    fn add_table_with_joins(&mut self, table: &'a TableWithJoins) {
+        self.add_table_factor(&table.relation);
+        for join in table.joins.iter() {
+            self.add_table_factor(&join.relation);
+            match join.join_operator {
+                JoinOperator::Inner(ref constraint)
+                | JoinOperator::LeftOuter(ref constraint)
+                | JoinOperator::RightOuter(ref constraint)
+                | JoinOperator::FullOuter(ref constraint) => match constraint {
+                    JoinConstraint::On(expr) => self.conditions.push(expr),
+                    JoinConstraint::Using(idents) => self.using.extend(idents.iter()),
+                    JoinConstraint::Natural | JoinConstraint::None => {}
+                },
+                JoinOperator::CrossJoin | JoinOperator::CrossApply | JoinOperator::OuterApply => {}
+            }
+        }
+    }
+
+    fn add_table_factor(&mut self, relation: &'a TableFactor) {
+        match relation {
+            TableFactor::Table { name, alias, .. } if name.0.len() == 1 => {
+                self.tables.push(ScopedTable {
+                    identifier: name.0[0].value.clone(),
+                    alias: alias.as_ref().map(|alias| alias.name.value.clone()),
+                })
+            }
+            TableFactor::NestedJoin(nested) => self.add_table_with_joins(nested),
+            _ => {}
+        }
+    }
+
+    ///The identifiers of the tables a column reference could belong to. Unqualified columns could
+    ///belong to any of them, since the tables' columns aren't known until they're loaded
+    fn tables_for(&self, qualifier: Option<&str>) -> Vec<String> {
+        self.tables
+            .iter()
+            .filter(|table| match (qualifier, &table.alias) {
+                (None, _) => true,
+                (Some(qualifier), Some(alias)) => qualifier.eq_ignore_ascii_case(alias),
+                (Some(qualifier), None) => qualifier == table.identifier,
+            })
+            .map(|table| table.identifier.clone())
+            .collect()
+    }
+}
+
+///Collects the columns which joins and WHERE clauses compare, since an index on them lets SQLite
+///look rows up rather than scanning the whole table
+struct IndexCollector {
+    candidates: HashMap<String, Vec<String>>,
+}
+
+impl Visitor for IndexCollector {
+    fn visit_table_name(&mut self, _name: &mut ObjectName) {}
+
+    fn visit_select(&mut self, select: &mut Select) {
+        let mut scope = Scope::default();
+        for from in select.from.iter() {
+            scope.add_table_with_joins(from);
+        }
+        if let Some(ref selection) = select.selection {
+            scope.conditions.push(selection);
+        }
+        for ident in scope.using.iter() {
+            for table in scope.tables.iter() {
+                self.add(&table.identifier, &ident.value);
+            }
+        }
+        for condition in scope.conditions.iter() {
+            self.collect(condition, &scope);
+        }
+    }
+}
+
+impl IndexCollector {
+    ///Finds the columns compared by a condition. Subqueries are left to their own SELECT
+    fn collect(&mut self, expr: &Expr, scope: &Scope) {
+        match expr {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And | BinaryOperator::Or,
+                right,
+            } => {
+                self.collect(left, scope);
+                self.collect(right, scope);
+            }
+            Expr::BinaryOp {
+                left,
+                op:
+                    BinaryOperator::Eq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq,
+                right,
+            } => {
+                self.add_column(left, scope);
+                self.add_column(right, scope);
+            }
+            Expr::Nested(expr)
+            | Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr,
+            } => self.collect(expr, scope),
+            Expr::InList { expr, .. }
+            | Expr::InSubquery { expr, .. }
+            | Expr::Between { expr, .. } => self.add_column(expr, scope),
+            _ => {}
+        }
+    }
+
+    fn add_column(&mut self, expr: &Expr, scope: &Scope) {
+        let (qualifier, column) = match expr {
+            // our dialect allows `.` within identifiers, so `t.a` is usually a single identifier
+            Expr::Identifier(ident) if ident.quote_style.is_none() && ident.value.contains('.') => {
+                let mut split = ident.value.splitn(2, '.');
+                match (split.next(), split.next()) {
+                    (Some(qualifier), Some(column)) => (Some(qualifier), column),
+                    _ => return,
+                }
+            }
+            Expr::Identifier(ident) if ident.quote_style != Some('\'') => {
+                (None, ident.value.as_str())
+            }
+            Expr::CompoundIdentifier(idents) if idents.len() == 2 => {
+                (Some(idents[0].value.as_str()), idents[1].value.as_str())
+            }
+            _ => return,
+        };
+        for identifier in scope.tables_for(qualifier).iter() {
+            self.add(identifier, column);
+        }
+    }
+
+    fn add(&mut self, identifier: &str, column: &str) {
+        let columns = self.candidates.entry(String::from(identifier)).or_default();
+        if !columns.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+            columns.push(String::from(column));
+        }
+    }
+}
+
+///The columns of each table which a statement's joins and WHERE clauses compare, keyed by the
+///table's identifier. An unqualified column in a SELECT reading several tables is listed under
+///each of them, so callers should skip the columns a table doesn't have
+pub fn index_candidates(statement: &Statement) -> HashMap<String, Vec<String>> {
+    let mut collector = IndexCollector {
+        candidates: HashMap::new(),
+    };
+    let mut statement = statement.clone();
+    match walk_statement(&mut collector, &mut statement) {
+        Ok(()) => collector.candidates,
+        Err(_) => HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn candidates(sql: &str) -> Vec<(String, Vec<String>)> {
+        let ast = Parser::parse_sql(sql).unwrap();
+        let mut candidates: Vec<(String, Vec<String>)> =
+            index_candidates(&ast[0]).into_iter().collect();
+        candidates.sort();
+        candidates
+    }
+
+    #[test]
+    fn it_finds_columns_compared_by_joins_and_where_clauses() {
+        assert_eq!(
+            candidates(
+                "select a.name from a.csv a join b.csv as b on a.id = b.a_id where b.size > 10 and upper(a.name) = 'X'"
+            ),
+            vec![
+                (String::from("a.csv"), vec![String::from("id")]),
+                (
+                    String::from("b.csv"),
+                    vec![String::from("a_id"), String::from("size")]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_lists_unqualified_and_using_columns_under_every_table() {
+        assert_eq!(
+            candidates("select * from a.csv join b.csv using (id) where kind in ('x', 'y')"),
+            vec![
+                (
+                    String::from("a.csv"),
+                    vec![String::from("id"), String::from("kind")]
+                ),
+                (
+                    String::from("b.csv"),
+                    vec![String::from("id"), String::from("kind")]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_finds_columns_in_subqueries() {
+        assert_eq!(
+            candidates(
+                "select * from a.csv where id in (select a_id from b.csv where size between 1 and 2)"
+            ),
+            vec![
+                (String::from("a.csv"), vec![String::from("id")]),
+                (String::from("b.csv"), vec![String::from("size")]),
+            ]
+        );
+    }
+}
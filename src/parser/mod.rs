@@ -1,15 +1,44 @@
 use sqlparser::ast::Statement;
 use sqlparser::dialect::Dialect;
 use sqlparser::parser::{Parser as SqlParser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::parser::operators::replace_json_operators;
 
 pub mod collector;
+pub mod indexes;
+pub mod operators;
+pub mod parameters;
+pub mod predicates;
+pub mod projection;
 pub mod rewriter;
+pub mod visitor;
 
 pub struct Parser {}
 impl Parser {
-    /// Parse SQL using our CSVDialect
+    /// Parse SQL using our CSVDialect, along with SQLite's JSON operators
     pub fn parse_sql(str: &str) -> Result<Vec<Statement>, ParserError> {
-        SqlParser::parse_sql(&CsvDialect, str)
+        let tokens = replace_json_operators(Tokenizer::new(&CsvDialect, str).tokenize()?)?;
+        let mut parser = SqlParser::new(tokens, &CsvDialect);
+        let mut statements = Vec::new();
+        let mut expecting_statement_delimiter = false;
+        loop {
+            while parser.consume_token(&Token::SemiColon) {
+                expecting_statement_delimiter = false;
+            }
+            if parser.peek_token() == Token::EOF {
+                break;
+            }
+            if expecting_statement_delimiter {
+                return Err(ParserError::ParserError(format!(
+                    "Expected end of statement, found: {}",
+                    parser.peek_token()
+                )));
+            }
+            statements.push(parser.parse_statement()?);
+            expecting_statement_delimiter = true;
+        }
+        Ok(statements)
     }
 }
 
@@ -22,25 +51,30 @@ impl Dialect for CsvDialect {
         ch == '`' || ch == '"' || ch == '['
     }
 
-    #[allow(clippy::nonminimal_bool)]
     fn is_identifier_start(&self, ch: char) -> bool {
-        // See https://www.sqlite.org/draft/tokenreq.html
-        ('a'..='z').contains(&ch)
-            || ('A'..='Z').contains(&ch)
-            || ch == '_'
-            || ch == '$'
-            || ('\u{007f}'..='\u{ffff}').contains(&ch)
-            || ch == '_'
-            || ch == '_'
-            || ch == '.'
-            || ch == '/'
+        // `:` starts named parameters such as `:name`, but can't appear within an identifier
+        ch == ':' || is_identifier_character(ch)
     }
 
     fn is_identifier_part(&self, ch: char) -> bool {
-        self.is_identifier_start(ch) || ('0'..='9').contains(&ch)
+        is_identifier_character(ch) || ch.is_ascii_digit()
     }
 }
 
+#[allow(clippy::nonminimal_bool)]
+fn is_identifier_character(ch: char) -> bool {
+    // See https://www.sqlite.org/draft/tokenreq.html
+    ch.is_ascii_lowercase()
+        || ch.is_ascii_uppercase()
+        || ch == '_'
+        || ch == '$'
+        || ('\u{007f}'..='\u{ffff}').contains(&ch)
+        || ch == '_'
+        || ch == '_'
+        || ch == '.'
+        || ch == '/'
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,4 +120,14 @@ mod tests {
         );
         println!("AST: {:?}", ast)
     }
+
+    #[test]
+    fn it_parses_sql_with_named_parameters() {
+        let sql = "select * from ./foo.csv where age > :min_age";
+        let ast = Parser::parse_sql(sql).unwrap();
+        assert_eq!(
+            ast[0].to_string(),
+            "SELECT * FROM ./foo.csv WHERE age > :min_age"
+        );
+    }
 }
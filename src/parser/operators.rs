@@ -0,0 +1,291 @@
+use sqlparser::ast::{
+    BinaryOperator, DataType, Expr, Ident, ObjectName, Query, SelectItem, SetExpr, Statement,
+};
+use sqlparser::dialect::keywords::Keyword;
+use sqlparser::parser::ParserError;
+use sqlparser::tokenizer::{Token, Word};
+
+use crate::db::JSON_FUNCTIONS;
+use crate::error::Result;
+use crate::parser::visitor::{walk_expr, walk_statement, Visitor};
+
+///Our parser doesn't know SQLite's JSON operators, so both are parsed as `^`, which SQLite
+///doesn't have. It binds more tightly than comparisons, `AND` and `OR`, like the operators it
+///stands in for, so predicates are still found in the right places. Both sides stay ordinary
+///expressions, so the columns and tables they use are found like any others
+const PLACEHOLDER: BinaryOperator = BinaryOperator::BitwiseXor;
+///The left side of `->` is also cast to this type, which can't be written in a query as it isn't
+///quoted, to tell it apart from `->>`
+const ARROW_MARKER: &str = "->";
+
+///SQLite's functions which return JSON, as opposed to values taken out of JSON
+const SQLITE_JSON_FUNCTIONS: &[&str] = &[
+    "json",
+    "json_array",
+    "json_object",
+    "json_group_array",
+    "json_group_object",
+    "json_insert",
+    "json_patch",
+    "json_quote",
+    "json_remove",
+    "json_replace",
+    "json_set",
+];
+
+///Replaces the tokens of `->` and `->>` with tokens our parser can read. SQLite has no `^`
+///operator, so one written in a query is rejected rather than taken for them
+pub fn replace_json_operators(tokens: Vec<Token>) -> std::result::Result<Vec<Token>, ParserError> {
+    let mut replaced = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match (&token, tokens.peek()) {
+            (Token::Minus, Some(Token::Gt)) => {
+                tokens.next();
+                replaced.push(Token::DoubleColon);
+                replaced.push(Token::Word(Word {
+                    value: String::from(ARROW_MARKER),
+                    quote_style: None,
+                    keyword: Keyword::NoKeyword,
+                }));
+                replaced.push(Token::Caret);
+            }
+            (Token::Minus, Some(Token::ShiftRight)) => {
+                tokens.next();
+                replaced.push(Token::Caret);
+            }
+            (Token::Caret, _) => {
+                return Err(ParserError::TokenizerError(String::from(
+                    "SQLite has no ^ operator",
+                )))
+            }
+            _ => replaced.push(token),
+        }
+    }
+    Ok(replaced)
+}
+
+///Writes a statement as the SQL SQLite runs, with the JSON operators as SQLite expects them.
+///The statement itself is left as it is, so it can still be looked into
+pub fn to_sql(statement: &Statement) -> Result<String> {
+    let mut statement = statement.clone();
+    walk_statement(&mut JsonOperators, &mut statement)?;
+    Ok(statement.to_string())
+}
+
+///Whether a statement uses `->` or `->>` anywhere
+pub fn uses_json_operators(statement: &Statement) -> bool {
+    let mut finder = OperatorFinder { found: false };
+    let mut statement = statement.clone();
+    walk_statement(&mut finder, &mut statement).is_ok() && finder.found
+}
+
+///Which of the columns a statement returns hold JSON, because they're the result of `->` or of a
+///function returning JSON. Columns which come from a wildcard, or from a table, view or subquery,
+///aren't known to hold JSON
+pub fn json_columns(statement: &Statement, column_count: usize) -> Vec<bool> {
+    let mut columns = vec![false; column_count];
+    let items = match statement {
+        Statement::Query(query) => projection(query),
+        _ => return columns,
+    };
+    let is_wildcard = |item: &SelectItem| {
+        matches!(
+            item,
+            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_)
+        )
+    };
+    // items before a wildcard are counted from the first column, and those after it from the last
+    let before = items.iter().position(is_wildcard).unwrap_or(items.len());
+    let after = items.iter().rev().position(is_wildcard).unwrap_or(0);
+    let from_start = items.iter().take(before).enumerate();
+    let from_end = items
+        .iter()
+        .skip(items.len() - after)
+        .enumerate()
+        .map(|(i, item)| ((column_count + i).checked_sub(after), item))
+        .filter_map(|(i, item)| i.map(|i| (i, item)));
+    for (i, item) in from_start.chain(from_end) {
+        if let (
+            Some(column),
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. },
+        ) = (columns.get_mut(i), item)
+        {
+            *column = is_json(expr);
+        }
+    }
+    columns
+}
+
+///The projection of the SELECT which names a query's columns, which is the first one of a UNION
+fn projection(query: &Query) -> &[SelectItem] {
+    let mut body = &query.body;
+    loop {
+        match body {
+            SetExpr::Select(select) => return &select.projection,
+            SetExpr::Query(query) => body = &query.body,
+            SetExpr::SetOperation { left, .. } => body = left,
+            _ => return &[],
+        }
+    }
+}
+
+fn is_json(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nested(expr) => is_json(expr),
+        Expr::BinaryOp { left, op, .. } if *op == PLACEHOLDER => has_arrow_marker(left),
+        Expr::Function(function) => function.name.0.last().is_some_and(|name| {
+            SQLITE_JSON_FUNCTIONS
+                .iter()
+                .chain(JSON_FUNCTIONS.iter())
+                .any(|json| name.value.eq_ignore_ascii_case(json))
+        }),
+        _ => false,
+    }
+}
+
+struct OperatorFinder {
+    found: bool,
+}
+
+impl Visitor for OperatorFinder {
+    fn visit_table_name(&mut self, _name: &mut ObjectName) {}
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        if let Expr::BinaryOp { op, .. } = expr {
+            self.found |= *op == PLACEHOLDER;
+        }
+    }
+}
+
+struct JsonOperators;
+
+impl Visitor for JsonOperators {
+    fn visit_table_name(&mut self, _name: &mut ObjectName) {}
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        if let Expr::BinaryOp { left, op, right } = expr {
+            if *op != PLACEHOLDER {
+                return;
+            }
+            let operator = if remove_arrow_marker(left) {
+                "->"
+            } else {
+                "->>"
+            };
+            walk_expr(self, left);
+            walk_expr(self, right);
+            // there's no expression for other operators, so it's written out as an identifier
+            let sql = format!("{} {} {}", left, operator, right);
+            *expr = Expr::Identifier(Ident::new(sql));
+        }
+    }
+}
+
+///Whether an expression is the left side of `->`, marked by a cast of its last part
+fn has_arrow_marker(expr: &Expr) -> bool {
+    match expr {
+        Expr::Cast {
+            data_type: DataType::Custom(ObjectName(name)),
+            ..
+        } => is_arrow_marker(name),
+        Expr::BinaryOp { right, .. } => has_arrow_marker(right),
+        Expr::UnaryOp { expr, .. } => has_arrow_marker(expr),
+        _ => false,
+    }
+}
+
+///Removes the cast marking the left side of `->`, which is applied to the last part of it
+fn remove_arrow_marker(expr: &mut Expr) -> bool {
+    match expr {
+        Expr::Cast {
+            expr: inner,
+            data_type: DataType::Custom(ObjectName(name)),
+        } if is_arrow_marker(name) => {
+            *expr = (**inner).clone();
+            true
+        }
+        Expr::BinaryOp { right, .. } => remove_arrow_marker(right),
+        Expr::UnaryOp { expr, .. } => remove_arrow_marker(expr),
+        _ => false,
+    }
+}
+
+fn is_arrow_marker(name: &[Ident]) -> bool {
+    name.len() == 1 && name[0].quote_style.is_none() && name[0].value == ARROW_MARKER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::predicates::single_table_predicates;
+    use crate::parser::projection::referenced_columns;
+    use crate::parser::Parser;
+
+    fn restored(sql: &str) -> String {
+        let ast = Parser::parse_sql(sql).unwrap();
+        to_sql(&ast[0]).unwrap()
+    }
+
+    #[test]
+    fn it_parses_and_restores_json_operators() {
+        assert_eq!(
+            restored("select data -> 'tags' ->> 0, data->>'$.a.b' from t where data ->> 'n' > 1"),
+            "SELECT data -> 'tags' ->> 0, data ->> '$.a.b' FROM t WHERE data ->> 'n' > 1"
+        );
+        assert_eq!(
+            restored("select (select max(x ->> 'a') from u) - 1 > 2, -a.b -> 'c' || 'd' from t"),
+            "SELECT (SELECT max(x ->> 'a') FROM u) - 1 > 2, - a.b -> 'c' || 'd' FROM t"
+        );
+    }
+
+    #[test]
+    fn it_rejects_the_operator_standing_in_for_json_operators() {
+        let error = Parser::parse_sql("select a ^ b from t").unwrap_err();
+        assert!(error.to_string().contains("SQLite has no ^ operator"));
+    }
+
+    #[test]
+    fn it_does_not_push_down_comparisons_of_json_values() {
+        let ast = Parser::parse_sql("select * from t where data ->> 'n' = 1 and id = 2").unwrap();
+        let predicates = single_table_predicates(&ast[0]);
+        assert_eq!(predicates["t"].len(), 1);
+    }
+
+    #[test]
+    fn it_finds_columns_used_only_by_json_operators() {
+        let ast = Parser::parse_sql("select id from t where payload ->> '$.x' = 1").unwrap();
+        let columns = referenced_columns(&ast[0]).unwrap();
+        assert!(columns.contains("payload"));
+        assert!(uses_json_operators(&ast[0]));
+        let ast = Parser::parse_sql("select id from t where payload = 1").unwrap();
+        assert!(!uses_json_operators(&ast[0]));
+    }
+
+    #[test]
+    fn it_finds_the_columns_holding_json() {
+        let columns = |sql: &str, count: usize| {
+            let ast = Parser::parse_sql(sql).unwrap();
+            json_columns(&ast[0], count)
+        };
+        assert_eq!(
+            columns(
+                "select a -> 'x', a ->> 'x', json_object('a', 1) as o, '[1]', (a -> 'y') from t",
+                5
+            ),
+            vec![true, false, true, false, true]
+        );
+        assert_eq!(
+            columns("select json_array(1), *, a -> 'x' from t", 4),
+            vec![true, false, false, true]
+        );
+        assert_eq!(
+            columns("select approx_top_k(a, 2), json_flatten(b) from t", 2),
+            vec![true, true]
+        );
+        assert_eq!(
+            columns("select a from t union select json(b) from u", 1),
+            vec![false]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use sqlparser::ast::{Expr, ObjectName, Statement};
+
+use crate::parser::visitor::{walk_statement, Visitor};
+
+///Collects the named parameters, such as `:name`, used anywhere in a statement
+struct ParameterCollector {
+    names: Vec<String>,
+}
+
+impl Visitor for ParameterCollector {
+    fn visit_table_name(&mut self, _name: &mut ObjectName) {}
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        if let Expr::Identifier(ident) = expr {
+            if ident.quote_style.is_none()
+                && ident.value.starts_with(':')
+                && !self.names.contains(&ident.value)
+            {
+                self.names.push(ident.value.clone());
+            }
+        }
+    }
+}
+
+///The named parameters a statement uses, including the leading `:`, in the order they're found
+pub fn parameter_names(statement: &Statement) -> Vec<String> {
+    let mut collector = ParameterCollector { names: vec![] };
+    let mut statement = statement.clone();
+    match walk_statement(&mut collector, &mut statement) {
+        Ok(()) => collector.names,
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn it_collects_named_parameters() {
+        let ast = Parser::parse_sql(
+            "select name, ':quoted' from people.csv where age > :min_age and (age < :max_age or name in (select name from b.csv where age = :min_age))",
+        )
+        .unwrap();
+        assert_eq!(
+            parameter_names(&ast[0]),
+            vec![String::from(":min_age"), String::from(":max_age")]
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use sqlparser::ast::{
+    BinaryOperator, Expr, Ident, ObjectName, SetExpr, Statement, TableFactor, UnaryOperator, Value,
+};
+
+use crate::csv::csv_data::CsvWrapper;
+use crate::csv::inference::parse;
+use crate::parser::visitor::{walk_statement, Visitor};
+
+///A comparison between a column and a constant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+impl Operator {
+    fn from_binary_operator(op: &BinaryOperator) -> Option<Operator> {
+        match op {
+            BinaryOperator::Eq => Some(Operator::Eq),
+            BinaryOperator::NotEq => Some(Operator::NotEq),
+            BinaryOperator::Lt => Some(Operator::Lt),
+            BinaryOperator::LtEq => Some(Operator::LtEq),
+            BinaryOperator::Gt => Some(Operator::Gt),
+            BinaryOperator::GtEq => Some(Operator::GtEq),
+            _ => None,
+        }
+    }
+
+    ///The operator to use when the constant is on the left, e.g. `10 < a` is `a > 10`
+    fn flip(self) -> Operator {
+        match self {
+            Operator::Lt => Operator::Gt,
+            Operator::LtEq => Operator::GtEq,
+            Operator::Gt => Operator::Lt,
+            Operator::GtEq => Operator::LtEq,
+            op => op,
+        }
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::NotEq => ordering != Ordering::Equal,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::LtEq => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::GtEq => ordering != Ordering::Less,
+        }
+    }
+}
+
+///A constant in a predicate, kept as written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(String),
+    Text(String),
+}
+
+///A simple condition on a single column which every row of a query's result must satisfy, such
+///as `year = 2026`, `month IN (10, 11)` or `day BETWEEN 1 AND 7`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Compare {
+        column: String,
+        operator: Operator,
+        value: Constant,
+    },
+    In {
+        column: String,
+        values: Vec<Constant>,
+        negated: bool,
+    },
+    Between {
+        column: String,
+        low: Constant,
+        high: Constant,
+        negated: bool,
+    },
+}
+
+impl Predicate {
+    ///The column the predicate is on
+    pub fn column(&self) -> &str {
+        match self {
+            Predicate::Compare { column, .. }
+            | Predicate::In { column, .. }
+            | Predicate::Between { column, .. } => column,
+        }
+    }
+
+    ///Whether a value of the predicate's column satisfies it, or None if that can't be known
+    ///without SQLite's full comparison rules, e.g. when comparing a number to text
+    pub fn evaluate(&self, value: &CsvWrapper) -> Option<bool> {
+        match self {
+            Predicate::Compare {
+                operator,
+                value: constant,
+                ..
+            } => compare(value, constant).map(|ordering| operator.holds(ordering)),
+            Predicate::In {
+                values, negated, ..
+            } => {
+                let mut found = false;
+                for constant in values.iter() {
+                    found |= compare(value, constant)? == Ordering::Equal;
+                }
+                Some(found != *negated)
+            }
+            Predicate::Between {
+                low, high, negated, ..
+            } => {
+                let within = compare(value, low)? != Ordering::Less
+                    && compare(value, high)? != Ordering::Greater;
+                Some(within != *negated)
+            }
+        }
+    }
+}
+
+///Compares a typed value to a constant the way SQLite would for a column of the value's type
+fn compare(value: &CsvWrapper, constant: &Constant) -> Option<Ordering> {
+    let constant = match (value, constant) {
+        (CsvWrapper::String(s), Constant::Text(constant)) => return Some(s.as_str().cmp(constant)),
+        // SQLite compares text columns to numbers as text, which is only easy to predict for
+        // integers, so `1e3` becomes `1000.0` rather than staying as written
+        (CsvWrapper::String(s), Constant::Number(constant)) => {
+            return match parse(constant) {
+                CsvWrapper::Integer(i) => Some(s.as_str().cmp(i.to_string().as_str())),
+                _ => None,
+            }
+        }
+        (_, Constant::Number(constant)) | (_, Constant::Text(constant)) => parse(constant),
+    };
+    match (value, constant) {
+        (CsvWrapper::Integer(i), CsvWrapper::Integer(constant)) => Some(i.cmp(&constant)),
+        (CsvWrapper::Integer(i), CsvWrapper::Float(constant)) => {
+            compare_integer_to_float(*i, constant)
+        }
+        (CsvWrapper::Float(f), CsvWrapper::Integer(constant)) => {
+            compare_integer_to_float(constant, *f).map(Ordering::reverse)
+        }
+        (CsvWrapper::Float(f), CsvWrapper::Float(constant)) => f.partial_cmp(&constant),
+        _ => None,
+    }
+}
+
+///Compares an integer to a float exactly, as SQLite does, rather than rounding the integer to the
+///nearest float, which makes integers above 2^53 equal to their neighbours
+fn compare_integer_to_float(i: i64, f: f64) -> Option<Ordering> {
+    match (i as f64).partial_cmp(&f)? {
+        // 2^63 is the only float which equals an i64 rounded to a float but is out of its range
+        Ordering::Equal if f >= 9_223_372_036_854_775_808.0 => Some(Ordering::Less),
+        Ordering::Equal => Some(i.cmp(&(f as i64))),
+        ordering => Some(ordering),
+    }
+}
+
+///Finds the predicates in the WHERE clause of a SELECT which reads from a single table, keyed by
+///that table's identifier. Only conditions joined by AND at the top level are used, so any row
+///which doesn't satisfy them can't be part of the result. Tables referenced more than once by the
+///statement are left out, since each reference may need different rows
+pub fn single_table_predicates(statement: &Statement) -> HashMap<String, Vec<Predicate>> {
+    let mut predicates = HashMap::new();
+    let query = match statement {
+        Statement::Query(query) => query,
+        _ => return predicates,
+    };
+    let select = match query.body {
+        SetExpr::Select(ref select) => select,
+        _ => return predicates,
+    };
+    if select.from.len() != 1 || !select.from[0].joins.is_empty() {
+        return predicates;
+    }
+    let (name, alias) = match select.from[0].relation {
+        TableFactor::Table {
+            ref name,
+            ref alias,
+            ..
+        } if name.0.len() == 1 => (&name.0[0], alias.as_ref().map(|a| &a.name)),
+        _ => return predicates,
+    };
+    if references(statement, &name.value) > 1 {
+        return predicates;
+    }
+    if let Some(ref selection) = select.selection {
+        let mut found = vec![];
+        collect_conjuncts(selection, name, alias, &mut found);
+        if !found.is_empty() {
+            predicates.insert(name.value.clone(), found);
+        }
+    }
+    predicates
+}
+
+fn collect_conjuncts(
+    expr: &Expr,
+    table: &Ident,
+    alias: Option<&Ident>,
+    predicates: &mut Vec<Predicate>,
+) {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            collect_conjuncts(left, table, alias, predicates);
+            collect_conjuncts(right, table, alias, predicates);
+        }
+        Expr::Nested(expr) => collect_conjuncts(expr, table, alias, predicates),
+        Expr::BinaryOp { left, op, right } => {
+            let operator = match Operator::from_binary_operator(op) {
+                Some(operator) => operator,
+                None => return,
+            };
+            let column = column_name(left, table, alias);
+            let value = constant(right);
+            let predicate = match (column, value) {
+                (Some(column), Some(value)) => Some((column, operator, value)),
+                _ => match (column_name(right, table, alias), constant(left)) {
+                    (Some(column), Some(value)) => Some((column, operator.flip(), value)),
+                    _ => None,
+                },
+            };
+            if let Some((column, operator, value)) = predicate {
+                predicates.push(Predicate::Compare {
+                    column,
+                    operator,
+                    value,
+                });
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let values: Option<Vec<Constant>> = list.iter().map(constant).collect();
+            if let (Some(column), Some(values)) = (column_name(expr, table, alias), values) {
+                predicates.push(Predicate::In {
+                    column,
+                    values,
+                    negated: *negated,
+                });
+            }
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            if let (Some(column), Some(low), Some(high)) = (
+                column_name(expr, table, alias),
+                constant(low),
+                constant(high),
+            ) {
+                predicates.push(Predicate::Between {
+                    column,
+                    low,
+                    high,
+                    negated: *negated,
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+///The column an expression refers to, if it's a plain or qualified reference to the table
+fn column_name(expr: &Expr, table: &Ident, alias: Option<&Ident>) -> Option<String> {
+    match expr {
+        // our dialect allows `.` within identifiers, so `t.a` is usually a single identifier
+        Expr::Identifier(ident) if ident.quote_style.is_none() && ident.value.contains('.') => {
+            let mut split = ident.value.splitn(2, '.');
+            qualified_column_name(split.next()?, split.next()?, table, alias)
+        }
+        Expr::Identifier(ident) if ident.quote_style != Some('\'') => Some(ident.value.clone()),
+        Expr::CompoundIdentifier(idents) if idents.len() == 2 => {
+            qualified_column_name(&idents[0].value, &idents[1].value, table, alias)
+        }
+        _ => None,
+    }
+}
+
+fn qualified_column_name(
+    qualifier: &str,
+    column: &str,
+    table: &Ident,
+    alias: Option<&Ident>,
+) -> Option<String> {
+    let matches = match alias {
+        Some(alias) => qualifier == alias.value,
+        None => qualifier == table.value,
+    };
+    if matches {
+        Some(String::from(column))
+    } else {
+        None
+    }
+}
+
+///A numeric or string constant
+fn constant(expr: &Expr) -> Option<Constant> {
+    match expr {
+        Expr::Value(Value::Number(n, _)) => Some(Constant::Number(n.clone())),
+        Expr::Value(Value::SingleQuotedString(s)) => Some(Constant::Text(s.clone())),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(n, _)) => Some(Constant::Number(format!("-{}", n))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+///Counts how many times a statement references a table identifier
+fn references(statement: &Statement, identifier: &str) -> usize {
+    struct Counter<'a> {
+        identifier: &'a str,
+        count: usize,
+    }
+    impl<'a> Visitor for Counter<'a> {
+        fn visit_table_name(&mut self, name: &mut ObjectName) {
+            if name.0.iter().any(|ident| ident.value == self.identifier) {
+                self.count += 1;
+            }
+        }
+    }
+    let mut counter = Counter {
+        identifier,
+        count: 0,
+    };
+    let mut statement = statement.clone();
+    // statements which can't be walked are never run, so there's nothing to count
+    walk_statement(&mut counter, &mut statement).map_or(0, |_| counter.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn predicates(sql: &str) -> HashMap<String, Vec<Predicate>> {
+        let ast = Parser::parse_sql(sql).unwrap();
+        single_table_predicates(&ast[0])
+    }
+
+    #[test]
+    fn it_finds_predicates_joined_by_and() {
+        let found = predicates(
+            "select * from logs/ as l where year = 2026 and (l.month in (9, 10) and 3 < day) and level = 'warn'",
+        );
+        assert_eq!(
+            found.get("logs/").unwrap(),
+            &vec![
+                Predicate::Compare {
+                    column: String::from("year"),
+                    operator: Operator::Eq,
+                    value: Constant::Number(String::from("2026"))
+                },
+                Predicate::In {
+                    column: String::from("month"),
+                    values: vec![
+                        Constant::Number(String::from("9")),
+                        Constant::Number(String::from("10"))
+                    ],
+                    negated: false
+                },
+                Predicate::Compare {
+                    column: String::from("day"),
+                    operator: Operator::Gt,
+                    value: Constant::Number(String::from("3"))
+                },
+                Predicate::Compare {
+                    column: String::from("level"),
+                    operator: Operator::Eq,
+                    value: Constant::Text(String::from("warn"))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_ignores_conditions_which_arent_simple() {
+        let found = predicates(
+            "select * from logs/ where year = 2026 or month = 10 and day between 1 and -7",
+        );
+        assert!(found.is_empty());
+        let found =
+            predicates("select * from logs/ where year + 1 = 2027 and day between 1 and -7");
+        assert_eq!(
+            found.get("logs/").unwrap(),
+            &vec![Predicate::Between {
+                column: String::from("day"),
+                low: Constant::Number(String::from("1")),
+                high: Constant::Number(String::from("-7")),
+                negated: false
+            }]
+        );
+    }
+
+    #[test]
+    fn it_ignores_joins_and_tables_referenced_twice() {
+        assert!(predicates("select * from a.csv join b.csv on a.id = b.id where x = 1").is_empty());
+        assert!(
+            predicates("select * from a.csv where x = 1 and y in (select y from a.csv)").is_empty()
+        );
+        assert!(predicates("select * from a.csv union select * from a.csv where x = 1").is_empty());
+    }
+
+    #[test]
+    fn it_evaluates_predicates() {
+        let year = Predicate::Compare {
+            column: String::from("year"),
+            operator: Operator::GtEq,
+            value: Constant::Number(String::from("2025")),
+        };
+        assert_eq!(year.evaluate(&CsvWrapper::Integer(2026)), Some(true));
+        assert_eq!(year.evaluate(&CsvWrapper::Float(2024.5)), Some(false));
+        assert_eq!(
+            year.evaluate(&CsvWrapper::String(String::from("2026"))),
+            Some(true)
+        );
+        let level = Predicate::In {
+            column: String::from("level"),
+            values: vec![
+                Constant::Text(String::from("warn")),
+                Constant::Text(String::from("error")),
+            ],
+            negated: true,
+        };
+        assert_eq!(
+            level.evaluate(&CsvWrapper::String(String::from("info"))),
+            Some(true)
+        );
+        assert_eq!(level.evaluate(&CsvWrapper::Integer(1)), None);
+    }
+
+    #[test]
+    fn it_compares_integers_above_2_to_the_53_exactly() {
+        let not_equal = |constant: &str| Predicate::Compare {
+            column: String::from("id"),
+            operator: Operator::NotEq,
+            value: Constant::Number(String::from(constant)),
+        };
+        let id = CsvWrapper::Integer(9_007_199_254_740_993);
+        assert_eq!(not_equal("9007199254740992").evaluate(&id), Some(true));
+        assert_eq!(not_equal("9007199254740993").evaluate(&id), Some(false));
+        // the float is exactly 2^53, which is less than the integer
+        assert_eq!(not_equal("9007199254740992.0").evaluate(&id), Some(true));
+        let max = CsvWrapper::Integer(i64::MAX);
+        assert_eq!(
+            not_equal("9223372036854775807.0").evaluate(&max),
+            Some(true)
+        );
+        let float = Predicate::Compare {
+            column: String::from("x"),
+            operator: Operator::Lt,
+            value: Constant::Number(String::from("9007199254740993")),
+        };
+        assert_eq!(
+            float.evaluate(&CsvWrapper::Float(9_007_199_254_740_992.0)),
+            Some(true)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use sqlparser::ast::{Expr, Join, JoinConstraint, JoinOperator, ObjectName, SelectItem, Statement};
+
+use crate::parser::visitor::{walk_statement, Visitor};
+
+///Collects the names of every column a statement could refer to, regardless of which table they
+///belong to. Finding a wildcard, or a join which picks its columns implicitly, means every column
+///could be needed
+struct ColumnCollector {
+    columns: HashSet<String>,
+    all_columns: bool,
+}
+
+impl Visitor for ColumnCollector {
+    fn visit_table_name(&mut self, _name: &mut ObjectName) {}
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Identifier(ident) => self.add(&ident.value),
+            Expr::CompoundIdentifier(idents) => {
+                if let Some(ident) = idents.last() {
+                    self.add(&ident.value)
+                }
+            }
+            Expr::QualifiedWildcard(_) => self.all_columns = true,
+            _ => {}
+        }
+    }
+
+    fn visit_select_item(&mut self, item: &mut SelectItem) {
+        if let SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) = item {
+            self.all_columns = true;
+        }
+    }
+
+    fn visit_join(&mut self, join: &mut Join) {
+        match join.join_operator {
+            JoinOperator::Inner(ref constraint)
+            | JoinOperator::LeftOuter(ref constraint)
+            | JoinOperator::RightOuter(ref constraint)
+            | JoinOperator::FullOuter(ref constraint) => match constraint {
+                JoinConstraint::Using(idents) => {
+                    for ident in idents.iter() {
+                        self.add(&ident.value);
+                    }
+                }
+                JoinConstraint::Natural => self.all_columns = true,
+                JoinConstraint::On(_) | JoinConstraint::None => {}
+            },
+            JoinOperator::CrossJoin | JoinOperator::CrossApply | JoinOperator::OuterApply => {}
+        }
+    }
+}
+
+impl ColumnCollector {
+    fn add(&mut self, name: &str) {
+        // our dialect allows `.` within identifiers, so `t.a` is usually a single identifier
+        let column = name.rsplit('.').next().unwrap_or(name);
+        self.columns.insert(column.to_lowercase());
+        self.columns.insert(name.to_lowercase());
+    }
+}
+
+///The lowercased names of every column a SELECT statement could use from the tables it reads, or
+///None if it could use all of them, e.g. through `SELECT *`. Names are collected from the whole
+///statement without working out which table they belong to, so each table's columns can be
+///narrowed down to those in the set
+pub fn referenced_columns(statement: &Statement) -> Option<HashSet<String>> {
+    if !matches!(statement, Statement::Query(_)) {
+        return None;
+    }
+    let mut collector = ColumnCollector {
+        columns: HashSet::new(),
+        all_columns: false,
+    };
+    let mut statement = statement.clone();
+    walk_statement(&mut collector, &mut statement).ok()?;
+    if collector.all_columns {
+        None
+    } else {
+        Some(collector.columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn columns(sql: &str) -> Option<Vec<String>> {
+        let ast = Parser::parse_sql(sql).unwrap();
+        referenced_columns(&ast[0]).map(|columns| {
+            let mut columns: Vec<String> = columns.into_iter().collect();
+            columns.sort();
+            columns
+        })
+    }
+
+    #[test]
+    fn it_collects_columns_from_every_clause() {
+        assert_eq!(
+            columns(
+                "select p.Name, count(*) from people.csv as p where age > (select min(age) from b.csv) group by \"home town\" order by 2"
+            ),
+            Some(vec![
+                String::from("age"),
+                String::from("home town"),
+                String::from("name"),
+                String::from("p.name"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_needs_every_column_for_wildcards_and_natural_joins() {
+        assert_eq!(columns("select * from people.csv where age > 1"), None);
+        assert_eq!(columns("select name from (select * from people.csv)"), None);
+        assert_eq!(columns("select name from a.csv natural join b.csv"), None);
+        assert_eq!(columns("create table foo as select name from a.csv"), None);
+    }
+
+    #[test]
+    fn it_collects_join_columns() {
+        assert_eq!(
+            columns("select name from a.csv join b.csv using (id)"),
+            Some(vec![String::from("id"), String::from("name")])
+        );
+    }
+}
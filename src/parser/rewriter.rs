@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use sqlparser::ast::{Query, Select, SetExpr, Statement, TableFactor, TableWithJoins};
+use sqlparser::ast::{ObjectName, Statement};
+
+use crate::error::Result;
+use crate::parser::visitor::{walk_statement, Visitor};
 
 /// Allows rewriting a SQL AST by mapping table identifiers to tablenames in mapping
 pub struct Rewriter {
@@ -13,87 +16,17 @@ impl Rewriter {
     }
     /// Rewrite the table identifiers in a SQL AST based on a mapping from the table identifier to
     /// the table name
-    pub fn rewrite(&self, ast: &mut Statement) {
-        match ast {
-            Statement::Query(boxed) => self.recurse_query(boxed),
-
-            _ => {
-                panic!("unrecognized")
-            }
-        }
-    }
-
-    fn recurse_query(&self, boxed: &mut Query) {
-        {
-            if let Some(ref mut with) = boxed.with {
-                for cte in with.cte_tables.iter_mut() {
-                    self.recurse_query(&mut cte.query);
-                }
-            }
-            match boxed.body {
-                SetExpr::Select(ref mut boxed) => {
-                    for from in boxed.from.iter_mut() {
-                        self.recurse_table_with_joins(from);
-                    }
-                }
-                SetExpr::Query(ref mut query) => self.recurse_query(query),
-                SetExpr::SetOperation {
-                    ref mut left,
-                    ref mut right,
-                    ..
-                } => {
-                    match left.as_mut() {
-                        SetExpr::Select(select) => {
-                            self.recurse_select(select);
-                        }
-                        SetExpr::Query(query) => {
-                            self.recurse_query(query);
-                        }
-                        _ => (),
-                    }
-
-                    match right.as_mut() {
-                        SetExpr::Select(select) => {
-                            self.recurse_select(select);
-                        }
-                        SetExpr::Query(query) => {
-                            self.recurse_query(query);
-                        }
-                        _ => (),
-                    }
-                }
-                SetExpr::Values(_) => {}
-                SetExpr::Insert(_) => {}
-            }
-        }
+    pub fn rewrite(&mut self, ast: &mut Statement) -> Result<()> {
+        walk_statement(self, ast)
     }
+}
 
-    fn recurse_select(&self, select: &mut Select) {
-        for from in select.from.iter_mut() {
-            self.recurse_table_with_joins(from);
-        }
-    }
-    fn handle_relation(&self, relation: &mut TableFactor) {
-        match relation {
-            TableFactor::Table { ref mut name, .. } => {
-                for ident in name.0.iter_mut() {
-                    if let Some(entry) = self.files_to_tables.get(ident.value.to_string().as_str())
-                    {
-                        ident.value = entry.clone()
-                    }
-                }
+impl Visitor for Rewriter {
+    fn visit_table_name(&mut self, name: &mut ObjectName) {
+        for ident in name.0.iter_mut() {
+            if let Some(entry) = self.files_to_tables.get(ident.value.to_string().as_str()) {
+                ident.value = entry.clone()
             }
-            TableFactor::Derived {
-                ref mut subquery, ..
-            } => self.recurse_query(subquery),
-            TableFactor::TableFunction { .. } => {}
-            TableFactor::NestedJoin(_) => {}
-        }
-    }
-    fn recurse_table_with_joins(&self, from: &mut TableWithJoins) {
-        self.handle_relation(&mut from.relation);
-        for join in from.joins.iter_mut() {
-            self.handle_relation(&mut join.relation);
         }
     }
 }
@@ -112,10 +45,10 @@ mod tests {
         let dialect = CsvDialect;
         let mut hm = HashMap::new();
         hm.insert(String::from("./foo.csv"), String::from("bar_table"));
-        let rewriter = Rewriter::new(hm);
+        let mut rewriter = Rewriter::new(hm);
         let sql = "select * from (select * from ./foo.csv)";
         let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        rewriter.rewrite(&mut ast[0]);
+        rewriter.rewrite(&mut ast[0]).unwrap();
         assert_eq!(
             ast[0].to_string(),
             "SELECT * FROM (SELECT * FROM bar_table)"
@@ -123,7 +56,7 @@ mod tests {
 
         let sql = "select * from (select * from (select * from ./foo.csv))";
         let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        rewriter.rewrite(&mut ast[0]);
+        rewriter.rewrite(&mut ast[0]).unwrap();
         assert_eq!(
             ast[0].to_string(),
             "SELECT * FROM (SELECT * FROM (SELECT * FROM bar_table))"
@@ -142,10 +75,10 @@ mod tests {
             String::from("testdata/occupations.csv"),
             String::from("occupations_table"),
         );
-        let rewriter = Rewriter::new(hm);
+        let mut rewriter = Rewriter::new(hm);
         let sql = " with some_cte (age) as (select distinct(age) from testdata/people.csv) select * from testdata/occupations.csv occupation INNER JOIN foo on (occupation.minimum_age = foo.age)";
         let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        rewriter.rewrite(&mut ast[0]);
+        rewriter.rewrite(&mut ast[0]).unwrap();
 
         assert_eq!(
             ast[0].to_string(),
@@ -165,14 +98,56 @@ mod tests {
             String::from("testdata/occupations.csv"),
             String::from("occupations_table"),
         );
-        let rewriter = Rewriter::new(hm);
+        let mut rewriter = Rewriter::new(hm);
         let sql = "select * from testdata/people.csv union select * from testdata/occupations.csv";
         let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
-        rewriter.rewrite(&mut ast[0]);
+        rewriter.rewrite(&mut ast[0]).unwrap();
 
         assert_eq!(
             ast[0].to_string(),
             "SELECT * FROM people_table UNION SELECT * FROM occupations_table"
         );
     }
+
+    #[test]
+    fn it_rewrites_filenames_in_create_table_and_view_statements() {
+        let dialect = CsvDialect;
+        let mut hm = HashMap::new();
+        hm.insert(
+            String::from("testdata/people.csv"),
+            String::from("people_table"),
+        );
+        let mut rewriter = Rewriter::new(hm);
+        let sql = "create table adults as select * from testdata/people.csv; create view names as select name from testdata/people.csv";
+        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
+        rewriter.rewrite(&mut ast[0]).unwrap();
+        rewriter.rewrite(&mut ast[1]).unwrap();
+
+        assert_eq!(
+            ast[0].to_string(),
+            "CREATE TABLE adults AS SELECT * FROM people_table"
+        );
+        assert_eq!(
+            ast[1].to_string(),
+            "CREATE VIEW names AS SELECT name FROM people_table"
+        );
+    }
+
+    #[test]
+    fn it_rewrites_filenames_in_expressions_and_nested_joins() {
+        let dialect = CsvDialect;
+        let mut hm = HashMap::new();
+        hm.insert(String::from("./a.csv"), String::from("a_table"));
+        hm.insert(String::from("./b.csv"), String::from("b_table"));
+        hm.insert(String::from("./c.csv"), String::from("c_table"));
+        let mut rewriter = Rewriter::new(hm);
+        let sql = "select (select count(*) from ./c.csv) from ./a.csv a join (./b.csv b join ./c.csv c on b.id = c.id) on a.id = b.id where a.id in (select id from ./b.csv) or exists (select 1 from ./c.csv)";
+        let mut ast = Parser::parse_sql(&dialect, sql).unwrap();
+        rewriter.rewrite(&mut ast[0]).unwrap();
+
+        assert_eq!(
+            ast[0].to_string(),
+            "SELECT (SELECT count(*) FROM c_table) FROM a_table AS a JOIN (b_table AS b JOIN c_table AS c ON b.id = c.id) ON a.id = b.id WHERE a.id IN (SELECT id FROM b_table) OR EXISTS (SELECT 1 FROM c_table)"
+        );
+    }
 }
@@ -0,0 +1,271 @@
+use sqlparser::ast::{
+    Expr, Function, FunctionArg, Join, JoinConstraint, JoinOperator, ListAggOnOverflow, ObjectName,
+    Query, Select, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+};
+
+use crate::error::{QsvError, Result};
+
+/// Something which wants to see every table reference in a SQL AST, wherever it appears: in FROM
+/// clauses, joins (including parenthesized ones), CTEs, set operations, and subqueries nested in
+/// expressions such as `IN (SELECT ...)`, `EXISTS (...)` or a scalar subquery in the projection
+pub trait Visitor {
+    /// Called for every table name referenced by the statement
+    fn visit_table_name(&mut self, name: &mut ObjectName);
+
+    /// Called for every expression, before any expressions nested within it
+    fn visit_expr(&mut self, _expr: &mut Expr) {}
+
+    /// Called for every item in the projection of a SELECT
+    fn visit_select_item(&mut self, _item: &mut SelectItem) {}
+
+    /// Called for every join, before the joined table
+    fn visit_join(&mut self, _join: &mut Join) {}
+
+    /// Called for every SELECT, before any of its parts
+    fn visit_select(&mut self, _select: &mut Select) {}
+}
+
+/// Walk every part of a statement which can reference a table, calling the visitor for each.
+/// Returns an error for statements qsv doesn't know how to run
+pub fn walk_statement<V: Visitor>(visitor: &mut V, statement: &mut Statement) -> Result<()> {
+    match statement {
+        Statement::Query(query) => walk_query(visitor, query),
+        Statement::CreateTable {
+            query: Some(query), ..
+        } => walk_query(visitor, query),
+        Statement::CreateView { query, .. } => walk_query(visitor, query),
+        Statement::CreateTable { query: None, .. } | Statement::Drop { .. } => {}
+        _ => return Err(QsvError::UnsupportedStatement(statement.to_string())),
+    }
+    Ok(())
+}
+
+fn walk_query<V: Visitor>(visitor: &mut V, query: &mut Query) {
+    if let Some(ref mut with) = query.with {
+        for cte in with.cte_tables.iter_mut() {
+            if let Some(ref mut from) = cte.from {
+                let mut name = ObjectName(vec![from.clone()]);
+                visitor.visit_table_name(&mut name);
+                if let Some(ident) = name.0.pop() {
+                    *from = ident;
+                }
+            }
+            walk_query(visitor, &mut cte.query);
+        }
+    }
+    walk_set_expr(visitor, &mut query.body);
+    for order_by in query.order_by.iter_mut() {
+        walk_expr(visitor, &mut order_by.expr);
+    }
+    if let Some(ref mut limit) = query.limit {
+        walk_expr(visitor, limit);
+    }
+    if let Some(ref mut offset) = query.offset {
+        walk_expr(visitor, &mut offset.value);
+    }
+    if let Some(ref mut quantity) = query.fetch.as_mut().and_then(|f| f.quantity.as_mut()) {
+        walk_expr(visitor, quantity);
+    }
+}
+
+fn walk_set_expr<V: Visitor>(visitor: &mut V, set_expr: &mut SetExpr) {
+    match set_expr {
+        SetExpr::Select(select) => walk_select(visitor, select),
+        SetExpr::Query(query) => walk_query(visitor, query),
+        SetExpr::SetOperation { left, right, .. } => {
+            walk_set_expr(visitor, left);
+            walk_set_expr(visitor, right);
+        }
+        SetExpr::Values(values) => {
+            for row in values.0.iter_mut() {
+                walk_exprs(visitor, row);
+            }
+        }
+        // only reachable from INSERT statements, which are rejected before walking
+        SetExpr::Insert(_) => {}
+    }
+}
+
+fn walk_select<V: Visitor>(visitor: &mut V, select: &mut Select) {
+    visitor.visit_select(select);
+    if let Some(ref mut quantity) = select.top.as_mut().and_then(|t| t.quantity.as_mut()) {
+        walk_expr(visitor, quantity);
+    }
+    for item in select.projection.iter_mut() {
+        visitor.visit_select_item(item);
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                walk_expr(visitor, expr)
+            }
+            SelectItem::QualifiedWildcard(_) | SelectItem::Wildcard => {}
+        }
+    }
+    for from in select.from.iter_mut() {
+        walk_table_with_joins(visitor, from);
+    }
+    for lateral_view in select.lateral_views.iter_mut() {
+        walk_expr(visitor, &mut lateral_view.lateral_view);
+    }
+    if let Some(ref mut selection) = select.selection {
+        walk_expr(visitor, selection);
+    }
+    walk_exprs(visitor, &mut select.group_by);
+    walk_exprs(visitor, &mut select.cluster_by);
+    walk_exprs(visitor, &mut select.distribute_by);
+    walk_exprs(visitor, &mut select.sort_by);
+    if let Some(ref mut having) = select.having {
+        walk_expr(visitor, having);
+    }
+}
+
+fn walk_table_with_joins<V: Visitor>(visitor: &mut V, table: &mut TableWithJoins) {
+    walk_table_factor(visitor, &mut table.relation);
+    for join in table.joins.iter_mut() {
+        walk_join(visitor, join);
+    }
+}
+
+fn walk_join<V: Visitor>(visitor: &mut V, join: &mut Join) {
+    visitor.visit_join(join);
+    walk_table_factor(visitor, &mut join.relation);
+    match join.join_operator {
+        JoinOperator::Inner(ref mut constraint)
+        | JoinOperator::LeftOuter(ref mut constraint)
+        | JoinOperator::RightOuter(ref mut constraint)
+        | JoinOperator::FullOuter(ref mut constraint) => {
+            if let JoinConstraint::On(expr) = constraint {
+                walk_expr(visitor, expr);
+            }
+        }
+        JoinOperator::CrossJoin | JoinOperator::CrossApply | JoinOperator::OuterApply => {}
+    }
+}
+
+fn walk_table_factor<V: Visitor>(visitor: &mut V, relation: &mut TableFactor) {
+    match relation {
+        TableFactor::Table {
+            name,
+            args,
+            with_hints,
+            ..
+        } => {
+            visitor.visit_table_name(name);
+            walk_function_args(visitor, args);
+            walk_exprs(visitor, with_hints);
+        }
+        TableFactor::Derived { subquery, .. } => walk_query(visitor, subquery),
+        TableFactor::TableFunction { expr, .. } => walk_expr(visitor, expr),
+        TableFactor::NestedJoin(nested) => walk_table_with_joins(visitor, nested),
+    }
+}
+
+fn walk_exprs<V: Visitor>(visitor: &mut V, exprs: &mut [Expr]) {
+    for expr in exprs.iter_mut() {
+        walk_expr(visitor, expr);
+    }
+}
+
+fn walk_function_args<V: Visitor>(visitor: &mut V, args: &mut [FunctionArg]) {
+    for arg in args.iter_mut() {
+        match arg {
+            FunctionArg::Named { arg, .. } | FunctionArg::Unnamed(arg) => walk_expr(visitor, arg),
+        }
+    }
+}
+
+fn walk_function<V: Visitor>(visitor: &mut V, function: &mut Function) {
+    walk_function_args(visitor, &mut function.args);
+    if let Some(ref mut over) = function.over {
+        walk_exprs(visitor, &mut over.partition_by);
+        for order_by in over.order_by.iter_mut() {
+            walk_expr(visitor, &mut order_by.expr);
+        }
+    }
+}
+
+/// Walk an expression and everything nested within it, calling the visitor for each
+pub fn walk_expr<V: Visitor>(visitor: &mut V, expr: &mut Expr) {
+    visitor.visit_expr(expr);
+    match expr {
+        Expr::Identifier(_)
+        | Expr::Wildcard
+        | Expr::QualifiedWildcard(_)
+        | Expr::CompoundIdentifier(_)
+        | Expr::Value(_)
+        | Expr::TypedString { .. } => {}
+        Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::Extract { expr, .. }
+        | Expr::Collate { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::MapAccess { column: expr, .. } => walk_expr(visitor, expr),
+        Expr::InList { expr, list, .. } => {
+            walk_expr(visitor, expr);
+            walk_exprs(visitor, list);
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            walk_expr(visitor, expr);
+            walk_query(visitor, subquery);
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            walk_expr(visitor, expr);
+            walk_expr(visitor, low);
+            walk_expr(visitor, high);
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            walk_expr(visitor, left);
+            walk_expr(visitor, right);
+        }
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+        } => {
+            walk_expr(visitor, expr);
+            if let Some(from) = substring_from {
+                walk_expr(visitor, from);
+            }
+            if let Some(substring_for) = substring_for {
+                walk_expr(visitor, substring_for);
+            }
+        }
+        Expr::Function(function) => walk_function(visitor, function),
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            if let Some(operand) = operand {
+                walk_expr(visitor, operand);
+            }
+            walk_exprs(visitor, conditions);
+            walk_exprs(visitor, results);
+            if let Some(else_result) = else_result {
+                walk_expr(visitor, else_result);
+            }
+        }
+        Expr::Exists(query) | Expr::Subquery(query) => walk_query(visitor, query),
+        Expr::ListAgg(list_agg) => {
+            walk_expr(visitor, &mut list_agg.expr);
+            if let Some(ref mut separator) = list_agg.separator {
+                walk_expr(visitor, separator);
+            }
+            if let Some(ListAggOnOverflow::Truncate {
+                filler: Some(ref mut filler),
+                ..
+            }) = list_agg.on_overflow
+            {
+                walk_expr(visitor, filler);
+            }
+            for order_by in list_agg.within_group.iter_mut() {
+                walk_expr(visitor, &mut order_by.expr);
+            }
+        }
+    }
+}
@@ -1,24 +1,21 @@
 use std::collections::HashMap;
-use std::error::Error;
-use std::path::Path;
 
 use log::debug;
 
 use crate::csv::inference::{ColumnInference, ColumnInferences};
+use crate::error::Result;
 use crate::parser::collector::Collector;
 use crate::parser::Parser;
-use crate::qsv::{csv_data_from_mime_type, Options};
+use crate::qsv::discovery::discover_files;
+use crate::qsv::{infer_columns, infer_partitions, load_csv_files, normalize_columns, Options};
 
 ///Executes a query, possibly returning Rows
-pub fn execute_analysis(
-    query: &str,
-    options: &Options,
-) -> Result<ColumnInferences, Box<dyn Error>> {
+pub fn execute_analysis(query: &str, options: &Options) -> Result<ColumnInferences> {
     let mut collector = Collector::new();
     let ast = Parser::parse_sql(query)?;
-    let statement = &ast[0];
-
-    collector.collect(statement); //TODO: should we handle multiple SQL statements later?
+    for statement in ast.iter() {
+        collector.collect(statement)?;
+    }
     let mut hashmap: HashMap<String, ColumnInference> = HashMap::new();
     for filename in collector.table_identifiers.iter() {
         if let Ok(Some(inference)) = maybe_load_analysis(filename, options) {
@@ -37,20 +34,16 @@ pub fn execute_analysis(
     Ok(ColumnInferences::new(hashmap))
 }
 
-fn maybe_load_analysis(
-    filename: &str,
-    options: &Options,
-) -> Result<Option<ColumnInference>, Box<dyn Error>> {
-    let path = Path::new(filename);
-    if !path.exists() {
-        return Ok(None);
-    }
-    let mime_type = tree_magic::from_filepath(path);
-    let csv = csv_data_from_mime_type(filename, mime_type.as_str(), options)?;
-    let inference = if options.textonly {
-        ColumnInference::default_inference(&csv.headers)
-    } else {
-        ColumnInference::from_csv(&csv)
+fn maybe_load_analysis(filename: &str, options: &Options) -> Result<Option<ColumnInference>> {
+    let files = match discover_files(filename)? {
+        Some(files) => files,
+        None => return Ok(None),
     };
+    let mut csvs = load_csv_files(&files, options)?;
+    if options.normalize_headers {
+        normalize_columns(&mut csvs);
+    }
+    let mut inference = infer_columns(&csvs, options);
+    inference.merge(&infer_partitions(&files, options));
     Ok(Some(inference))
 }
@@ -0,0 +1,165 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{QsvError, Result};
+
+///Set when Ctrl-C is pressed while a query is running
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+///Whether Ctrl-C cancels the running query rather than killing the process
+static CATCHING: AtomicBool = AtomicBool::new(false);
+
+///Why a query stopped before it finished
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cancelled {
+    ///Ctrl-C was pressed
+    Interrupted,
+    ///The query ran for longer than its timeout
+    TimedOut(Duration),
+}
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cancelled::Interrupted => f.write_str("Query was cancelled"),
+            Cancelled::TimedOut(timeout) => write!(f, "Query timed out after {:?}", timeout),
+        }
+    }
+}
+
+///Makes Ctrl-C cancel the running query, instead of killing the process. Pressing it again before
+///the query has stopped kills the process as usual
+pub fn catch_interrupts() {
+    CATCHING.store(true, Ordering::SeqCst);
+    install_handler();
+}
+
+#[cfg(unix)]
+fn install_handler() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_handler() {}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    running: bool,
+    deadline: Option<Instant>,
+    cancelled: Option<Cancelled>,
+}
+
+///Decides when the running query should stop: once Ctrl-C is pressed, or once it's run for longer
+///than its timeout. Clones share the same state, so SQLite's progress handler can hold one
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    timeout: Option<Duration>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Cancellation {
+    pub fn new(timeout: Option<Duration>) -> Cancellation {
+        Cancellation {
+            timeout,
+            state: Arc::default(),
+        }
+    }
+
+    ///Starts timing a query, forgetting whether an earlier one was cancelled
+    pub fn start(&self) {
+        if CATCHING.load(Ordering::SeqCst) {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+            install_handler();
+        }
+        let mut state = self.state.lock().expect("cancellation lock poisoned");
+        state.running = true;
+        state.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        state.cancelled = None;
+    }
+
+    ///Stops checking once a query has finished, so work done between queries isn't interrupted
+    pub fn finish(&self) {
+        self.state
+            .lock()
+            .expect("cancellation lock poisoned")
+            .running = false;
+    }
+
+    ///Whether the running query should be interrupted. This is only true the first time the query
+    ///is found to be cancelled, so cleaning up afterwards isn't interrupted as well
+    pub fn should_interrupt(&self) -> bool {
+        let mut state = self.state.lock().expect("cancellation lock poisoned");
+        if !state.running || state.cancelled.is_some() {
+            return false;
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            state.cancelled = Some(Cancelled::Interrupted);
+        } else if let (Some(deadline), Some(timeout)) = (state.deadline, self.timeout) {
+            if Instant::now() >= deadline {
+                state.cancelled = Some(Cancelled::TimedOut(timeout));
+            }
+        }
+        state.cancelled.is_some()
+    }
+
+    ///Why the running query was cancelled, if it was
+    pub fn cancelled(&self) -> Option<Cancelled> {
+        self.should_interrupt();
+        self.state
+            .lock()
+            .expect("cancellation lock poisoned")
+            .cancelled
+    }
+
+    ///Fails if the running query has been cancelled, for checking between the steps of a query
+    ///which don't run in SQLite
+    pub fn check(&self) -> Result<()> {
+        match self.cancelled() {
+            Some(cancelled) => Err(QsvError::Cancelled(cancelled)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_interrupts_once_the_timeout_passes() {
+        let cancellation = Cancellation::new(Some(Duration::from_millis(0)));
+        cancellation.start();
+        assert!(cancellation.should_interrupt());
+        assert!(!cancellation.should_interrupt());
+        assert_eq!(
+            cancellation.cancelled(),
+            Some(Cancelled::TimedOut(Duration::from_millis(0)))
+        );
+        assert!(cancellation.check().is_err());
+        cancellation.finish();
+        cancellation.start();
+        cancellation.finish();
+        assert!(!cancellation.should_interrupt());
+        assert_eq!(cancellation.cancelled(), None);
+    }
+
+    #[test]
+    fn it_runs_until_interrupted_without_a_timeout() {
+        let cancellation = Cancellation::new(None);
+        cancellation.start();
+        assert!(!cancellation.should_interrupt());
+        assert!(cancellation.check().is_ok());
+    }
+}
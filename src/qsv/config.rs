@@ -0,0 +1,520 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::debug;
+use rusqlite::types::Value;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use crate::db::plugins::{SqlFunction, SqlFunctionBody};
+use crate::db::DbOptions;
+use crate::error::{QsvError, Result};
+use crate::qsv::util::{parse_duration, parse_pragma, parse_size, parse_table};
+use crate::qsv::OutputFormat;
+
+///The name of the config file for a project, found in the current directory or any above it
+pub const PROJECT_CONFIG_FILE: &str = ".qsv.toml";
+
+///Defaults for the options of every subcommand, read from `~/.config/qsv/config.toml` and the
+///project's `.qsv.toml`. Options given on the command line take precedence over both, and the
+///project's file over the user's:
+///```toml
+///delimiter = ";"
+///output-header = true
+///
+///[pragmas]
+///cache_size = -64000
+///
+///[sources]
+///sales = "/data/sales/*.csv.gz"
+///
+///[functions.full_name]
+///args = ["first", "last"]
+///sql = "first || ' ' || last"
+///```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub delimiter: Option<char>,
+    pub trim: Option<bool>,
+    pub textonly: Option<bool>,
+    pub filename_column: Option<bool>,
+    pub pushdown: Option<bool>,
+    pub vtab: Option<bool>,
+    pub auto_index: Option<bool>,
+    pub normalize_headers: Option<bool>,
+    pub output_header: Option<bool>,
+    #[serde(deserialize_with = "output_format")]
+    pub format: Option<OutputFormat>,
+    #[serde(deserialize_with = "size")]
+    pub memory_limit: Option<u64>,
+    pub temp_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "duration")]
+    pub timeout: Option<Duration>,
+    ///Pragmas set on the database before any given with `--pragma`
+    #[serde(deserialize_with = "pragmas")]
+    pub pragmas: Vec<(String, Value)>,
+    ///Names for files, glob patterns or directories, which queries can read as tables
+    #[serde(deserialize_with = "sources")]
+    pub sources: Vec<(String, String)>,
+    ///SQLite extensions loaded before any given with `--load-extension`
+    pub extensions: Vec<PathBuf>,
+    ///Functions defined in SQL, either scalar functions with `sql` or aggregates with `initial`,
+    ///`step` and `result`
+    #[serde(deserialize_with = "functions")]
+    pub functions: Vec<SqlFunction>,
+    ///The extensions and functions of the project's config, kept apart as they're only used with
+    ///`--allow-project-extensions`
+    #[serde(skip)]
+    project_code: Option<ProjectCode>,
+}
+
+///Extensions and functions from a project's config file. Extensions can run any code, and so can
+///functions by using them, so a `.qsv.toml` in a directory someone else controls can't add them
+///without being allowed to
+#[derive(Debug, PartialEq)]
+struct ProjectCode {
+    path: PathBuf,
+    extensions: Vec<PathBuf>,
+    functions: Vec<SqlFunction>,
+}
+
+///A function in the config file, before it's checked to be either a scalar or an aggregate
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FunctionConfig {
+    #[serde(default)]
+    args: Vec<String>,
+    sql: Option<String>,
+    initial: Option<String>,
+    step: Option<String>,
+    result: Option<String>,
+}
+
+impl Config {
+    ///Reads the user's config file and the project's, if there are any
+    pub fn load() -> Result<Config> {
+        let user = user_config_path()
+            .map(|path| Config::read(&path))
+            .transpose()?
+            .flatten();
+        let project = match std::env::current_dir()
+            .ok()
+            .and_then(|directory| project_config_path(&directory))
+        {
+            Some(path) => Config::read(&path)?.map(|config| config.without_code(path)),
+            None => None,
+        };
+        Ok(user.unwrap_or_default().merge(project.unwrap_or_default()))
+    }
+
+    ///Moves the extensions and functions of a project's config aside, until they're allowed
+    fn without_code(mut self, path: PathBuf) -> Config {
+        if !self.extensions.is_empty() || !self.functions.is_empty() {
+            self.project_code = Some(ProjectCode {
+                path,
+                extensions: std::mem::take(&mut self.extensions),
+                functions: std::mem::take(&mut self.functions),
+            });
+        }
+        self
+    }
+
+    ///Reads a config file, or returns None if there isn't one. Relative paths to sources,
+    ///extensions and the temp dir are relative to the directory of the file, rather than wherever
+    ///qsv is run from
+    fn read(path: &Path) -> Result<Option<Config>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        debug!("Reading config from {:?}", path);
+        let filename = path.to_string_lossy();
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| QsvError::file_load(&filename, e))?;
+        let mut config = Config::parse(&contents).map_err(|e| QsvError::file_load(&filename, e))?;
+        if let Some(directory) = path.parent() {
+            config.sources = config
+                .sources
+                .into_iter()
+                .map(|(name, source)| {
+                    let source = directory.join(source).to_string_lossy().into_owned();
+                    (name, source)
+                })
+                .collect();
+            config.extensions = config
+                .extensions
+                .into_iter()
+                .map(|extension| directory.join(extension))
+                .collect();
+            config.temp_dir = config.temp_dir.map(|temp_dir| directory.join(temp_dir));
+        }
+        Ok(Some(config))
+    }
+
+    fn parse(contents: &str) -> std::result::Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    ///Combines this config with one which takes precedence over it. Pragmas from both are set,
+    ///and sources from both are available, with `other`'s sources replacing those of the same name
+    pub fn merge(self, other: Config) -> Config {
+        let mut sources: Vec<(String, String)> = self
+            .sources
+            .into_iter()
+            .filter(|(name, _)| {
+                !other
+                    .sources
+                    .iter()
+                    .any(|(o, _)| o.eq_ignore_ascii_case(name))
+            })
+            .collect();
+        sources.extend(other.sources);
+        let mut pragmas = self.pragmas;
+        pragmas.extend(other.pragmas);
+        let mut extensions = self.extensions;
+        extensions.extend(other.extensions);
+        let functions = merge_functions(self.functions, other.functions);
+        Config {
+            delimiter: other.delimiter.or(self.delimiter),
+            trim: other.trim.or(self.trim),
+            textonly: other.textonly.or(self.textonly),
+            filename_column: other.filename_column.or(self.filename_column),
+            pushdown: other.pushdown.or(self.pushdown),
+            vtab: other.vtab.or(self.vtab),
+            auto_index: other.auto_index.or(self.auto_index),
+            normalize_headers: other.normalize_headers.or(self.normalize_headers),
+            output_header: other.output_header.or(self.output_header),
+            format: other.format.or(self.format),
+            memory_limit: other.memory_limit.or(self.memory_limit),
+            temp_dir: other.temp_dir.or(self.temp_dir),
+            timeout: other.timeout.or(self.timeout),
+            pragmas,
+            sources,
+            extensions,
+            functions,
+            project_code: other.project_code.or(self.project_code),
+        }
+    }
+
+    ///The database settings, with those given on the command line taking precedence. The project's
+    ///extensions and functions are an error unless they're allowed
+    pub fn database(
+        &self,
+        pragmas: Vec<(String, Value)>,
+        memory_limit: Option<u64>,
+        temp_dir: Option<PathBuf>,
+        extensions: Vec<PathBuf>,
+        allow_project_extensions: bool,
+    ) -> Result<DbOptions> {
+        let mut all_pragmas = self.pragmas.clone();
+        all_pragmas.extend(pragmas);
+        let mut all_extensions = self.extensions.clone();
+        let mut functions = self.functions.clone();
+        if let Some(code) = &self.project_code {
+            if !allow_project_extensions {
+                return Err(QsvError::Usage(format!(
+                    "{} loads extensions or defines functions, which can run any code, so they're only used with --allow-project-extensions",
+                    code.path.display()
+                )));
+            }
+            all_extensions.extend(code.extensions.iter().cloned());
+            functions = merge_functions(functions, code.functions.clone());
+        }
+        all_extensions.extend(extensions);
+        Ok(DbOptions {
+            pragmas: all_pragmas,
+            memory_limit: memory_limit.or(self.memory_limit),
+            temp_dir: temp_dir.or_else(|| self.temp_dir.clone()),
+            extensions: all_extensions,
+            functions,
+        })
+    }
+
+    ///The named sources along with the tables given on the command line, which replace sources
+    ///of the same name
+    pub fn tables(&self, tables: Vec<(String, String)>) -> Vec<(String, String)> {
+        let mut all_tables: Vec<(String, String)> = self
+            .sources
+            .iter()
+            .filter(|(name, _)| !tables.iter().any(|(t, _)| t.eq_ignore_ascii_case(name)))
+            .cloned()
+            .collect();
+        all_tables.extend(tables);
+        all_tables
+    }
+}
+
+///Functions along with others which replace those of the same name
+fn merge_functions(functions: Vec<SqlFunction>, others: Vec<SqlFunction>) -> Vec<SqlFunction> {
+    let mut merged: Vec<SqlFunction> = functions
+        .into_iter()
+        .filter(|function| {
+            !others
+                .iter()
+                .any(|o| o.name.eq_ignore_ascii_case(&function.name))
+        })
+        .collect();
+    merged.extend(others);
+    merged
+}
+
+///`$XDG_CONFIG_HOME/qsv/config.toml`, or `~/.config/qsv/config.toml` without it
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("qsv").join("config.toml"))
+}
+
+///The closest `.qsv.toml` in a directory or any directory above it
+fn project_config_path(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+fn output_format<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<OutputFormat>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|format| format.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+fn size<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<u64>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|size| parse_size(&size).map_err(D::Error::custom))
+        .transpose()
+}
+
+fn duration<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|duration| parse_duration(&duration).map_err(D::Error::custom))
+        .transpose()
+}
+
+///Pragmas are validated and typed the same way as those given with `--pragma`
+fn pragmas<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<(String, Value)>, D::Error> {
+    BTreeMap::<String, toml::Value>::deserialize(d)?
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Boolean(b) => (b as i64).to_string(),
+                other => {
+                    return Err(D::Error::custom(format!(
+                        "Pragma {} can't be set to {}",
+                        name, other
+                    )))
+                }
+            };
+            parse_pragma(&format!("{}={}", name, value)).map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+///Sources are named the same way as tables given with `--table`
+fn sources<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Vec<(String, String)>, D::Error> {
+    BTreeMap::<String, String>::deserialize(d)?
+        .into_iter()
+        .map(|(name, path)| parse_table(&format!("{}={}", name, path)).map_err(D::Error::custom))
+        .collect()
+}
+
+///Functions are scalar functions if they have `sql`, or aggregates if they have `step`
+fn functions<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<SqlFunction>, D::Error> {
+    BTreeMap::<String, FunctionConfig>::deserialize(d)?
+        .into_iter()
+        .map(|(name, function)| {
+            let body = match function {
+                FunctionConfig {
+                    sql: Some(sql),
+                    initial: None,
+                    step: None,
+                    result: None,
+                    ..
+                } => SqlFunctionBody::Scalar(sql),
+                FunctionConfig {
+                    sql: None,
+                    step: Some(step),
+                    initial,
+                    result,
+                    ..
+                } => SqlFunctionBody::Aggregate {
+                    initial: initial.unwrap_or_else(|| String::from("NULL")),
+                    step,
+                    result: result.unwrap_or_else(|| String::from("state")),
+                },
+                _ => {
+                    return Err(D::Error::custom(format!(
+                        "Function {} needs either sql, or a step and optionally initial and result",
+                        name
+                    )))
+                }
+            };
+            SqlFunction::new(&name, function.args, body).map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_options_pragmas_and_sources() {
+        let config = Config::parse(
+            r#"
+delimiter = ";"
+output-header = true
+format = "json"
+memory-limit = "4GB"
+timeout = "30s"
+
+[pragmas]
+cache_size = -64000
+foreign_keys = true
+
+[sources]
+sales = "/data/sales/*.csv.gz"
+
+[functions.total]
+args = ["x"]
+step = "coalesce(state, 0) + x"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.delimiter, Some(';'));
+        assert_eq!(config.output_header, Some(true));
+        assert_eq!(config.trim, None);
+        assert_eq!(config.format, Some(OutputFormat::Json));
+        assert_eq!(config.memory_limit, Some(4 << 30));
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(
+            config.pragmas,
+            vec![
+                (String::from("cache_size"), Value::Integer(-64000)),
+                (String::from("foreign_keys"), Value::Integer(1))
+            ]
+        );
+        assert_eq!(
+            config.sources,
+            vec![(String::from("sales"), String::from("/data/sales/*.csv.gz"))]
+        );
+        assert_eq!(
+            config.functions,
+            vec![SqlFunction::new(
+                "total",
+                vec![String::from("x")],
+                SqlFunctionBody::Aggregate {
+                    initial: String::from("NULL"),
+                    step: String::from("coalesce(state, 0) + x"),
+                    result: String::from("state"),
+                }
+            )
+            .unwrap()]
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_options_and_invalid_values() {
+        assert!(Config::parse("delimeter = \";\"").is_err());
+        assert!(Config::parse("timeout = \"soon\"").is_err());
+        assert!(Config::parse("[sources]\n\"my-sales\" = \"sales.csv\"").is_err());
+        assert!(Config::parse("[pragmas]\n\"cache size\" = 1").is_err());
+        assert!(Config::parse("[functions.f]\nsql = \"1\"\nstep = \"state\"").is_err());
+        assert!(Config::parse("[functions.f]\nargs = [\"x y\"]\nsql = \"1\"").is_err());
+    }
+
+    #[test]
+    fn it_only_uses_the_projects_extensions_and_functions_when_allowed() {
+        let user = Config::parse("extensions = [\"/lib/user.so\"]").unwrap();
+        let project = Config::parse(
+            "extensions = [\"/lib/project.so\"]\n[functions.double]\nargs = [\"x\"]\nsql = \"x * 2\"",
+        )
+        .unwrap()
+        .without_code(PathBuf::from("/work/.qsv.toml"));
+        let config = user.merge(project);
+        let error = config
+            .database(vec![], None, None, vec![], false)
+            .unwrap_err();
+        assert!(error.to_string().contains("/work/.qsv.toml"), "{}", error);
+        let database = config
+            .database(vec![], None, None, vec![PathBuf::from("given.so")], true)
+            .unwrap();
+        assert_eq!(
+            database.extensions,
+            vec![
+                PathBuf::from("/lib/user.so"),
+                PathBuf::from("/lib/project.so"),
+                PathBuf::from("given.so")
+            ]
+        );
+        assert_eq!(database.functions.len(), 1);
+        let user_only = Config::parse("extensions = [\"/lib/user.so\"]").unwrap();
+        assert!(user_only
+            .merge(
+                Config::parse("trim = true")
+                    .unwrap()
+                    .without_code(PathBuf::new())
+            )
+            .database(vec![], None, None, vec![], false)
+            .is_ok());
+    }
+
+    #[test]
+    fn it_finds_paths_relative_to_the_config_file() {
+        let directory =
+            std::env::temp_dir().join(format!("qsv_config_extensions_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(PROJECT_CONFIG_FILE);
+        std::fs::write(
+            &path,
+            "extensions = [\"lib/a.so\", \"/usr/lib/b.so\"]\ntemp-dir = \"tmp\"\n[sources]\nsales = \"data/*.csv\"\nusers = \"/data/users.csv\"",
+        )
+        .unwrap();
+        let config = Config::read(&path).unwrap().unwrap();
+        assert_eq!(
+            config.extensions,
+            vec![directory.join("lib/a.so"), PathBuf::from("/usr/lib/b.so")]
+        );
+        assert_eq!(config.temp_dir, Some(directory.join("tmp")));
+        assert_eq!(
+            config.sources,
+            vec![
+                (
+                    String::from("sales"),
+                    directory.join("data/*.csv").to_string_lossy().into_owned()
+                ),
+                (String::from("users"), String::from("/data/users.csv"))
+            ]
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_prefers_the_project_config_and_then_the_command_line() {
+        let user = Config::parse(
+            "delimiter = \";\"\ntrim = true\n[sources]\na = \"a.csv\"\nb = \"b.csv\"",
+        )
+        .unwrap();
+        let project = Config::parse("delimiter = \"|\"\n[sources]\nB = \"other.csv\"").unwrap();
+        let config = user.merge(project);
+        assert_eq!(config.delimiter, Some('|'));
+        assert_eq!(config.trim, Some(true));
+        assert_eq!(
+            config.tables(vec![(String::from("a"), String::from("given.csv"))]),
+            vec![
+                (String::from("B"), String::from("other.csv")),
+                (String::from("a"), String::from("given.csv"))
+            ]
+        );
+    }
+}
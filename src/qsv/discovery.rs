@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::error::{QsvError, Result};
+use crate::qsv::util::{remove_extension, sanitize};
+
+///The value Hive uses for a partition key which is NULL
+const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+const GLOB_CHARACTERS: &[char] = &['*', '?', '['];
+///Extensions of the files picked up when a whole directory is loaded as a table, which are also
+///left out of the names of tables loaded from files
+const DIRECTORY_EXTENSIONS: &[&str] = &["csv", "tsv", "txt", "gz"];
+
+///Whether a table identifier is a glob pattern such as `logs/*.csv` rather than a single path
+pub fn is_glob(identifier: &str) -> bool {
+    identifier.contains(GLOB_CHARACTERS)
+}
+
+///Whether a table identifier refers to a directory. Only identifiers ending in a path separator,
+///such as `logs/`, count, so CTEs and tables which happen to share a directory's name still work
+pub fn is_directory(identifier: &str) -> bool {
+    identifier.ends_with(std::path::is_separator) && Path::new(identifier).is_dir()
+}
+
+///Finds the files a table identifier refers to: the file itself, every file matching a glob
+///pattern, or every CSV file under a directory, in sorted order.
+///Returns None if the identifier matches no files
+pub fn discover_files(identifier: &str) -> Result<Option<Vec<String>>> {
+    let path = Path::new(identifier);
+    let mut files = if path.is_file() {
+        return Ok(Some(vec![String::from(identifier)]));
+    } else if is_directory(identifier) {
+        let mut files = vec![];
+        walk_directory(path, &mut files).map_err(|e| QsvError::file_load(identifier, e))?;
+        files
+    } else if is_glob(identifier) {
+        let paths = glob::glob(identifier).map_err(|e| QsvError::file_load(identifier, e))?;
+        let mut files = vec![];
+        for path in paths {
+            let path = path.map_err(|e| QsvError::file_load(identifier, e))?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        files
+    } else {
+        return Ok(None);
+    };
+    files.sort();
+    debug!("Identifier {} matched files: {:?}", identifier, files);
+    if files.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        files
+            .iter()
+            .map(|file| file.to_string_lossy().into_owned())
+            .collect(),
+    ))
+}
+
+fn walk_directory(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            walk_directory(&path, files)?;
+        } else if has_directory_extension(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn has_directory_extension(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| DIRECTORY_EXTENSIONS.contains(&extension.as_str()))
+}
+
+///Hive-style partition keys and values in the directories of a file's path, so
+///`logs/year=2026/month=10/part.csv` has a year of 2026 and a month of 10. NULL partitions are
+///left out, leaving the column NULL for the file's rows
+pub fn partitions(file: &str) -> Vec<(String, String)> {
+    let parent = match Path::new(file).parent() {
+        Some(parent) => parent,
+        None => return vec![],
+    };
+    let mut partitions: Vec<(String, String)> = vec![];
+    for component in parent.components() {
+        let component = component.as_os_str().to_string_lossy();
+        let mut split = component.splitn(2, '=');
+        if let (Some(key), Some(value)) = (split.next(), split.next()) {
+            if key.is_empty() || value == NULL_PARTITION {
+                continue;
+            }
+            // a key repeated deeper in the path takes precedence
+            partitions.retain(|(existing, _)| existing != key);
+            partitions.push((String::from(key), String::from(value)));
+        }
+    }
+    partitions
+}
+
+///The name a file, glob pattern or directory is loaded as when no table name is given.
+///Files are named after the file without its extension, directories after the directory, and
+///glob patterns after the directory the pattern starts in, so `logs/*.csv` becomes `logs`
+pub fn default_table_name(identifier: &str) -> Option<String> {
+    let path = Path::new(identifier);
+    if is_glob(identifier) {
+        let prefix: PathBuf = path
+            .components()
+            .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+            .collect();
+        sanitize(
+            prefix
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        )
+    } else if is_directory(identifier) {
+        sanitize(
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        )
+    } else {
+        sanitize(remove_extension(path, DIRECTORY_EXTENSIONS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_discovers_a_single_file() {
+        assert_eq!(
+            discover_files("testdata/people.csv").unwrap(),
+            Some(vec![String::from("testdata/people.csv")])
+        );
+    }
+
+    #[test]
+    fn it_discovers_files_matching_a_glob() {
+        assert_eq!(
+            discover_files("testdata/logs/2026-10-0[12].csv").unwrap(),
+            Some(vec![
+                String::from("testdata/logs/2026-10-01.csv"),
+                String::from("testdata/logs/2026-10-02.csv"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_discovers_files_in_a_directory() {
+        assert_eq!(
+            discover_files("testdata/logs/").unwrap(),
+            Some(vec![
+                String::from("testdata/logs/2026-10-01.csv"),
+                String::from("testdata/logs/2026-10-02.csv"),
+                String::from("testdata/logs/2026-10-03.csv"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_discovers_nothing_for_identifiers_which_match_no_files() {
+        assert_eq!(discover_files("testdata/logs").unwrap(), None);
+        assert_eq!(discover_files("testdata/nothing_*.csv").unwrap(), None);
+        assert_eq!(discover_files("people").unwrap(), None);
+    }
+
+    #[test]
+    fn it_finds_partitions_in_paths() {
+        assert_eq!(
+            partitions("sales/year=2026/month=10/part.csv"),
+            vec![
+                (String::from("year"), String::from("2026")),
+                (String::from("month"), String::from("10")),
+            ]
+        );
+        assert_eq!(
+            partitions("sales/year=__HIVE_DEFAULT_PARTITION__/region=eu/part.csv"),
+            vec![(String::from("region"), String::from("eu"))]
+        );
+        assert_eq!(partitions("year=2026.csv"), vec![]);
+    }
+
+    #[test]
+    fn it_names_tables() {
+        assert_eq!(
+            default_table_name("testdata/people.csv"),
+            Some(String::from("people"))
+        );
+        assert_eq!(
+            default_table_name("testdata/logs/"),
+            Some(String::from("logs"))
+        );
+        assert_eq!(
+            default_table_name("testdata/logs/2026-10-*.csv"),
+            Some(String::from("logs"))
+        );
+        assert_eq!(
+            default_table_name("data/a.v1.csv"),
+            Some(String::from("a_v1"))
+        );
+        assert_eq!(default_table_name("*.csv"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::db::{Parameters, PlanStep};
+use crate::error::Result;
+use crate::qsv::session::Session;
+use crate::qsv::Options;
+
+///A table a statement reads from files, along with the columns and types it was created with
+#[derive(Debug)]
+pub struct TableSchema {
+    pub identifier: String,
+    pub table_name: String,
+    pub columns: Vec<(String, String)>,
+}
+
+///How a statement runs: the statement as given, the statement SQLite runs once the tables its
+///files were loaded as are substituted in, the schema of those tables and SQLite's query plan
+#[derive(Debug)]
+pub struct Explanation {
+    pub statement: String,
+    pub rewritten: String,
+    pub tables: Vec<TableSchema>,
+    pub plan: Vec<PlanStep>,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Statement: {}", self.statement)?;
+        writeln!(f, "Rewritten: {}", self.rewritten)?;
+        writeln!(f, "Tables:")?;
+        for table in self.tables.iter() {
+            writeln!(f, "\t{} as {}", table.identifier, table.table_name)?;
+            for (column, column_type) in table.columns.iter() {
+                writeln!(f, "\t\t{} -> {}", column, column_type)?;
+            }
+        }
+        writeln!(f, "Query plan:")?;
+        // steps are listed after the step they're part of, so its depth is always known
+        let mut depths: HashMap<i64, usize> = HashMap::new();
+        for step in self.plan.iter() {
+            let depth = depths.get(&step.parent).map_or(1, |depth| depth + 1);
+            depths.insert(step.id, depth);
+            writeln!(f, "{}{}", "\t".repeat(depth), step.detail)?;
+        }
+        Ok(())
+    }
+}
+
+///Explains how each statement in a query or script runs, without running its SELECT statements
+pub fn execute_explain(
+    query: &str,
+    options: &Options,
+    parameters: Parameters,
+) -> Result<Vec<Explanation>> {
+    let mut session = Session::new(options.clone())?;
+    for (name, value) in parameters {
+        session.set_parameter(name, value);
+    }
+    session.explain(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_indents_the_query_plan_by_depth() {
+        let step = |id, parent, detail: &str| PlanStep {
+            id,
+            parent,
+            detail: String::from(detail),
+        };
+        let explanation = Explanation {
+            statement: String::from("select * from a.csv where id in (select id from b.csv)"),
+            rewritten: String::from("SELECT * FROM a WHERE id IN (SELECT id FROM b)"),
+            tables: vec![TableSchema {
+                identifier: String::from("a.csv"),
+                table_name: String::from("a"),
+                columns: vec![(String::from("id"), String::from("integer"))],
+            }],
+            plan: vec![
+                step(2, 0, "SEARCH a USING INDEX qsv_a_id (id=?)"),
+                step(6, 0, "LIST SUBQUERY 1"),
+                step(8, 6, "SCAN b"),
+            ],
+        };
+        assert_eq!(
+            explanation.to_string(),
+            "Statement: select * from a.csv where id in (select id from b.csv)
+Rewritten: SELECT * FROM a WHERE id IN (SELECT id FROM b)
+Tables:
+\ta.csv as a
+\t\tid -> integer
+Query plan:
+\tSEARCH a USING INDEX qsv_a_id (id=?)
+\tLIST SUBQUERY 1
+\t\tSCAN b
+"
+        );
+    }
+}
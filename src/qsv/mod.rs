@@ -1,76 +1,283 @@
-use std::error::Error;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
+use csv::{StringRecord, Trim};
 use flate2::read::GzDecoder;
-use log::error;
+use log::{debug, error};
+use rayon::prelude::*;
+use rusqlite::types::Value;
 
 pub use analysis::execute_analysis;
+pub use cancel::{catch_interrupts, Cancelled};
+pub use config::Config;
+pub use explain::execute_explain;
 pub use query::execute_query;
+pub use shell::run_shell;
 pub use statistics::execute_statistics;
+pub use util::{
+    parse_duration, parse_index, parse_parameter, parse_pragma, parse_size, parse_table,
+};
 
 use crate::csv::csv_data::{CsvData, CsvStream};
-use crate::db::Rows;
+use crate::csv::inference::ColumnInference;
+use crate::db::utils::normalize_headers;
+use crate::db::{DbOptions, Rows};
+use crate::error::{QsvError, Result};
+use crate::qsv::discovery::partitions;
 
 mod analysis;
+mod cancel;
+mod config;
+mod discovery;
+mod explain;
 mod query;
+mod session;
+mod shell;
 mod statistics;
 mod util;
 
+#[derive(Clone)]
 pub struct Options {
     pub delimiter: char,
     pub trim: bool,
     pub textonly: bool,
+    ///Adds a `_filename` column to tables loaded from files, holding the file each row came from
+    pub filename_column: bool,
+    ///Only loads the files, columns and rows a query can use, rather than whole files which later
+    ///queries can reuse
+    pub pushdown: bool,
+    ///Reads plain CSV files in place through a virtual table, rather than importing them
+    pub vtab: bool,
+    ///Indexes the columns a statement's joins and WHERE clauses compare once their tables are
+    ///loaded
+    pub auto_index: bool,
+    ///Indexes to create once a table is loaded, as the table's identifier and the columns to index
+    pub indexes: Vec<(String, Vec<String>)>,
+    ///Pragmas, the memory limit and where temporary files go for the database tables are loaded
+    ///into
+    pub database: DbOptions,
+    ///How long a query can run before it's cancelled
+    pub timeout: Option<Duration>,
+    ///Names registered with `--table` for files, which queries can read by name, along with the
+    ///file, glob pattern or directory each one is loaded from
+    pub tables: Vec<(String, String)>,
+    ///Renames the columns of tables loaded from files to snake_case identifiers
+    pub normalize_headers: bool,
+    ///Gives columns which were renamed their original names again in query results
+    pub original_headers: bool,
 }
 
-fn csv_data_from_mime_type(
-    filename: &str,
-    mime_type: &str,
-    options: &Options,
-) -> Result<CsvData, Box<dyn Error>> {
+///The name of the column holding the file each row was loaded from
+pub const FILENAME_COLUMN: &str = "_filename";
+
+fn csv_data_from_mime_type(filename: &str, mime_type: &str, options: &Options) -> Result<CsvData> {
     if mime_type == "application/gzip" {
-        let reader = File::open(filename)?;
+        let reader = File::open(filename).map_err(|e| QsvError::file_load(filename, e))?;
         let d = GzDecoder::new(reader);
         CsvData::from_reader(d, filename, options.delimiter, options.trim)
     } else if mime_type == "text/plain" {
         CsvData::from_filename(filename, options.delimiter, options.trim)
     } else {
-        let error_format = format!("Unsupported MIME type {} for file {}", mime_type, filename);
-        error!("{}", error_format);
-        Err(error_format.into())
+        let error_format = format!("Unsupported MIME type {}", mime_type);
+        error!("{} for file {}", error_format, filename);
+        Err(QsvError::file_load(filename, error_format))
+    }
+}
+
+///Loads every file making up a single table, parsing the files in parallel
+fn load_csv_files(files: &[String], options: &Options) -> Result<Vec<CsvData>> {
+    files
+        .par_iter()
+        .map(|filename| {
+            let mime_type = tree_magic::from_filepath(Path::new(filename));
+            debug!("File '{}' has MIME type: '{}'", filename, mime_type);
+            csv_data_from_mime_type(filename, mime_type.as_str(), options)
+        })
+        .collect()
+}
+
+///Renames the columns of each file to snake_case identifiers, returning the columns which were
+///renamed along with their original names
+fn normalize_columns(csvs: &mut [CsvData]) -> Vec<(String, String)> {
+    let mut renamed: Vec<(String, String)> = vec![];
+    for csv in csvs.iter_mut() {
+        let normalized = normalize_headers(&csv.headers);
+        for (column, original) in normalized.iter().zip(csv.headers.iter()) {
+            if column != original && !renamed.iter().any(|(other, _)| other == column) {
+                renamed.push((column.to_string(), original.to_string()));
+            }
+        }
+        csv.headers = normalized;
     }
+    renamed
+}
+
+///Reads the header row of a plain CSV file
+fn read_headers(filename: &str, options: &Options) -> Result<StringRecord> {
+    let trim = if options.trim { Trim::All } else { Trim::None };
+    csv::ReaderBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .trim(trim)
+        .from_path(filename)
+        .and_then(|mut reader| reader.headers().cloned())
+        .map_err(|e| QsvError::file_load(filename, e))
+}
+
+///Infers the columns of one or more files loaded as a single table. Columns are the union of
+///those in each file, and a column inferred differently in two files gets the wider type
+fn infer_columns(csvs: &[CsvData], options: &Options) -> ColumnInference {
+    let inferences: Vec<ColumnInference> = csvs
+        .par_iter()
+        .map(|csv| {
+            if options.textonly {
+                ColumnInference::default_inference(&csv.headers)
+            } else {
+                ColumnInference::from_csv(csv)
+            }
+        })
+        .collect();
+    let mut inference = ColumnInference::default_inference(&StringRecord::new());
+    for other in inferences.iter() {
+        inference.merge(other);
+    }
+    inference
+}
+
+///Infers the types of the Hive-style partition columns of a set of files
+fn infer_partitions(files: &[String], options: &Options) -> ColumnInference {
+    let partitions: Vec<CsvData> = files
+        .iter()
+        .map(|file| {
+            let (keys, values): (Vec<String>, Vec<String>) = partitions(file).into_iter().unzip();
+            CsvData {
+                records: vec![StringRecord::from(values)],
+                headers: StringRecord::from(keys),
+                filename: file.clone(),
+            }
+        })
+        .collect();
+    infer_columns(&partitions, options)
 }
 
-trait ReadAndSeek: std::io::Read + std::io::Seek {}
 fn csv_stream_from_mime_type(
     filename: &str,
     mime_type: &str,
     options: &Options,
-) -> Result<CsvStream<File>, Box<dyn Error>> {
+) -> Result<CsvStream<File>> {
     if mime_type == "text/plain" {
-        let reader = File::open(filename)?;
+        let reader = File::open(filename).map_err(|e| QsvError::file_load(filename, e))?;
         CsvStream::from_reader(reader, filename, options.delimiter, options.trim)
     } else {
-        let error_format = format!("Unsupported MIME type {} for file {}", mime_type, filename);
-        error!("{}", error_format);
-        Err(error_format.into())
+        let error_format = format!("Unsupported MIME type {}", mime_type);
+        error!("{} for file {}", error_format, filename);
+        Err(QsvError::file_load(filename, error_format))
+    }
+}
+
+///The formats results can be written out in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "Unknown output format '{}', expected csv or json",
+                s
+            )),
+        }
+    }
+}
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Csv => f.write_str("csv"),
+            OutputFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("null"),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(s) => s.clone(),
+        Value::Blob(b) => String::from_utf8_lossy(b).to_string(),
+    }
+}
+
+///Text in a column holding JSON, such as the result of `json_group_array`, is written as JSON
+///rather than as a string. Any other text is a string, even if it looks like JSON
+fn json_value(value: &Value, is_json: bool) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => serde_json::Value::from(*i),
+        Value::Real(f) => serde_json::Value::from(*f),
+        Value::Text(s) if is_json => {
+            serde_json::from_str(s).unwrap_or_else(|_| serde_json::Value::from(s.as_str()))
+        }
+        Value::Text(s) => serde_json::Value::from(s.as_str()),
+        Value::Blob(b) => serde_json::Value::from(String::from_utf8_lossy(b).to_string()),
     }
 }
 
 ///Writes a set of rows to STDOUT
-pub fn write_to_stdout(results: Rows) -> Result<(), Box<dyn Error>> {
+pub fn write_to_stdout(results: Rows) -> Result<()> {
     let stdout = std::io::stdout();
     let lock = stdout.lock();
     let mut buf = std::io::BufWriter::new(lock);
     for result in results {
-        buf.write_all(result.join(",").as_str().as_bytes())?;
+        let line: Vec<String> = result.iter().map(format_value).collect();
+        buf.write_all(line.join(",").as_bytes())?;
         buf.write_all(b"\n")?;
     }
     Ok(())
 }
 
+///Writes a set of rows to STDOUT as a JSON array with one object per row, keyed by the header.
+///The values of the columns marked in `json` are written as the JSON they hold
+pub fn write_to_stdout_as_json(results: Rows, header: &[String], json: &[bool]) -> Result<()> {
+    let stdout = std::io::stdout();
+    let lock = stdout.lock();
+    let mut buf = std::io::BufWriter::new(lock);
+    buf.write_all(b"[")?;
+    for (i, result) in results.iter().enumerate() {
+        if i > 0 {
+            buf.write_all(b",\n")?;
+        }
+        let fields: Vec<String> = header
+            .iter()
+            .zip(result.iter())
+            .enumerate()
+            .map(|(index, (column, value))| {
+                format!(
+                    "{}:{}",
+                    serde_json::Value::from(column.as_str()),
+                    json_value(value, json.get(index).copied().unwrap_or(false))
+                )
+            })
+            .collect();
+        buf.write_all(b"{")?;
+        buf.write_all(fields.join(",").as_bytes())?;
+        buf.write_all(b"}")?;
+    }
+    buf.write_all(b"]\n")?;
+    Ok(())
+}
+
 ///Writes a set of rows to STDOUT, with the header included
-pub fn write_to_stdout_with_header(results: Rows, header: &[String]) -> Result<(), Box<dyn Error>> {
+pub fn write_to_stdout_with_header(results: Rows, header: &[String]) -> Result<()> {
     let header = header.join(",");
     println!("{}", header);
     write_to_stdout(results)
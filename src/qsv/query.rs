@@ -1,103 +1,146 @@
-use std::collections::HashMap;
-use std::error::Error;
-use std::path::Path;
+use crate::db::Parameters;
+use crate::error::Result;
+use crate::qsv::session::{Output, Session};
+use crate::qsv::Options;
 
-use log::debug;
-use uuid::Uuid;
+///Executes a query or script of several statements, passing the Rows of each SELECT statement to
+///`output` as soon as it has run, with which of its columns hold JSON
+pub fn execute_query(
+    query: &str,
+    options: &Options,
+    parameters: Parameters,
+    output: &mut Output,
+) -> Result<()> {
+    let mut session = Session::new(options.clone())?;
+    for (name, value) in parameters {
+        session.set_parameter(name, value);
+    }
+    session.query(query, output)
+}
 
-use crate::csv::inference::ColumnInference;
-use crate::db::utils::to_table_parameters;
-use crate::db::{Db, Header, Rows};
-use crate::parser::collector::Collector;
-use crate::parser::rewriter::Rewriter;
-use crate::parser::Parser;
-use crate::qsv::util::{remove_extension, sanitize};
-use crate::qsv::{csv_data_from_mime_type, Options};
+#[cfg(test)]
+mod tests {
+    use std::fs;
 
-///Executes a query, possibly returning Rows
-pub fn execute_query(query: &str, options: &Options) -> Result<(Header, Rows), Box<dyn Error>> {
-    let mut collector = Collector::new();
+    use rusqlite::types::Value;
 
-    let ast = Parser::parse_sql(query)?;
-    let mut db = Db::open_in_memory()?;
-    if ast.len() != 1 {
-        return Err("Expected exactly one SQL statement in query input".into());
-    }
-    let statement = &ast[0];
+    use super::*;
+    use crate::db::DbOptions;
 
-    collector.collect(statement); //TODO: should we handle multiple SQL statements later?
-    let mut files_to_tables = HashMap::new();
-    for filename in collector.table_identifiers.iter() {
-        let maybe_load = maybe_load_file(&mut files_to_tables, filename, &mut db, options);
-        match maybe_load {
-            Ok(Some(())) => {
-                debug!(
-                    "Potential filename from SQL was able to be loaded: {}",
-                    filename
-                );
-            }
-            Ok(None) => {
-                debug!(
-                    "Identifier in SQL could not be loaded as file, as it didn't exist: {}",
-                    filename
-                );
+    ///Pieces of SQL which would end an identifier or string early, or start another statement, if
+    ///a header or filename containing them wasn't quoted properly
+    const FRAGMENTS: &[&str] = &[
+        "\"",
+        "'",
+        "`",
+        "[",
+        "]",
+        ";",
+        "--",
+        "/*",
+        "*/",
+        "(",
+        ")",
+        ",",
+        " ",
+        "\\",
+        "\t",
+        "?",
+        ":p",
+        "$p",
+        "@p",
+        "drop table adversarial",
+        "\";select load_extension(\"foo\")",
+        "é",
+        "x",
+    ];
+
+    ///A small xorshift generator, so the same names are generated on every run
+    struct Names(u64);
+
+    impl Names {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        ///A name made of a few fragments, ending in `i` so names are never the same
+        fn name(&mut self, i: usize) -> String {
+            let mut name = String::new();
+            for _ in 0..1 + self.next() % 5 {
+                name.push_str(FRAGMENTS[self.next() % FRAGMENTS.len()]);
             }
-            Err(e) => return Err(e),
+            format!("{}_{}", name, i)
         }
     }
-    let rewritten = Rewriter::new(files_to_tables);
-    let mut to_rewrite = statement.clone();
-    rewritten.rewrite(&mut to_rewrite);
-    debug!("Rewritten query: {}", to_rewrite.to_string());
-    db.select_statement(to_rewrite.to_string().as_str())
-}
 
-fn maybe_load_file(
-    files_to_tables: &mut HashMap<String, String>,
-    filename: &str,
-    db: &mut Db,
-    options: &Options,
-) -> Result<Option<()>, Box<dyn Error>> {
-    let path = Path::new(filename);
-    if !path.exists() {
-        return Ok(None);
+    fn options(path: &str, vtab: bool) -> Options {
+        Options {
+            delimiter: ',',
+            trim: false,
+            textonly: false,
+            filename_column: false,
+            pushdown: false,
+            vtab,
+            auto_index: false,
+            indexes: vec![],
+            database: DbOptions::default(),
+            timeout: None,
+            tables: vec![(String::from("adversarial"), String::from(path))],
+            normalize_headers: false,
+            original_headers: false,
+        }
     }
-    let mime_type = tree_magic::from_filepath(path);
-    debug!("File '{}' has MIME type: '{}'", filename, mime_type);
-    let csv = csv_data_from_mime_type(filename, mime_type.as_str(), options)?;
-    let path = Path::new(filename);
-    debug!(
-        "Attempting to load identifier from SQL as file: {}",
-        filename
-    );
-    let without_extension = remove_extension(path);
-    let table_name = sanitize(without_extension)
-        .unwrap_or_else(|| String::from("t") + &Uuid::new_v4().as_u128().to_string());
-    let inference = if options.textonly {
-        ColumnInference::default_inference(&csv.headers)
-    } else {
-        ColumnInference::from_csv(&csv)
-    };
-    let table_parameters = to_table_parameters(&csv, &inference);
-    let table_parameters: Vec<&str> = table_parameters.iter().map(|s| s.as_str()).collect();
-    let table_name = table_name.as_str();
 
-    if !files_to_tables.values().any(|s| s == table_name) {
-        debug!(
-            "Attempting to create table {} for filename {}",
-            table_name, filename
-        );
-        db.create_table(table_name, &table_parameters)?;
-        let headers: Vec<&str> = csv.headers.iter().collect();
-        let records: Vec<Vec<&str>> = csv.records.iter().map(|r| r.iter().collect()).collect();
-        debug!("Inserting {} rows into {}", records.len(), table_name);
-        db.insert(table_name, &headers, records);
-        files_to_tables.insert(filename.to_string(), String::from(table_name));
-    } else {
-        debug!(
-            "Table already exists {} for filename {}, not creating it or inserting records",
-            table_name, filename
-        );
+    #[test]
+    fn adversarial_headers_and_filenames_never_run_other_statements() {
+        let directory = std::env::temp_dir().join(format!("qsv_injection_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let mut names = Names(0x2545_f491_4f6c_dd1d);
+        for case in 0..40 {
+            // files are recognised as CSV by their contents, so they start the same way
+            let mut headers = vec![String::from("id")];
+            headers.extend((0..3).map(|i| names.name(i)));
+            let filename = names.name(case).replace('/', "|");
+            let path = directory.join(format!("{}.csv", filename));
+            let mut writer = csv::Writer::from_path(&path).unwrap();
+            writer.write_record(&headers).unwrap();
+            writer.write_record(["0", "1", "x", "2.5"]).unwrap();
+            writer.flush().unwrap();
+            for vtab in [false, true].iter() {
+                let mut results = vec![];
+                execute_query(
+                    "select * from adversarial; select type, name from sqlite_master",
+                    &options(path.to_str().unwrap(), *vtab),
+                    vec![],
+                    &mut |header, rows, _| {
+                        results.push((header, rows));
+                        Ok(())
+                    },
+                )
+                .unwrap_or_else(|e| panic!("{:?} with headers {:?}: {}", path, headers, e));
+                assert_eq!(results[0].0, headers);
+                assert_eq!(
+                    results[0].1,
+                    vec![vec![
+                        Value::Integer(0),
+                        Value::Integer(1),
+                        Value::Text(String::from("x")),
+                        Value::Real(2.5)
+                    ]]
+                );
+                // loading the file created its table and nothing else
+                assert_eq!(
+                    results[1].1,
+                    vec![vec![
+                        Value::Text(String::from("table")),
+                        Value::Text(String::from("adversarial"))
+                    ]]
+                );
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
     }
-    Ok(Some(()))
 }
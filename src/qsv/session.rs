@@ -0,0 +1,1084 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use csv::StringRecord;
+use log::debug;
+use rayon::prelude::*;
+use rusqlite::types::Value;
+use sqlparser::ast::Statement;
+use uuid::Uuid;
+
+use crate::csv::csv_data::{CsvData, CsvType, CsvWrapper};
+use crate::csv::inference::{parse, ColumnInference};
+use crate::db::utils::{escape_table, table_columns};
+use crate::db::{Db, Header, Parameters, Rows};
+use crate::error::{QsvError, Result};
+use crate::parser::collector::Collector;
+use crate::parser::indexes::index_candidates;
+use crate::parser::operators::{json_columns, to_sql, uses_json_operators};
+use crate::parser::parameters::parameter_names;
+use crate::parser::predicates::{single_table_predicates, Predicate};
+use crate::parser::projection::referenced_columns;
+use crate::parser::rewriter::Rewriter;
+use crate::parser::Parser;
+use crate::qsv::cancel::Cancellation;
+use crate::qsv::discovery::{default_table_name, discover_files, partitions};
+use crate::qsv::explain::{Explanation, TableSchema};
+use crate::qsv::{
+    infer_columns, infer_partitions, load_csv_files, normalize_columns, read_headers, Options,
+    FILENAME_COLUMN,
+};
+
+///A table loaded from files
+enum LoadedTable {
+    ///Every matching file was loaded, so the table can be reused by later statements
+    Complete(String),
+    ///Only files which could match the statement being run were loaded, so the table is dropped
+    ///once the statement has run
+    Partial(String),
+}
+
+///What a table identifier refers to, before the files it finds are loaded
+enum Found {
+    ///The identifier's files have already been loaded as the given table
+    Existing(String),
+    ///Files to load, along with the name registered or given for their table, if any
+    Files {
+        path: String,
+        files: Vec<String>,
+        table_name: Option<String>,
+    },
+}
+
+///Files to load as a table, and the name the table is created with
+struct TableSource {
+    ///The file, glob pattern or directory the files were found from
+    path: String,
+    files: Vec<String>,
+    table_name: String,
+}
+
+///What a table identifier refers to, once any new table has been named
+enum Named {
+    Existing(String),
+    New(TableSource),
+}
+
+///The files for a table identifier, parsed and ready to be written to the database
+enum PreparedTable {
+    ///The identifier has already been loaded as the given table
+    Existing(String),
+    ///A plain CSV file to read in place through a virtual table
+    Virtual {
+        table_name: String,
+        ///Columns renamed by `--normalize-headers`, along with their original names
+        renamed: Vec<(String, String)>,
+    },
+    ///Rows to import into a new table
+    Import {
+        table_name: String,
+        csvs: Vec<CsvData>,
+        inference: ColumnInference,
+        ///Whether every file, column and row was loaded
+        complete: bool,
+        ///Columns renamed by `--normalize-headers`, along with their original names
+        renamed: Vec<(String, String)>,
+    },
+}
+
+///What a statement needs from the table for one identifier, so files, columns and rows it can't
+///use are never loaded
+#[derive(Default)]
+struct Pushdown<'a> {
+    ///Conditions every row the statement uses from the table satisfies
+    predicates: &'a [Predicate],
+    ///The lowercased names of the columns the statement could use, or None for all of them
+    columns: Option<&'a HashSet<String>>,
+}
+
+///The tables a statement reads from files, once they're loaded
+struct StatementTables {
+    ///Every identifier loaded so far mapped to its table, including tables loaded only for this
+    ///statement
+    files_to_tables: HashMap<String, String>,
+    ///The identifiers in the statement which were loaded from files, along with their tables
+    loaded: Vec<(String, String)>,
+    ///Tables loaded only for this statement, which are dropped once it has run
+    partial: Vec<String>,
+}
+
+///Receives the results of each SELECT statement, along with which of their columns hold JSON
+pub type Output<'a> = dyn FnMut(Header, Rows, &[bool]) -> Result<()> + 'a;
+
+///A database kept alive across queries, along with the files already loaded into it
+pub struct Session {
+    db: Db,
+    files_to_tables: HashMap<String, String>,
+    ///The original names of columns renamed by `--normalize-headers`
+    original_headers: HashMap<String, String>,
+    options: Options,
+    parameters: Parameters,
+    cancellation: Cancellation,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Result<Session> {
+        for (i, (name, _)) in options.tables.iter().enumerate() {
+            if options.tables[..i]
+                .iter()
+                .any(|(other, _)| other.eq_ignore_ascii_case(name))
+            {
+                return Err(QsvError::Usage(format!(
+                    "Table name {} is given more than once",
+                    name
+                )));
+            }
+        }
+        let mut db = Db::open(options.database.clone())?;
+        let cancellation = Cancellation::new(options.timeout);
+        let check = cancellation.clone();
+        db.interrupt_when(Arc::new(move || check.should_interrupt()));
+        Ok(Session {
+            db,
+            files_to_tables: HashMap::new(),
+            original_headers: HashMap::new(),
+            options,
+            parameters: vec![],
+            cancellation,
+        })
+    }
+
+    ///Sets the value bound to a named parameter such as `:name` in later queries
+    pub fn set_parameter(&mut self, name: String, value: Value) {
+        self.parameters.retain(|(existing, _)| existing != &name);
+        self.parameters.push((name, value));
+    }
+
+    ///Executes one or more SQL statements, loading any files they reference which haven't been
+    ///loaded yet. The results of each SELECT statement are passed to `output` as soon as it has
+    ///run, so if the query is cancelled part way through, everything found until then is written.
+    ///Along with them is which columns hold JSON, such as the results of `json_object`
+    pub fn query(&mut self, query: &str, output: &mut Output) -> Result<()> {
+        let ast = Parser::parse_sql(query)?;
+        self.cancellation.start();
+        let result = self.run_statements(&ast, output);
+        self.cancellation.finish();
+        result
+    }
+
+    fn run_statements(&mut self, statements: &[Statement], output: &mut Output) -> Result<()> {
+        for statement in statements.iter() {
+            match self.run_statement(statement) {
+                Ok(Some((header, rows))) => {
+                    let json = json_columns(statement, header.len());
+                    output(self.output_header(header), rows, &json)?
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    // statements SQLite interrupts fail, so report why they were interrupted
+                    self.cancellation.check()?;
+                    return Err(e);
+                }
+            }
+            self.cancellation.check()?;
+        }
+        Ok(())
+    }
+
+    ///Gives columns renamed by `--normalize-headers` their original names again, if asked to
+    fn output_header(&self, header: Header) -> Header {
+        if !self.options.original_headers {
+            return header;
+        }
+        header
+            .into_iter()
+            .map(|column| {
+                self.original_headers
+                    .get(&column)
+                    .cloned()
+                    .unwrap_or(column)
+            })
+            .collect()
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> Result<Option<(Header, Rows)>> {
+        self.check_parameters(statement)?;
+        self.check_json_operators(statement)?;
+        let tables = self.load_tables(statement)?;
+        let result = self.run_rewritten(statement, &tables.files_to_tables);
+        self.drop_partial_tables(&tables)?;
+        result
+    }
+
+    ///Checks every named parameter in a statement has a value, before any files are loaded for it
+    fn check_parameters(&self, statement: &Statement) -> Result<()> {
+        for name in parameter_names(statement) {
+            if !self
+                .parameters
+                .iter()
+                .any(|(parameter, _)| parameter == &name)
+            {
+                return Err(rusqlite::Error::InvalidParameterName(name).into());
+            }
+        }
+        Ok(())
+    }
+
+    ///Checks SQLite can run `->` and `->>` if a statement uses them, as they're only in SQLite 3.38
+    ///and later, built with JSON support
+    fn check_json_operators(&self, statement: &Statement) -> Result<()> {
+        if uses_json_operators(statement) && !self.db.supports_json_operators()? {
+            return Err(QsvError::Sqlite(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+                Some(format!(
+                    "The -> and ->> operators need SQLite 3.38 or later with JSON support, but qsv is using SQLite {}",
+                    rusqlite::version()
+                )),
+            )));
+        }
+        Ok(())
+    }
+
+    ///Loads the tables for every identifier in a statement which refers to files, skipping the
+    ///files, columns and rows it can't use, then indexes the columns it compares
+    fn load_tables(&mut self, statement: &Statement) -> Result<StatementTables> {
+        let mut collector = Collector::new();
+        collector.collect(statement)?;
+        let (predicates, mut columns) = if self.options.pushdown {
+            (
+                single_table_predicates(statement),
+                referenced_columns(statement),
+            )
+        } else {
+            (HashMap::new(), None)
+        };
+        // columns indexed with --index are kept, even if the statement doesn't use them
+        if let Some(ref mut columns) = columns {
+            for (_, index) in self.options.indexes.iter() {
+                columns.extend(index.iter().map(|column| column.to_lowercase()));
+            }
+        }
+        let indexes = if self.options.auto_index {
+            index_candidates(statement)
+        } else {
+            HashMap::new()
+        };
+        let mut seen = HashSet::new();
+        let identifiers: Vec<&String> = collector
+            .table_identifiers
+            .iter()
+            .filter(|filename| seen.insert(*filename))
+            .collect();
+        // identifiers which aren't files, such as CTEs and tables created earlier, keep their names
+        let mut found = vec![];
+        let mut reserved = HashSet::new();
+        for identifier in identifiers {
+            match self.find(identifier, None)? {
+                Some(files) => found.push((identifier, files)),
+                None => {
+                    debug!(
+                        "Identifier in SQL could not be loaded as file, as it didn't exist: {}",
+                        identifier
+                    );
+                    reserved.insert(identifier.to_lowercase());
+                }
+            }
+        }
+        // tables are named one at a time, so tables read by the same statement get distinct names.
+        // A path read through several identifiers, such as a name given with --table and the path
+        // itself, is loaded once, with every row since each identifier could filter different rows
+        let mut paths: HashMap<String, String> = HashMap::new();
+        let mut shared = HashSet::new();
+        let mut named = Vec::with_capacity(found.len());
+        for (identifier, found) in found {
+            let table = match found {
+                Found::Existing(table_name) => Named::Existing(table_name),
+                Found::Files { path, .. } if paths.contains_key(&path) => {
+                    shared.insert(path.clone());
+                    Named::Existing(paths[&path].clone())
+                }
+                Found::Files {
+                    path,
+                    files,
+                    table_name,
+                } => {
+                    let table_name = self.name_table(&path, table_name.as_deref(), &reserved)?;
+                    reserved.insert(table_name.to_lowercase());
+                    paths.insert(path.clone(), table_name.clone());
+                    Named::New(TableSource {
+                        path,
+                        files,
+                        table_name,
+                    })
+                }
+            };
+            named.push((identifier, table));
+        }
+        // the files of every table are parsed in parallel, then written one table at a time
+        let options = &self.options;
+        let prepared: Vec<Result<PreparedTable>> = named
+            .par_iter()
+            .map(|(identifier, table)| match table {
+                Named::Existing(table_name) => Ok(PreparedTable::Existing(table_name.clone())),
+                Named::New(source) => {
+                    let predicates = match predicates.get(*identifier) {
+                        Some(predicates) if !shared.contains(&source.path) => predicates.as_slice(),
+                        _ => &[],
+                    };
+                    let pushdown = Pushdown {
+                        predicates,
+                        columns: columns.as_ref(),
+                    };
+                    prepare(source, &pushdown, options)
+                }
+            })
+            .collect();
+        self.cancellation.check()?;
+        let mut tables = vec![];
+        let mut partial = vec![];
+        for ((identifier, table), prepared) in named.into_iter().zip(prepared) {
+            let candidates = indexes.get(identifier).map_or(&[][..], |c| c.as_slice());
+            let path = match table {
+                Named::Existing(_) => identifier.as_str(),
+                Named::New(ref source) => source.path.as_str(),
+            };
+            let loaded = self.store(path, prepared?)?;
+            match loaded {
+                LoadedTable::Complete(table_name) => {
+                    debug!(
+                        "Potential filename from SQL was able to be loaded: {}",
+                        identifier
+                    );
+                    self.create_indexes(identifier, &table_name, candidates)?;
+                    tables.push((identifier.clone(), table_name));
+                }
+                LoadedTable::Partial(table_name) => {
+                    debug!(
+                        "Some of {} was skipped, loaded the rest as {} for this statement",
+                        identifier, table_name
+                    );
+                    self.create_indexes(identifier, &table_name, candidates)?;
+                    tables.push((identifier.clone(), table_name.clone()));
+                    partial.push(table_name);
+                }
+            }
+        }
+        // every identifier is mapped to its table, including partial tables and the tables of
+        // identifiers sharing a path with another
+        let mut files_to_tables = self.files_to_tables.clone();
+        files_to_tables.extend(tables.iter().cloned());
+        Ok(StatementTables {
+            files_to_tables,
+            loaded: tables,
+            partial,
+        })
+    }
+
+    fn drop_partial_tables(&mut self, tables: &StatementTables) -> Result<()> {
+        for table_name in tables.partial.iter() {
+            let drop = format!("DROP TABLE {}", escape_table(table_name));
+            self.db.execute(drop.as_str(), &vec![])?;
+        }
+        Ok(())
+    }
+
+    ///Rewrites a statement to read from the tables its files were loaded as
+    fn rewrite(
+        &self,
+        statement: &Statement,
+        files_to_tables: &HashMap<String, String>,
+    ) -> Result<Statement> {
+        let mut rewritten = Rewriter::new(files_to_tables.clone());
+        let mut to_rewrite = statement.clone();
+        rewritten.rewrite(&mut to_rewrite)?;
+        // The whole database only lives as long as the session, so temporary tables are created as
+        // regular tables. This allows views, which our parser can't mark as temporary, to use them
+        if let Statement::CreateTable {
+            ref mut temporary, ..
+        } = to_rewrite
+        {
+            *temporary = false;
+        }
+        Ok(to_rewrite)
+    }
+
+    fn run_rewritten(
+        &mut self,
+        statement: &Statement,
+        files_to_tables: &HashMap<String, String>,
+    ) -> Result<Option<(Header, Rows)>> {
+        let rewritten = self.rewrite(statement, files_to_tables)?;
+        let sql = to_sql(&rewritten)?;
+        debug!("Rewritten statement: {}", sql);
+        match rewritten {
+            Statement::Query(_) => Ok(Some(self.db.select_statement(&sql, &self.parameters)?)),
+            _ => {
+                self.db.execute(&sql, &self.parameters)?;
+                Ok(None)
+            }
+        }
+    }
+
+    ///Explains how each statement in a query would run: the statement the tables it reads are
+    ///substituted into, the schema of each table and SQLite's query plan. SELECT statements aren't
+    ///run, but other statements are, so later statements can use the tables and views they create
+    pub fn explain(&mut self, query: &str) -> Result<Vec<Explanation>> {
+        let ast = Parser::parse_sql(query)?;
+        self.cancellation.start();
+        let mut explanations = Vec::with_capacity(ast.len());
+        let mut result = Ok(());
+        for statement in ast.iter() {
+            match self.explain_statement(statement) {
+                Ok(explanation) => explanations.push(explanation),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.cancellation.finish();
+        result.map(|_| explanations)
+    }
+
+    fn explain_statement(&mut self, statement: &Statement) -> Result<Explanation> {
+        self.check_parameters(statement)?;
+        self.check_json_operators(statement)?;
+        let tables = self.load_tables(statement)?;
+        let explanation = self.explain_loaded(statement, &tables);
+        self.drop_partial_tables(&tables)?;
+        explanation
+    }
+
+    fn explain_loaded(
+        &mut self,
+        statement: &Statement,
+        tables: &StatementTables,
+    ) -> Result<Explanation> {
+        let rewritten = self.rewrite(statement, &tables.files_to_tables)?;
+        let mut schemas = Vec::with_capacity(tables.loaded.len());
+        for (identifier, table_name) in tables.loaded.iter() {
+            schemas.push(TableSchema {
+                identifier: identifier.clone(),
+                table_name: table_name.clone(),
+                columns: self.db.column_types(table_name)?,
+            });
+        }
+        let sql = to_sql(&rewritten)?;
+        let plan = self.db.query_plan(&sql, &self.parameters)?;
+        if !matches!(rewritten, Statement::Query(_)) {
+            self.db.execute(&sql, &self.parameters)?;
+        }
+        Ok(Explanation {
+            statement: to_sql(statement)?,
+            rewritten: sql,
+            tables: schemas,
+            plan,
+        })
+    }
+
+    ///Loads a file, or every file matching a glob pattern or under a directory, into a table,
+    ///optionally under an explicit table name. Returns the name of the table the files are
+    ///available as, or None if no files match
+    pub fn load_file(
+        &mut self,
+        filename: &str,
+        table_name: Option<&str>,
+    ) -> Result<Option<String>> {
+        let table_name = match self.load(filename, table_name, &Pushdown::default())? {
+            Some(LoadedTable::Complete(table_name)) | Some(LoadedTable::Partial(table_name)) => {
+                table_name
+            }
+            None => return Ok(None),
+        };
+        self.create_indexes(filename, &table_name, &[])?;
+        Ok(Some(table_name))
+    }
+
+    ///Creates the indexes asked for on a table with `--index`, along with an index on each column
+    ///the statement being run compares which the table has. Indexes which already exist are kept
+    fn create_indexes(
+        &mut self,
+        identifier: &str,
+        table_name: &str,
+        candidates: &[String],
+    ) -> Result<()> {
+        let explicit: Vec<Vec<String>> = self
+            .options
+            .indexes
+            .iter()
+            .filter(|(table, _)| table == identifier || table == table_name)
+            .map(|(_, columns)| columns.clone())
+            .collect();
+        if explicit.is_empty() && candidates.is_empty() {
+            return Ok(());
+        }
+        if self.db.is_virtual_table(table_name)? {
+            if !explicit.is_empty() {
+                return Err(QsvError::Usage(format!(
+                    "Unable to index {}, as it's read in place through a virtual table",
+                    identifier
+                )));
+            }
+            debug!("Not indexing virtual table {}", table_name);
+            return Ok(());
+        }
+        let table_columns = self.db.columns(table_name)?;
+        let find = |column: &str| {
+            table_columns
+                .iter()
+                .find(|existing| existing.eq_ignore_ascii_case(column))
+                .map(|existing| existing.as_str())
+        };
+        let mut indexes: Vec<Vec<&str>> = vec![];
+        for columns in explicit.iter() {
+            let mut index = vec![];
+            for column in columns.iter() {
+                index.push(find(column).ok_or_else(|| {
+                    QsvError::Usage(format!(
+                        "Unable to index {}, as it has no column {}",
+                        identifier, column
+                    ))
+                })?);
+            }
+            indexes.push(index);
+        }
+        indexes.extend(
+            candidates
+                .iter()
+                .filter_map(|column| find(column))
+                .map(|column| vec![column]),
+        );
+        for index in indexes.iter() {
+            match self.db.create_index(table_name, index)? {
+                Some(index_name) => debug!(
+                    "Created index {} on {} ({})",
+                    index_name,
+                    table_name,
+                    index.join(", ")
+                ),
+                None => debug!(
+                    "Table {} is already indexed on ({})",
+                    table_name,
+                    index.join(", ")
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    ///Loads the files a table identifier refers to, skipping the files, columns and rows the
+    ///statement being run can't use
+    fn load(
+        &mut self,
+        filename: &str,
+        table_name: Option<&str>,
+        pushdown: &Pushdown,
+    ) -> Result<Option<LoadedTable>> {
+        let prepared = match self.find(filename, table_name)? {
+            Some(Found::Existing(table_name)) => PreparedTable::Existing(table_name),
+            Some(Found::Files {
+                path,
+                files,
+                table_name,
+            }) => {
+                let table_name = self.name_table(&path, table_name.as_deref(), &HashSet::new())?;
+                let source = TableSource {
+                    path,
+                    files,
+                    table_name,
+                };
+                let prepared = prepare(&source, pushdown, &self.options)?;
+                return Ok(Some(self.store(&source.path, prepared)?));
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(self.store(filename, prepared)?))
+    }
+
+    ///Finds the files a table identifier refers to, either directly or through a name registered
+    ///with `--table`. Returns None if it refers to no files
+    fn find(&self, identifier: &str, table_name: Option<&str>) -> Result<Option<Found>> {
+        let registered = self
+            .options
+            .tables
+            .iter()
+            .find(|(name, path)| name.eq_ignore_ascii_case(identifier) || path == identifier);
+        let (path, table_name) = match registered {
+            Some((name, path)) => (path.as_str(), table_name.or(Some(name.as_str()))),
+            None => (identifier, table_name),
+        };
+        if let Some(existing) = self.files_to_tables.get(path) {
+            debug!(
+                "File {} has already been loaded as table {}",
+                path, existing
+            );
+            return Ok(Some(Found::Existing(existing.clone())));
+        }
+        Ok(discover_files(path)?.map(|files| Found::Files {
+            path: String::from(path),
+            files,
+            table_name: table_name.map(String::from),
+        }))
+    }
+
+    ///The name to load a path's files as. A name registered or given for them is used as it is,
+    ///failing if another table already has it. Otherwise the name is derived from the path,
+    ///adding a number to it if it's taken, so every path gets its own table
+    fn name_table(
+        &self,
+        path: &str,
+        table_name: Option<&str>,
+        reserved: &HashSet<String>,
+    ) -> Result<String> {
+        if let Some(table_name) = table_name {
+            if self.is_name_taken(table_name, reserved)? {
+                return Err(QsvError::Usage(format!(
+                    "Unable to load {} as {}, as another table already has that name",
+                    path, table_name
+                )));
+            }
+            return Ok(String::from(table_name));
+        }
+        let base = default_table_name(path)
+            .unwrap_or_else(|| String::from("t") + &Uuid::new_v4().as_u128().to_string());
+        let mut table_name = base.clone();
+        let mut n = 1;
+        while self.is_name_taken(&table_name, reserved)?
+            || self
+                .options
+                .tables
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(&table_name))
+        {
+            n += 1;
+            table_name = format!("{}_{}", base, n);
+        }
+        if n > 1 {
+            debug!(
+                "Table name {} is taken, loading {} as {}",
+                base, path, table_name
+            );
+        }
+        Ok(table_name)
+    }
+
+    ///Whether a table name is used by a table loaded from other files, one created by an earlier
+    ///statement, or an identifier in the statement being run. Names are compared case-insensitively,
+    ///as SQLite does
+    fn is_name_taken(&self, table_name: &str, reserved: &HashSet<String>) -> Result<bool> {
+        Ok(reserved.contains(&table_name.to_lowercase())
+            || self
+                .files_to_tables
+                .values()
+                .any(|existing| existing.eq_ignore_ascii_case(table_name))
+            || self.db.has_table(table_name)?)
+    }
+
+    ///Writes a prepared table to the database. Tables holding every file are remembered, so
+    ///later statements can reuse them
+    fn store(&mut self, filename: &str, prepared: PreparedTable) -> Result<LoadedTable> {
+        let (table_name, import, renamed) = match prepared {
+            PreparedTable::Existing(table_name) => return Ok(LoadedTable::Complete(table_name)),
+            PreparedTable::Virtual {
+                table_name,
+                renamed,
+            } => (table_name, None, renamed),
+            PreparedTable::Import {
+                table_name,
+                csvs,
+                inference,
+                complete,
+                renamed,
+            } => (table_name, Some((csvs, inference, complete)), renamed),
+        };
+        let complete = match import {
+            None => {
+                debug!(
+                    "Creating virtual table {} reading {} in place",
+                    table_name, filename
+                );
+                self.db
+                    .create_csv_table(
+                        &table_name,
+                        filename,
+                        self.options.delimiter,
+                        self.options.trim,
+                        self.options.textonly,
+                        self.options.normalize_headers,
+                    )
+                    .map_err(|e| QsvError::file_load(filename, e))?;
+                true
+            }
+            Some((csvs, inference, complete)) => {
+                debug!(
+                    "Attempting to create table {} for filename {}",
+                    table_name, filename
+                );
+                self.db
+                    .create_table(&table_name, &table_columns(&inference))?;
+                for csv in csvs.iter() {
+                    self.insert_csv(&table_name, csv, &inference)?;
+                }
+                complete
+            }
+        };
+        for (column, original) in renamed {
+            self.original_headers.entry(column).or_insert(original);
+        }
+        if complete {
+            self.files_to_tables
+                .insert(filename.to_string(), table_name.clone());
+            Ok(LoadedTable::Complete(table_name))
+        } else {
+            Ok(LoadedTable::Partial(table_name))
+        }
+    }
+
+    ///Inserts the rows of a file along with its partition values, and its filename if asked for.
+    ///Each file only inserts the columns it has which are in the table, leaving the rest of the
+    ///table's columns NULL
+    fn insert_csv(
+        &mut self,
+        table_name: &str,
+        csv: &CsvData,
+        inference: &ColumnInference,
+    ) -> Result<()> {
+        let partitions = partitions(&csv.filename);
+        let file_columns: Vec<usize> = file_columns(csv, &partitions)
+            .into_iter()
+            .filter(|i| inference.columns_to_types.contains_key(&csv.headers[*i]))
+            .collect();
+        let mut headers: Vec<&str> = file_columns.iter().map(|i| &csv.headers[*i]).collect();
+        headers.extend(partitions.iter().map(|(key, _)| key.as_str()));
+        if self.options.filename_column {
+            headers.push(FILENAME_COLUMN);
+        }
+        let records: Vec<Vec<&str>> = csv
+            .records
+            .iter()
+            .map(|record| {
+                let mut values: Vec<&str> = file_columns.iter().map(|i| &record[*i]).collect();
+                values.extend(partitions.iter().map(|(_, value)| value.as_str()));
+                if self.options.filename_column {
+                    values.push(csv.filename.as_str());
+                }
+                values
+            })
+            .collect();
+        debug!(
+            "Inserting {} rows from {} into {}",
+            records.len(),
+            csv.filename,
+            table_name
+        );
+        let types: Vec<CsvType> = headers
+            .iter()
+            .map(|header| {
+                inference
+                    .columns_to_types
+                    .get(*header)
+                    .copied()
+                    .unwrap_or(CsvType::String)
+            })
+            .collect();
+        self.db.insert(table_name, &headers, &types, records)?;
+        Ok(())
+    }
+
+    ///Lists the tables currently in the database
+    pub fn tables(&self) -> Result<Rows> {
+        let (_, rows) = self.db.select_statement(
+            "SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name",
+            &vec![],
+        )?;
+        Ok(rows)
+    }
+
+    ///Shows the DDL of every table in the database, or of a single table
+    pub fn schema(&self, table_name: Option<&str>) -> Result<Rows> {
+        let query = match table_name {
+            Some(table_name) => format!(
+                "SELECT sql FROM sqlite_master WHERE name = '{}'",
+                table_name.replace('\'', "''")
+            ),
+            None => {
+                String::from("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name")
+            }
+        };
+        let (_, rows) = self.db.select_statement(query.as_str(), &vec![])?;
+        Ok(rows)
+    }
+}
+
+///Finds and parses the files a table identifier refers to, and infers their columns, skipping the
+///files, columns and rows the statement being run can't use. Nothing is written to the database,
+///so every table a statement reads can be prepared in parallel
+fn prepare(source: &TableSource, pushdown: &Pushdown, options: &Options) -> Result<PreparedTable> {
+    let (filename, files) = (source.path.as_str(), &source.files);
+    let table_name = source.table_name.clone();
+    debug!(
+        "Attempting to load identifier from SQL as files: {:?}",
+        files
+    );
+    if options.vtab && can_read_in_place(filename, files, options) {
+        let renamed = if options.normalize_headers {
+            let headers = read_headers(filename, options)?;
+            normalize_columns(&mut [CsvData {
+                records: vec![],
+                headers,
+                filename: filename.to_string(),
+            }])
+        } else {
+            vec![]
+        };
+        return Ok(PreparedTable::Virtual {
+            table_name,
+            renamed,
+        });
+    }
+    // partition columns are typed using every file, so the schema doesn't depend on pruning
+    let partition_inference = infer_partitions(files, options);
+    let to_load: Vec<String> = files
+        .iter()
+        .filter(|file| satisfies(&partitions(file), &partition_inference, pushdown.predicates))
+        .cloned()
+        .collect();
+    let mut complete = to_load.len() == files.len();
+    let mut csvs = if to_load.is_empty() {
+        // no rows can match, but the table still needs the columns the query refers to
+        let mut csvs = load_csv_files(&files[..1], options)?;
+        csvs[0].records.clear();
+        csvs
+    } else {
+        load_csv_files(&to_load, options)?
+    };
+    let renamed = if options.normalize_headers {
+        normalize_columns(&mut csvs)
+    } else {
+        vec![]
+    };
+    debug!(
+        "Loading {} of {} files for {}",
+        to_load.len(),
+        files.len(),
+        filename
+    );
+    // types are inferred from every row, so they don't depend on which rows are skipped
+    let mut inference = infer_columns(&csvs, options);
+    let filtered: Vec<bool> = csvs
+        .par_iter_mut()
+        .map(|csv| {
+            let rows = csv.records.len();
+            filter_rows(csv, &inference, pushdown.predicates);
+            debug!(
+                "Loading {} of {} rows from {}",
+                csv.records.len(),
+                rows,
+                csv.filename
+            );
+            csv.records.len() != rows
+        })
+        .collect();
+    complete &= !filtered.contains(&true);
+    if let Some(columns) = pushdown.columns {
+        let all_columns = inference.columns_to_types.len();
+        project(&mut inference, columns);
+        debug!(
+            "Loading {} of {} columns for {}",
+            inference.columns_to_types.len(),
+            all_columns,
+            filename
+        );
+        complete &= inference.columns_to_types.len() == all_columns;
+    }
+    inference.merge(&partition_inference);
+    if options.filename_column {
+        inference.merge(&ColumnInference::default_inference(&StringRecord::from(
+            vec![FILENAME_COLUMN],
+        )));
+    }
+    Ok(PreparedTable::Import {
+        table_name,
+        csvs,
+        inference,
+        complete,
+        renamed,
+    })
+}
+
+///Whether an identifier is a single plain CSV file which a virtual table can read. Compressed
+///files, and tables made of several files or with extra columns, are imported instead
+fn can_read_in_place(filename: &str, files: &[String], options: &Options) -> bool {
+    if files != [filename] || options.filename_column || !partitions(filename).is_empty() {
+        return false;
+    }
+    let mime_type = tree_magic::from_filepath(Path::new(filename));
+    debug!("File '{}' has MIME type: '{}'", filename, mime_type);
+    mime_type == "text/plain"
+}
+
+///The indexes of a file's columns which aren't replaced by its partition values. A partition value
+///takes precedence over a column of the same name in the file
+fn file_columns(csv: &CsvData, partitions: &[(String, String)]) -> Vec<usize> {
+    (0..csv.headers.len())
+        .filter(|i| !partitions.iter().any(|(key, _)| key == &csv.headers[*i]))
+        .collect()
+}
+
+///Converts a value from a file to the type its column was inferred to be
+fn typed_value(value: &str, column_type: Option<&CsvType>) -> CsvWrapper {
+    match column_type {
+        Some(CsvType::String) => CsvWrapper::String(String::from(value)),
+        _ => parse(value),
+    }
+}
+
+///Whether a file's partition values could satisfy every predicate on its partition columns.
+///Predicates on other columns, or which can't be evaluated, never rule a file out
+fn satisfies(
+    partitions: &[(String, String)],
+    inference: &ColumnInference,
+    predicates: &[Predicate],
+) -> bool {
+    predicates.iter().all(|predicate| {
+        let partition = partitions
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(predicate.column()));
+        let (key, value) = match partition {
+            Some(partition) => partition,
+            None => return true,
+        };
+        let value = typed_value(value, inference.columns_to_types.get(key));
+        predicate.evaluate(&value) != Some(false)
+    })
+}
+
+///Removes the rows of a file which can't satisfy the predicates on its columns
+fn filter_rows(csv: &mut CsvData, inference: &ColumnInference, predicates: &[Predicate]) {
+    let partitions = partitions(&csv.filename);
+    let filters: Vec<(usize, &Predicate, Option<&CsvType>)> = file_columns(csv, &partitions)
+        .into_iter()
+        .flat_map(|i| {
+            let header = &csv.headers[i];
+            predicates
+                .iter()
+                .filter(move |predicate| header.eq_ignore_ascii_case(predicate.column()))
+                .map(move |predicate| (i, predicate, inference.columns_to_types.get(header)))
+        })
+        .collect();
+    if filters.is_empty() {
+        return;
+    }
+    csv.records.retain(|record| {
+        filters.iter().all(|(i, predicate, column_type)| {
+            predicate.evaluate(&typed_value(&record[*i], *column_type)) != Some(false)
+        })
+    });
+}
+
+///Narrows the columns of a table down to those a statement could use. At least one column is
+///always kept, so queries such as `SELECT count(*)` still see every row
+fn project(inference: &mut ColumnInference, columns: &HashSet<String>) {
+    let first = inference
+        .columns_to_types
+        .first()
+        .map(|(column, column_type)| (column.clone(), *column_type));
+    inference
+        .columns_to_types
+        .retain(|column, _| columns.contains(&column.to_lowercase()));
+    inference
+        .columns_to_indexes
+        .retain(|column, _| columns.contains(&column.to_lowercase()));
+    if let (true, Some((column, column_type))) = (inference.columns_to_types.is_empty(), first) {
+        inference.columns_to_indexes.insert(column.clone(), 0);
+        inference.columns_to_types.insert(column, column_type);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbOptions;
+    use crate::parser::Parser;
+
+    fn predicates(sql: &str) -> Vec<Predicate> {
+        let ast = Parser::parse_sql(sql).unwrap();
+        single_table_predicates(&ast[0]).remove("sales/").unwrap()
+    }
+
+    #[test]
+    fn it_skips_files_whose_partitions_cant_match() {
+        let files = vec![
+            String::from("sales/year=2025/month=12/part.csv"),
+            String::from("sales/year=2026/month=1/part.csv"),
+            String::from("sales/year=2026/month=10/part.csv"),
+        ];
+        let options = Options {
+            delimiter: ',',
+            trim: false,
+            textonly: false,
+            filename_column: false,
+            pushdown: true,
+            vtab: false,
+            auto_index: true,
+            indexes: vec![],
+            database: DbOptions::default(),
+            timeout: None,
+            tables: vec![],
+            normalize_headers: false,
+            original_headers: false,
+        };
+        let inference = infer_partitions(&files, &options);
+        let predicates =
+            predicates("select * from sales/ where year = 2026 and month > 9 and amount < 10");
+        let matching: Vec<&String> = files
+            .iter()
+            .filter(|file| satisfies(&partitions(file), &inference, &predicates))
+            .collect();
+        assert_eq!(matching, vec!["sales/year=2026/month=10/part.csv"]);
+    }
+
+    #[test]
+    fn it_filters_rows_using_predicates_on_their_columns() {
+        let mut csv = CsvData {
+            records: vec![
+                StringRecord::from(vec!["Michael", "13"]),
+                StringRecord::from(vec!["Christine", "18"]),
+                StringRecord::from(vec!["Abdul", "62"]),
+            ],
+            headers: StringRecord::from(vec!["name", "age"]),
+            filename: String::from("people.csv"),
+        };
+        let inference = ColumnInference::from_csv(&csv);
+        filter_rows(
+            &mut csv,
+            &inference,
+            &predicates("select * from sales/ where AGE >= 18 and name <> 'Abdul' and x = 1"),
+        );
+        assert_eq!(
+            csv.records,
+            vec![StringRecord::from(vec!["Christine", "18"])]
+        );
+    }
+
+    #[test]
+    fn it_projects_columns_keeping_at_least_one() {
+        let csv = CsvData {
+            records: vec![StringRecord::from(vec!["Michael", "13"])],
+            headers: StringRecord::from(vec!["Name", "age"]),
+            filename: String::from("people.csv"),
+        };
+        let mut inference = ColumnInference::from_csv(&csv);
+        project(
+            &mut inference,
+            &vec![String::from("name")].into_iter().collect(),
+        );
+        let columns: Vec<&String> = inference.columns_to_types.keys().collect();
+        assert_eq!(columns, vec!["Name"]);
+        let mut inference = ColumnInference::from_csv(&csv);
+        project(&mut inference, &HashSet::new());
+        let columns: Vec<&String> = inference.columns_to_types.keys().collect();
+        assert_eq!(columns, vec!["Name"]);
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+use log::debug;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use crate::error::{QsvError, Result};
+use crate::qsv::session::Session;
+use crate::qsv::{
+    parse_parameter, write_to_stdout, write_to_stdout_as_json, write_to_stdout_with_header,
+    Options, OutputFormat,
+};
+
+const PROMPT: &str = "qsv> ";
+const CONTINUATION_PROMPT: &str = "...> ";
+const HISTORY_FILE: &str = ".qsv_history";
+const HELP: &str = ".help                    Show this message
+.tables                  List the tables which have been loaded
+.schema [table]          Show the CREATE statement for every table, or for a single table
+.load <file> [as <name>] Load a file as a table, optionally with an explicit table name
+.mode csv|json           Set the output format
+.header on|off           Turn the output of headers on or off
+.param name=value        Bind a value to the named parameter :name in later queries
+.quit                    Exit the shell";
+
+///State of the interactive shell which persists between lines of input
+struct Shell {
+    session: Session,
+    mode: OutputFormat,
+    output_header: bool,
+}
+
+///Runs an interactive shell which keeps loaded files around across queries.
+///SQL statements are run once a line ends with a semicolon; lines starting with a dot are
+///commands for the shell itself
+pub fn run_shell(options: &Options, mode: OutputFormat, output_header: bool) -> Result<()> {
+    let mut shell = Shell {
+        session: Session::new(options.clone())?,
+        mode,
+        output_header,
+    };
+    let mut editor = Editor::<()>::new();
+    let history = history_path();
+    if let Some(ref history) = history {
+        if editor.load_history(history).is_err() {
+            debug!("No previous history found at {:?}", history);
+        }
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                let trimmed = line.trim();
+                if trimmed.is_empty() {
+                    continue;
+                }
+                if buffer.is_empty() && trimmed.starts_with('.') {
+                    editor.add_history_entry(trimmed);
+                    match shell.run_command(trimmed) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    continue;
+                }
+                if !buffer.is_empty() {
+                    buffer.push('\n');
+                }
+                buffer.push_str(&line);
+                if trimmed.ends_with(';') {
+                    editor.add_history_entry(buffer.as_str());
+                    if let Err(e) = shell.run_query(&buffer) {
+                        eprintln!("Error: {}", e);
+                    }
+                    buffer.clear();
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(ReadlineError::Io(e)) => return Err(QsvError::Io(e)),
+            Err(e) => return Err(QsvError::Io(std::io::Error::other(e.to_string()))),
+        }
+    }
+    if let Some(ref history) = history {
+        if let Err(e) = editor.save_history(history) {
+            debug!("Unable to save history to {:?}: {}", history, e);
+        }
+    }
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+impl Shell {
+    fn run_query(&mut self, query: &str) -> Result<()> {
+        let (mode, output_header) = (self.mode, self.output_header);
+        self.session
+            .query(query, &mut |header, results, json| match mode {
+                OutputFormat::Json => write_to_stdout_as_json(results, &header, json),
+                OutputFormat::Csv if output_header => write_to_stdout_with_header(results, &header),
+                OutputFormat::Csv => write_to_stdout(results),
+            })
+    }
+
+    ///Runs a dot-command, returning false if the shell should exit
+    fn run_command(&mut self, line: &str) -> Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arguments: Vec<&str> = words.collect();
+        match (command, arguments.as_slice()) {
+            (".quit", []) | (".exit", []) => return Ok(false),
+            (".help", []) => println!("{}", HELP),
+            (".tables", []) => write_to_stdout(self.session.tables()?)?,
+            (".schema", []) => write_to_stdout(self.session.schema(None)?)?,
+            (".schema", [table]) => write_to_stdout(self.session.schema(Some(table))?)?,
+            (".load", [filename]) => self.load(filename, None)?,
+            (".load", [filename, "as", table]) => self.load(filename, Some(table))?,
+            (".mode", [mode]) => self.mode = mode.parse().map_err(QsvError::Usage)?,
+            (".mode", []) => println!("{}", self.mode),
+            (".header", ["on"]) => self.output_header = true,
+            (".header", ["off"]) => self.output_header = false,
+            (".param", [assignment]) => {
+                let (name, value) = parse_parameter(assignment).map_err(QsvError::Usage)?;
+                self.session.set_parameter(name, value);
+            }
+            _ => {
+                return Err(QsvError::Usage(format!(
+                    "Unrecognized command or arguments: '{}', see .help",
+                    line
+                )))
+            }
+        }
+        Ok(true)
+    }
+
+    fn load(&mut self, filename: &str, table_name: Option<&str>) -> Result<()> {
+        match self.session.load_file(filename, table_name)? {
+            Some(table_name) => {
+                println!("Loaded {} as {}", filename, table_name);
+                Ok(())
+            }
+            None => Err(QsvError::file_load(filename, "file does not exist")),
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use crate::csv::csv_data::{reset_stream, CsvStream, CsvType};
 use crate::csv::inference::ColumnInference;
+use crate::error::{QsvError, Result};
 use crate::qsv::{csv_stream_from_mime_type, Options};
 use log::debug;
 use stats::{Frequencies, MinMax, OnlineStats};
-use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::hash::Hash;
@@ -74,82 +74,87 @@ fn print_frequencies(top_10: &[String], f: &mut Formatter) -> std::fmt::Result {
 ///Calculates some statistics from the CSV
 /// Depending on what type the column has, we can calculate different values such as:
 /// mean, stddev, count unique, top 10 most frequent values
-pub fn execute_statistics(
-    filename: &str,
-    options: &Options,
-) -> Result<Vec<Statistics>, Box<dyn Error>> {
-    if let Ok((inference, ref mut csv_stream)) = maybe_load_stats(filename, options) {
-        let mut vec = Vec::with_capacity(10);
-        for (key, value) in &inference.columns_to_types {
-            reset_stream(csv_stream).unwrap();
-            match value {
-                CsvType::Integer => {
-                    let mut statistics = OnlineStats::new();
-                    let mut frequencies = Frequencies::new();
-                    let mut minmax = MinMax::new();
-                    compute_statistics_closure(csv_stream, &inference, key, |element: i64| {
-                        statistics.add(element);
-                        frequencies.add(element);
-                        minmax.add(element);
-                    })?;
-                    let min = minmax.min().map(|inner| MinValue::Int(*inner));
-                    let max = minmax.max().map(|inner| MinValue::Int(*inner));
-                    let cardinality = Some(frequencies.cardinality());
+pub fn execute_statistics(filename: &str, options: &Options) -> Result<Vec<Statistics>> {
+    match maybe_load_stats(filename, options) {
+        Ok((inference, ref mut csv_stream)) => {
+            let mut vec = Vec::with_capacity(10);
+            for (key, value) in &inference.columns_to_types {
+                reset_stream(csv_stream).map_err(|e| QsvError::file_load(filename, e))?;
+                match value {
+                    CsvType::Integer => {
+                        let mut statistics = OnlineStats::new();
+                        let mut frequencies = Frequencies::new();
+                        let mut minmax = MinMax::new();
+                        compute_statistics_closure(csv_stream, &inference, key, |element: i64| {
+                            statistics.add(element);
+                            frequencies.add(element);
+                            minmax.add(element);
+                        })?;
+                        let min = minmax.min().map(|inner| MinValue::Int(*inner));
+                        let max = minmax.max().map(|inner| MinValue::Int(*inner));
+                        let cardinality = Some(frequencies.cardinality());
 
-                    let result = Statistics {
-                        column: key.clone(),
-                        stats: Some(statistics),
-                        top_10: Some(format_top_10(frequencies)),
-                        min,
-                        max,
-                        cardinality,
-                    };
-                    vec.push(result)
-                }
-                CsvType::Float => {
-                    let mut statistics = OnlineStats::new();
-                    let mut minmax = MinMax::new();
-                    compute_statistics_closure(csv_stream, &inference, key, |element: f64| {
-                        statistics.add(element);
-                        minmax.add(element);
-                    })?;
+                        let result = Statistics {
+                            column: key.clone(),
+                            stats: Some(statistics),
+                            top_10: Some(format_top_10(frequencies)),
+                            min,
+                            max,
+                            cardinality,
+                        };
+                        vec.push(result)
+                    }
+                    CsvType::Float => {
+                        let mut statistics = OnlineStats::new();
+                        let mut minmax = MinMax::new();
+                        compute_statistics_closure(csv_stream, &inference, key, |element: f64| {
+                            statistics.add(element);
+                            minmax.add(element);
+                        })?;
 
-                    let min = minmax.min().map(|inner| MinValue::Float(*inner));
-                    let max = minmax.max().map(|inner| MinValue::Float(*inner));
-                    let result = Statistics {
-                        column: key.clone(),
-                        stats: Some(statistics),
-                        top_10: None,
-                        min,
-                        max,
-                        cardinality: None,
-                    };
-                    vec.push(result)
-                }
+                        let min = minmax.min().map(|inner| MinValue::Float(*inner));
+                        let max = minmax.max().map(|inner| MinValue::Float(*inner));
+                        let result = Statistics {
+                            column: key.clone(),
+                            stats: Some(statistics),
+                            top_10: None,
+                            min,
+                            max,
+                            cardinality: None,
+                        };
+                        vec.push(result)
+                    }
 
-                CsvType::String => {
-                    let mut frequencies = Frequencies::new();
-                    compute_statistics_closure(csv_stream, &inference, key, |element: String| {
-                        frequencies.add(element);
-                    })?;
-                    let cardinality = Some(frequencies.cardinality());
-                    let result = Statistics {
-                        column: key.clone(),
-                        top_10: Some(format_top_10(frequencies)),
-                        stats: None,
-                        min: None,
-                        max: None,
-                        cardinality,
-                    };
-                    vec.push(result)
+                    CsvType::String => {
+                        let mut frequencies = Frequencies::new();
+                        compute_statistics_closure(
+                            csv_stream,
+                            &inference,
+                            key,
+                            |element: String| {
+                                frequencies.add(element);
+                            },
+                        )?;
+                        let cardinality = Some(frequencies.cardinality());
+                        let result = Statistics {
+                            column: key.clone(),
+                            top_10: Some(format_top_10(frequencies)),
+                            stats: None,
+                            min: None,
+                            max: None,
+                            cardinality,
+                        };
+                        vec.push(result)
+                    }
                 }
             }
+            debug!("Filename was able to be loaded: {}", filename);
+            Ok(vec)
+        }
+        Err(e) => {
+            debug!("Filename could not be loaded: {}", filename);
+            Err(e)
         }
-        debug!("Filename was able to be loaded: {}", filename);
-        Ok(vec)
-    } else {
-        debug!("Filename could not be loaded: {}", filename);
-        Err("failed to load file".into())
     }
 }
 fn format_top_10<T: Eq + Hash + Display>(freqs: Frequencies<T>) -> Vec<String> {
@@ -169,22 +174,21 @@ fn compute_statistics_closure<Parse: FromStr, F>(
     inference: &ColumnInference,
     key: &str,
     mut process: F,
-) -> Result<(), Box<dyn Error>>
+) -> Result<()>
 where
     F: FnMut(Parse),
 {
+    let filename = csv_stream.filename.clone();
+    let index = *inference.columns_to_indexes.get(key).unwrap();
     for record in csv_stream.stream.records() {
-        let record = record?;
-        let index = inference.columns_to_indexes.get(key).unwrap();
-        let try_parse: Parse = record
-            .get(*index)
-            .unwrap()
-            .parse()
-            .map_err(|_err| {
-                let error: Box<dyn Error> = "Error parsing type".into();
-                error
-            })
-            .unwrap();
+        let record = record.map_err(|e| QsvError::file_load(&filename, e))?;
+        let cell = record.get(index).unwrap_or_default();
+        let try_parse: Parse = cell.parse().map_err(|_err| {
+            QsvError::TypeConversion(format!(
+                "value '{}' in column '{}' doesn't match its inferred type",
+                cell, key
+            ))
+        })?;
         process(try_parse);
     }
     Ok(())
@@ -193,17 +197,17 @@ where
 fn maybe_load_stats(
     filename: &str,
     options: &Options,
-) -> Result<(ColumnInference, CsvStream<File>), Box<dyn Error>> {
+) -> Result<(ColumnInference, CsvStream<File>)> {
     let path = Path::new(filename);
     if !path.exists() {
-        return Err("failed to find path".into());
+        return Err(QsvError::file_load(filename, "file does not exist"));
     }
     let mime_type = tree_magic::from_filepath(path);
     let mut csv = csv_stream_from_mime_type(filename, mime_type.as_str(), options)?;
     let inference = if options.textonly {
         ColumnInference::default_inference(&csv.headers)
     } else {
-        ColumnInference::from_stream(&mut csv)?
+        ColumnInference::from_stream(&mut csv).map_err(|e| QsvError::file_load(filename, e))?
     };
     Ok((inference, csv))
 }
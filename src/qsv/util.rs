@@ -1,11 +1,287 @@
 use std::path::Path;
+use std::time::Duration;
 
-pub fn remove_extension(p0: &Path) -> Option<String> {
-    let file_name = p0.file_name()?;
-    let file_str = file_name.to_str()?;
-    let mut split = file_str.split('.');
-    split.next().map(String::from)
+use rusqlite::types::Value;
+
+use crate::csv::csv_data::CsvWrapper;
+use crate::csv::inference::parse;
+use crate::db::utils::sql_identifier;
+
+///The name of a file without any of the given extensions, so `a.v1.csv.gz` becomes `a.v1` when
+///`csv` and `gz` are given. A name which is nothing but an extension is left as it is
+pub fn remove_extension(p0: &Path, extensions: &[&str]) -> Option<String> {
+    let mut name = p0.file_name()?.to_str()?;
+    while let Some((stem, extension)) = name.rsplit_once('.') {
+        if stem.is_empty() || !extensions.contains(&extension.to_lowercase().as_str()) {
+            break;
+        }
+        name = stem;
+    }
+    Some(String::from(name))
 }
+
+///Makes a name usable as a table name in SQL without quoting. See `sql_identifier`
 pub fn sanitize(str: Option<String>) -> Option<String> {
-    str.map(|s| s.replace(" ", "_"))
+    str.map(|s| sql_identifier(&s, "t"))
+}
+
+///Parses a `name=value` assignment for a named parameter in a query.
+///The value is typed the same way CSV cells are, so `age=18` binds an integer
+pub fn parse_parameter(assignment: &str) -> Result<(String, Value), String> {
+    let mut split = assignment.splitn(2, '=');
+    let name = split.next().unwrap_or_default().trim_start_matches(':');
+    let value = split.next().ok_or_else(|| {
+        format!(
+            "Expected a parameter of the form name=value: {}",
+            assignment
+        )
+    })?;
+    if name.is_empty() {
+        return Err(format!("Parameter has no name: {}", assignment));
+    }
+    Ok((format!(":{}", name), typed_value(value)))
+}
+
+///Types a value given on the command line the same way CSV cells are, except that numbers are
+///kept as text unless they're written exactly as the number would be, so codes such as `007`
+///keep their leading zeros. SQLite still compares text with numeric columns as a number
+fn typed_value(value: &str) -> Value {
+    match parse(value) {
+        CsvWrapper::Integer(i) if i.to_string() == value => Value::Integer(i),
+        CsvWrapper::Float(f) if f.to_string() == value => Value::Real(f),
+        _ => Value::Text(String::from(value)),
+    }
+}
+
+///Parses a `name=value` pragma to set on the database, such as `cache_size=-64000`.
+///Names can only contain letters, digits and underscores
+pub fn parse_pragma(assignment: &str) -> Result<(String, Value), String> {
+    let mut split = assignment.splitn(2, '=');
+    let name = split.next().unwrap_or_default().trim();
+    let value = split
+        .next()
+        .ok_or_else(|| format!("Expected a pragma of the form name=value: {}", assignment))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Not a valid pragma name: {}", name));
+    }
+    Ok((name.to_lowercase(), typed_value(value.trim())))
+}
+
+///Parses a number of bytes, optionally followed by a unit such as `512MB` or `4G`.
+///Units are powers of 1024
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Expected a size such as 512MB or 4GB: {}", size))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("Unknown unit in size: {}", size)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size is too large: {}", size))
+}
+
+///Parses a `name=path` table to load a file, glob pattern or directory as. The name has to be
+///usable in SQL without quoting, so it can only contain letters, digits and underscores
+pub fn parse_table(table: &str) -> Result<(String, String), String> {
+    let mut split = table.splitn(2, '=');
+    let name = split.next().unwrap_or_default().trim();
+    let path = split
+        .next()
+        .map(|path| path.trim())
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| format!("Expected a table of the form name=path: {}", table))?;
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!(
+            "Table names can only contain letters, digits and underscores: {}",
+            name
+        ));
+    }
+    Ok((String::from(name), String::from(path)))
+}
+
+///Parses a `table:column[,column...]` index to create once a table is loaded. The table is given
+///the same way as in a query, by its file or its name
+pub fn parse_index(index: &str) -> Result<(String, Vec<String>), String> {
+    let mut split = index.rsplitn(2, ':');
+    let columns = split.next().unwrap_or_default();
+    let table = split.next().ok_or_else(|| {
+        format!(
+            "Expected an index of the form table:column[,column...]: {}",
+            index
+        )
+    })?;
+    let columns: Vec<String> = columns
+        .split(',')
+        .map(|column| String::from(column.trim()))
+        .collect();
+    if table.is_empty() || columns.iter().any(|column| column.is_empty()) {
+        return Err(format!("Index needs a table and its columns: {}", index));
+    }
+    Ok((String::from(table), columns))
+}
+
+///Parses a duration such as `30s`, `500ms`, `5m` or `1h`. A number on its own is in seconds
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let digits = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Expected a duration such as 30s or 5m: {}", duration))?;
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(format!("Unknown unit in duration: {}", duration)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_typed_parameters() {
+        assert_eq!(
+            parse_parameter("age=18"),
+            Ok((String::from(":age"), Value::Integer(18)))
+        );
+        assert_eq!(
+            parse_parameter(":ratio=0.5"),
+            Ok((String::from(":ratio"), Value::Real(0.5)))
+        );
+        assert_eq!(
+            parse_parameter("name=a=b"),
+            Ok((String::from(":name"), Value::Text(String::from("a=b"))))
+        );
+    }
+
+    #[test]
+    fn it_keeps_numbers_with_leading_zeros_as_text() {
+        assert_eq!(
+            parse_parameter("code=007"),
+            Ok((String::from(":code"), Value::Text(String::from("007"))))
+        );
+        assert_eq!(
+            parse_parameter("ratio=0.50"),
+            Ok((String::from(":ratio"), Value::Text(String::from("0.50"))))
+        );
+        assert_eq!(
+            parse_parameter("offset=-3"),
+            Ok((String::from(":offset"), Value::Integer(-3)))
+        );
+    }
+
+    #[test]
+    fn it_rejects_parameters_without_a_value() {
+        assert!(parse_parameter("age").is_err());
+        assert!(parse_parameter("=18").is_err());
+    }
+
+    #[test]
+    fn it_parses_indexes() {
+        assert_eq!(
+            parse_index("testdata/people.csv:age"),
+            Ok((
+                String::from("testdata/people.csv"),
+                vec![String::from("age")]
+            ))
+        );
+        assert_eq!(
+            parse_index("C:/data/people.csv:name, age"),
+            Ok((
+                String::from("C:/data/people.csv"),
+                vec![String::from("name"), String::from("age")]
+            ))
+        );
+        assert!(parse_index("people").is_err());
+        assert!(parse_index("people:").is_err());
+        assert!(parse_index(":age").is_err());
+    }
+
+    #[test]
+    fn it_parses_pragmas() {
+        assert_eq!(
+            parse_pragma("Cache_Size=-64000"),
+            Ok((String::from("cache_size"), Value::Integer(-64000)))
+        );
+        assert_eq!(
+            parse_pragma("temp_store=memory"),
+            Ok((
+                String::from("temp_store"),
+                Value::Text(String::from("memory"))
+            ))
+        );
+        assert!(parse_pragma("cache_size").is_err());
+        assert!(parse_pragma("cache_size; drop table foo=1").is_err());
+    }
+
+    #[test]
+    fn it_parses_sizes() {
+        assert_eq!(parse_size("1000"), Ok(1000));
+        assert_eq!(parse_size("512MB"), Ok(512 << 20));
+        assert_eq!(parse_size("4g"), Ok(4 << 30));
+        assert_eq!(parse_size("2 KiB"), Ok(2048));
+        assert!(parse_size("GB").is_err());
+        assert!(parse_size("1.5GB").is_err());
+        assert!(parse_size("1PB").is_err());
+    }
+
+    #[test]
+    fn it_parses_durations() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("5d").is_err());
+    }
+
+    #[test]
+    fn it_parses_tables() {
+        assert_eq!(
+            parse_table("people=testdata/people.csv"),
+            Ok((String::from("people"), String::from("testdata/people.csv")))
+        );
+        assert_eq!(
+            parse_table("logs=testdata/logs/*.csv"),
+            Ok((String::from("logs"), String::from("testdata/logs/*.csv")))
+        );
+        assert!(parse_table("people").is_err());
+        assert!(parse_table("people=").is_err());
+        assert!(parse_table("my-people=people.csv").is_err());
+        assert!(parse_table("1people=people.csv").is_err());
+    }
+
+    #[test]
+    fn it_derives_table_names_from_files() {
+        let extensions = ["csv", "gz"];
+        let name = |file: &str| sanitize(remove_extension(Path::new(file), &extensions));
+        assert_eq!(name("data/a.v1.csv"), Some(String::from("a_v1")));
+        assert_eq!(name("people.csv.gz"), Some(String::from("people")));
+        assert_eq!(
+            name("occupations with spaces.csv"),
+            Some(String::from("occupations_with_spaces"))
+        );
+        assert_eq!(name("2026-10-01.csv"), Some(String::from("t_2026_10_01")));
+        assert_eq!(name(".csv"), Some(String::from("csv")));
+        assert_eq!(name("sales (copy).csv"), Some(String::from("sales_copy")));
+        assert_eq!(name("order.csv"), Some(String::from("order_")));
+    }
 }
@@ -0,0 +1,3 @@
+select name from testdata/people.csv
+where age > :min_age
+order by name;
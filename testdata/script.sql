@@ -0,0 +1,4 @@
+create temp table adults as select * from testdata/people.csv where age > 17;
+create view names as select name from adults;
+select count(*) from adults;
+select * from names order by name;
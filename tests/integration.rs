@@ -2,6 +2,7 @@ mod query_subcommand {
     use std::process::Command;
 
     use assert_cmd::prelude::*;
+    use predicates::prelude::*;
     fn build_cmd() -> Command {
         let mut cmd = Command::cargo_bin("qsv").unwrap();
         cmd.arg("query");
@@ -62,6 +63,15 @@ mod query_subcommand {
         Ok(())
     }
 
+    #[test]
+    fn it_will_run_a_query_with_files_in_subquery_expressions(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select name, (select count(*) from testdata/occupations.csv) from testdata/people.csv where age in (select minimum_age from testdata/occupations.csv) and exists (select 1 from testdata/statistical.csv)");
+        cmd.assert().success().stdout("Christine,2\n");
+        Ok(())
+    }
+
     #[test]
     fn it_will_run_a_simple_query_with_sqrt_on_float() -> Result<(), Box<dyn std::error::Error>> {
         let mut cmd = build_cmd();
@@ -102,6 +112,53 @@ mod query_subcommand {
         Ok(())
     }
 
+    #[test]
+    fn it_takes_sample_stddevs_of_text_columns() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select round(stddev(number), 4), round(stddev_samp(number), 4), var_samp(number) from testdata/statistical.csv")
+            .arg("--textonly");
+        cmd.assert().success().stdout("1.7078,1.8708,3.5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_takes_stddevs_over_windows() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select number, stddev(number) over (order by number rows between 1 preceding and current row) from testdata/statistical.csv where number < 4");
+        cmd.assert().success().stdout("1,0\n2,0.5\n3,0.5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_summarize_columns_with_aggregate_functions() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut cmd = build_cmd();
+        cmd.arg("select median(age), percentile(age, 50), percentile_cont(age, 0.5), mode(name) from testdata/people.csv");
+        cmd.assert().success().stdout("18,18,18,Michael\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_report_over_time_with_date_functions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select date_trunc('week', parse_date(time, '%d/%m/%Y %H:%M')) as week, count(*) from testdata/requests.csv group by week order by week");
+        cmd.assert()
+            .success()
+            .stdout("2026-10-12 00:00:00,1\n2026-10-19 00:00:00,3\n2026-10-26 00:00:00,1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_summarize_columns_with_approximate_aggregates(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select approx_count_distinct(level), approx_median(latency), approx_top_k(level, 1) from testdata/logs/");
+        cmd.assert()
+            .success()
+            .stdout("3,12,[{\"value\":\"info\",\"count\":3}]\n");
+        Ok(())
+    }
+
     #[test]
     fn it_will_run_with_an_alternate_delimiter() -> Result<(), Box<dyn std::error::Error>> {
         let mut cmd = build_cmd();
@@ -173,16 +230,441 @@ mod query_subcommand {
     }
 
     #[test]
-    fn it_rejects_multiple_sql_statements_in_query() -> Result<(), Box<dyn std::error::Error>> {
+    fn it_runs_multiple_sql_statements_in_query() -> Result<(), Box<dyn std::error::Error>> {
         let mut cmd = build_cmd();
-        cmd.arg("select 1 = 1;select 1 = 1;");
+        cmd.arg("select 40 + 1;select 40 + 2;");
         cmd.arg("--textonly");
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::contains("41\n42\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_shares_tables_and_views_between_statements() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("create temp table adults as select * from testdata/people.csv where age > 17; create view adult_names as select name from adults; select * from adult_names order by name");
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::contains("Abdul\nChristine\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_a_script_from_a_file() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("-f").arg("testdata/script.sql");
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::contains("2\nAbdul\nChristine\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_query_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("-");
+        cmd.with_stdin()
+            .buffer("select count(*) from testdata/people.csv")
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("3"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_binds_named_parameters() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("-f").arg("testdata/adults.sql");
+        cmd.arg("--param").arg("min_age=17");
+        cmd.assert().success().stdout("Abdul\nChristine\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_binds_parameters_as_values_rather_than_sql() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select count(*) from testdata/people.csv where name = :name");
+        cmd.arg("--param=name=x' or '1' = '1");
+        cmd.assert().success().stdout("0\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_on_parameters_without_a_value() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select name from testdata/people.csv where age > :min_age");
+        cmd.arg("--param").arg("minage=17");
+        cmd.assert()
+            .failure()
+            .code(2)
+            .stderr(predicates::str::contains(
+                "No value given for parameter :min_age",
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_binds_parameters_with_leading_zeros_as_text() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select country from testdata/codes.csv where code = :code")
+            .arg("--param")
+            .arg("code=007")
+            .arg("--textonly");
+        cmd.assert().success().stdout("Bond\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_cleanly_on_unsupported_statements() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("insert into foo select * from testdata/people.csv");
+        cmd.assert()
+            .failure()
+            .code(4)
+            .stderr(predicates::str::contains("Unsupported statement"))
+            .stderr(predicates::str::contains("panicked").not());
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_cleanly_on_invalid_sql() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("selec 1");
+        cmd.assert()
+            .failure()
+            .code(3)
+            .stderr(predicates::str::contains("Unable to parse SQL"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_cleanly_on_sqlite_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select * from no_such_table");
+        cmd.assert()
+            .failure()
+            .code(7)
+            .stderr(predicates::str::contains("no such table: no_such_table"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_files_matching_a_glob_as_one_table() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select id, latency from `testdata/logs/2026-10-0[12].csv` order by id");
+        cmd.assert().success().stdout("1,12\n2,30\n3,7.5\n4,51\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_a_directory_as_one_table_with_a_unioned_schema(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select typeof(latency), host from testdata/logs/ order by id desc limit 2");
+        cmd.assert().success().stdout("real,web-1\nreal,null\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_a_filename_column() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select _filename, count(*) from 'testdata/logs/*.csv' group by 1")
+            .arg("--filename-column");
+        cmd.assert().success().stdout(
+            "testdata/logs/2026-10-01.csv,2\ntestdata/logs/2026-10-02.csv,2\ntestdata/logs/2026-10-03.csv,1\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_typed_partition_columns() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select region, year, month, typeof(month) from testdata/sales/ order by year, month, region");
+        cmd.assert().success().stdout(
+            "eu,2025,12,integer\nus,2025,12,integer\neu,2026,1,integer\nus,2026,1,integer\neu,2026,2,integer\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_filters_on_partition_columns() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select month, sum(amount) from testdata/sales/ where year = 2026 and month in (1, 3) group by month; select count(*) from testdata/sales/ where year = 1999; select count(*) from testdata/sales/");
+        cmd.assert().success().stdout("1,200\n0\n5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_gives_the_same_results_with_and_without_pushdown(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let queries = [
+            "select name from testdata/people.csv where age between 13 and 18 and name <> 'Michael'",
+            "select count(*), max(age) from testdata/people.csv where age in (13, 62)",
+            "select p.name from testdata/people.csv p where p.age >= 18 order by 1",
+            "select a.name from testdata/people.csv a join testdata/people.csv b using (name) where b.age < 20 order by 1",
+            "select count(*) from testdata/people.csv where name > 1 and age > 10.0",
+            "select name from testdata/big_ids.csv where id <> 9007199254740992",
+            "select name from testdata/big_ids.csv where id > 9007199254740992.0",
+        ];
+        for query in queries.iter() {
+            let full = build_cmd().arg(query).arg("--no-pushdown").output()?;
+            let pushed_down = build_cmd().arg(query).output()?;
+            assert!(full.status.success());
+            assert_eq!(
+                String::from_utf8(full.stdout)?,
+                String::from_utf8(pushed_down.stdout)?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_pushes_down_comparisons_of_integers_above_2_to_the_53(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select id from testdata/big_ids.csv where id <> 9007199254740992");
+        cmd.assert().success().stdout("9007199254740993\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_whole_tables_for_views() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("create view adults as select name from testdata/people.csv where age > 17; select name from testdata/people.csv where age < 17; select count(*), max(age) from testdata/people.csv");
+        cmd.assert().success().stdout("Michael\n3,62\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_files_in_place_through_a_virtual_table() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select count(*), max(age), typeof(max(age)) from testdata/people.csv")
+            .arg("--vtab");
+        cmd.assert().success().stdout("3,62,integer\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_joins_virtual_tables() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select p.name, o.occupation from testdata/people.csv p join testdata/occupations.csv o on p.age >= o.minimum_age where p.name = 'Abdul' order by 2")
+            .arg("--vtab");
+        cmd.assert()
+            .success()
+            .stdout("Abdul,Bartender\nAbdul,Construction Worker\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_imports_compressed_files_with_vtab() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select name from testdata/people.csv.gz where age > 60")
+            .arg("--vtab");
+        cmd.assert().success().stdout("Abdul\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_indexes_columns_compared_by_joins_and_where_clauses(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select a.name, b.occupation from testdata/people.csv a join testdata/occupations.csv b on a.age >= b.minimum_age where a.name = 'Abdul' order by 2; select name from sqlite_master where type = 'index' order by 1");
+        cmd.assert().success().stdout(
+            "Abdul,Bartender\nAbdul,Construction Worker\nqsv_occupations_minimum_age\nqsv_people_age\nqsv_people_name\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_creates_indexes_given_explicitly() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select count(*) from testdata/people.csv where age >= 18; select name from sqlite_master where type = 'index'")
+            .arg("--no-pushdown")
+            .arg("--no-auto-index")
+            .arg("--index")
+            .arg("people:name,AGE");
+        cmd.assert().success().stdout("2\nqsv_people_name_age\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_sets_pragmas() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select * from pragma_cache_size")
+            .arg("--pragma")
+            .arg("cache_size=-2000");
+        cmd.assert().success().stdout("-2000\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_pragma_names() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select 1")
+            .arg("--pragma")
+            .arg("cache_size;select=1");
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("Not a valid pragma name"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_moves_the_database_to_a_temporary_file_over_its_memory_limit(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = std::env::temp_dir().join(format!("qsv_spill_test_{}", std::process::id()));
+        std::fs::create_dir_all(&temp_dir)?;
+        let mut cmd = build_cmd();
+        cmd.env("RUST_LOG", "debug")
+            .arg("select count(*), sum(age) from testdata/people.csv")
+            .arg("--memory-limit")
+            .arg("1KB")
+            .arg("--temp-dir")
+            .arg(&temp_dir);
+        cmd.assert()
+            .success()
+            .stdout(predicates::str::contains("moving it to"))
+            .stdout(predicates::str::contains("3,"));
+        // the temporary file is removed once the query has run
+        assert_eq!(std::fs::read_dir(&temp_dir)?.count(), 0);
+        std::fs::remove_dir(&temp_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_cancels_queries_which_run_past_their_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select 'first'; with recursive r(n) as (select 1 union all select n + 1 from r) select n from r")
+            .arg("--timeout")
+            .arg("200ms");
+        cmd.assert()
+            .failure()
+            .code(9)
+            .stdout(predicates::str::starts_with("first\n1\n2\n"))
+            .stderr("Error: Query timed out after 200ms\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_cancels_queries_on_ctrl_c() -> Result<(), Box<dyn std::error::Error>> {
+        let child = build_cmd()
+            .arg("with recursive r(n) as (select 1 union all select n + 1 from r) select count(*) from r")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+        }
+        let output = child.wait_with_output()?;
+        assert_eq!(output.status.code(), Some(9));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Error: Query was cancelled\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_files_with_the_same_name_as_different_tables(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select count(*) from testdata/people.csv a join testdata/people.csv.gz b using (name); select name from sqlite_master where type = 'table' order by name")
+            .arg("--no-pushdown");
+        cmd.assert().success().stdout("3\npeople\npeople_2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_files_under_the_table_names_given() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select count(*) from folks join testdata/people.csv p using (name) where folks.age > 18; select name from sqlite_master where type = 'table'")
+            .arg("--no-pushdown")
+            .arg("--table")
+            .arg("folks=testdata/people.csv");
+        cmd.assert().success().stdout("1\nfolks\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_table_names_which_need_quoting() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select * from folks")
+            .arg("--table")
+            .arg("my-folks=testdata/people.csv");
         cmd.assert().failure().stderr(predicates::str::contains(
-            "Expected exactly one SQL statement in query input",
+            "Table names can only contain letters, digits and underscores",
         ));
         Ok(())
     }
 
+    #[test]
+    fn it_names_tables_so_they_never_need_quoting() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg(
+            "select count(*) from 'testdata/Sign-ups (2026).csv'; select name from sqlite_master",
+        )
+        .arg("--no-pushdown");
+        cmd.assert().success().stdout("2\nSign_ups_2026\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_normalizes_headers() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select first_name, last_name, age_years, c_2026_score from 'testdata/Sign-ups (2026).csv' where age_years > 26")
+            .arg("--normalize-headers")
+            .arg("-o");
+        cmd.assert()
+            .success()
+            .stdout("first_name,last_name,age_years,c_2026_score\nAlice,Smith,30,9.5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_original_headers_of_normalized_columns(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for vtab in &[false, true] {
+            let mut cmd = build_cmd();
+            cmd.arg("select first_name, age_years * 2 as double from 'testdata/Sign-ups (2026).csv' order by 2")
+                .arg("--normalize-headers")
+                .arg("--original-headers")
+                .arg("-o");
+            if *vtab {
+                cmd.arg("--vtab");
+            }
+            cmd.assert()
+                .success()
+                .stdout("First Name,double\nBob,50\nAlice,60\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_when_indexing_a_column_which_doesnt_exist(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select * from testdata/people.csv")
+            .arg("--index")
+            .arg("testdata/people.csv:height");
+        cmd.assert()
+            .failure()
+            .code(2)
+            .stderr(predicates::str::contains("has no column height"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_tables_in_parallel() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.env("RAYON_NUM_THREADS", "4")
+            .arg("select count(*), sum(l.latency), count(distinct p.name) from testdata/logs/ l join testdata/people.csv p join testdata/sales/ s on s.amount > 200");
+        cmd.assert().success().stdout("15,328.5,3\n");
+        Ok(())
+    }
+
     #[test]
     fn it_succeeds_with_a_single_statement_ended_with_semicolon(
     ) -> Result<(), Box<dyn std::error::Error>> {
@@ -201,6 +683,232 @@ mod query_subcommand {
         Ok(())
     }
 
+    #[test]
+    fn it_writes_results_as_json() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select name, age from testdata/people.csv where age > 18")
+            .arg("--format")
+            .arg("json");
+        cmd.assert()
+            .success()
+            .stdout("[{\"name\":\"Abdul\",\"age\":62}]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_json_in_cells() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select payload -> 'user' ->> 'name', json_array_length(payload, '$.items') from testdata/events.csv where payload ->> '$.user.plan' = 'pro'");
+        cmd.assert().success().stdout("Ann,2\nCy,1\n");
+        let mut cmd = build_cmd();
+        cmd.arg("select sum(value) from testdata/events.csv, json_each(payload, '$.items')");
+        cmd.assert().success().stdout("12\n");
+        let mut cmd = build_cmd();
+        cmd.arg("select json_flatten(payload) from testdata/events.csv where id = 2");
+        cmd.assert()
+            .success()
+            .stdout("{\"user.name\":\"Bo\",\"user.plan\":\"free\",\"items\":[]}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_json_values_as_json() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select payload ->> '$.user.plan' as plan, json_group_array(id) as ids, json_object('first', min(id)) as first from testdata/events.csv group by plan")
+            .arg("--format")
+            .arg("json");
+        cmd.assert().success().stdout(
+            "[{\"plan\":\"free\",\"ids\":[2],\"first\":{\"first\":2}},\n{\"plan\":\"pro\",\"ids\":[1,3],\"first\":{\"first\":1}}]\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_most_common_values_as_json() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select approx_top_k(level, 1) as top from testdata/logs/")
+            .arg("--format")
+            .arg("json");
+        cmd.assert()
+            .success()
+            .stdout("[{\"top\":[{\"value\":\"info\",\"count\":3}]}]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_text_which_looks_like_json_as_a_string() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut cmd = build_cmd();
+        cmd.arg("select id, payload -> '$.items' as items, payload ->> '$.items' as text from testdata/events.csv where id = 1")
+            .arg("--format")
+            .arg("json");
+        cmd.assert()
+            .success()
+            .stdout("[{\"id\":1,\"items\":[3,4],\"text\":\"[3,4]\"}]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_columns_used_only_by_json_operators() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select id from testdata/events.csv where payload ->> '$.user.plan' = 'pro'");
+        cmd.assert().success().stdout("1\n3\n");
+        Ok(())
+    }
+
+    ///Runs qsv in a directory of its own, with a user config directory of its own
+    fn build_cmd_with_config(
+        name: &str,
+        user_config: Option<&str>,
+        project_config: Option<&str>,
+    ) -> Result<(Command, std::path::PathBuf), Box<dyn std::error::Error>> {
+        let directory =
+            std::env::temp_dir().join(format!("qsv_config_{}_{}", name, std::process::id()));
+        let project = directory.join("project");
+        let user = directory.join("user");
+        std::fs::create_dir_all(&project)?;
+        std::fs::create_dir_all(user.join("qsv"))?;
+        if let Some(config) = user_config {
+            std::fs::write(user.join("qsv").join("config.toml"), config)?;
+        }
+        if let Some(config) = project_config {
+            std::fs::write(project.join(".qsv.toml"), config)?;
+        }
+        let mut cmd = build_cmd();
+        cmd.current_dir(&project).env("XDG_CONFIG_HOME", &user);
+        Ok((cmd, directory))
+    }
+
+    fn testdata(file: &str) -> String {
+        format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    #[test]
+    fn it_reads_defaults_and_sources_from_the_project_config(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let config = format!(
+            "output-header = true\n[sources]\nfolks = \"{}\"\n",
+            testdata("people.csv")
+        );
+        let (mut cmd, directory) = build_cmd_with_config("project", None, Some(&config))?;
+        cmd.arg("select count(*) as people from folks");
+        cmd.assert().success().stdout("people\n3\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_sources_relative_to_the_project_config_from_a_subdirectory(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let config = "[sources]\nfolks = \"data/people.csv\"\n";
+        let (mut cmd, directory) = build_cmd_with_config("relative", None, Some(config))?;
+        let project = directory.join("project");
+        std::fs::create_dir_all(project.join("data"))?;
+        std::fs::create_dir_all(project.join("reports"))?;
+        std::fs::copy(
+            testdata("people.csv"),
+            project.join("data").join("people.csv"),
+        )?;
+        cmd.current_dir(project.join("reports"))
+            .arg("select count(*) from folks");
+        cmd.assert().success().stdout("3\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_the_command_line_then_the_project_config_to_the_user_config(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let user = format!(
+            "output-header = true\nformat = \"json\"\n[sources]\nfolks = \"{}\"\njobs = \"{}\"\n",
+            testdata("people.csv"),
+            testdata("occupations.csv")
+        );
+        let project = format!(
+            "format = \"csv\"\n[sources]\nfolks = \"{}\"\n",
+            testdata("occupations.csv")
+        );
+        let (mut cmd, directory) =
+            build_cmd_with_config("precedence", Some(&user), Some(&project))?;
+        cmd.arg("select (select count(*) from folks) as folks, (select count(*) from jobs) as jobs, (select count(*) from people) as people")
+            .arg("--table")
+            .arg(format!("people={}", testdata("people.csv")));
+        cmd.assert().success().stdout("folks,jobs,people\n2,2,3\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_turns_off_flags_set_in_the_config() -> Result<(), Box<dyn std::error::Error>> {
+        let config = "output-header = true\ntextonly = true\n";
+        let (mut cmd, directory) = build_cmd_with_config("negated", None, Some(config))?;
+        cmd.arg(format!(
+            "select typeof(age) from {} limit 1",
+            testdata("people.csv")
+        ))
+        .arg("--no-output-header")
+        .arg("--textonly")
+        .arg("--no-textonly");
+        cmd.assert().success().stdout("integer\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_invalid_config_files() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut cmd, directory) =
+            build_cmd_with_config("invalid", None, Some("delimeter = \";\"\n"))?;
+        cmd.arg("select 1");
+        cmd.assert()
+            .code(5)
+            .stderr(predicates::str::contains(".qsv.toml"))
+            .stderr(predicates::str::contains("unknown field `delimeter`"));
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_functions_defined_in_the_config() -> Result<(), Box<dyn std::error::Error>> {
+        let config = "[functions.label]\nargs = [\"name\", \"age\"]\nsql = \"name || ' (' || age || ')'\"\n\n[functions.product]\nargs = [\"x\"]\ninitial = \"1\"\nstep = \"state * x\"\n";
+        let (mut cmd, directory) = build_cmd_with_config("functions", Some(config), None)?;
+        cmd.arg(format!(
+            "select label(name, age), (select product(age) from {0}) from {0} where age > 18",
+            testdata("people.csv")
+        ));
+        cmd.assert().success().stdout("Abdul (62),14508\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_only_runs_the_projects_functions_when_allowed() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let config = "[functions.double]\nargs = [\"x\"]\nsql = \"x * 2\"\n";
+        let (mut cmd, directory) = build_cmd_with_config("project_functions", None, Some(config))?;
+        cmd.arg("select double(21)");
+        cmd.assert()
+            .code(2)
+            .stderr(predicates::str::contains("--allow-project-extensions"));
+        let (mut cmd, _) = build_cmd_with_config("project_functions", None, Some(config))?;
+        cmd.arg("select double(21)")
+            .arg("--allow-project-extensions");
+        cmd.assert().success().stdout("42\n");
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_extensions_which_cant_be_loaded() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select 1")
+            .arg("--load-extension")
+            .arg("testdata/no_such_extension");
+        cmd.assert()
+            .code(7)
+            .stderr(predicates::str::contains("no_such_extension"));
+        Ok(())
+    }
+
     #[test]
     fn it_succeeds_without_running_sql_in_file_headers() -> Result<(), Box<dyn std::error::Error>> {
         let mut cmd = build_cmd();
@@ -208,7 +916,73 @@ mod query_subcommand {
         cmd.assert().success();
         Ok(())
     }
+
+    #[test]
+    fn it_quotes_headers_containing_any_characters() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select * from testdata/sql_injection_unquoted.csv")
+            .arg("-o");
+        cmd.assert()
+            .success()
+            .stdout("id,name;drop table people,it's a \\path\n1,Ada,x\n");
+        Ok(())
+    }
+}
+mod explain_subcommand {
+    use std::process::Command;
+
+    use assert_cmd::prelude::*;
+    use predicates::str::contains;
+
+    fn build_cmd() -> Command {
+        let mut cmd = Command::cargo_bin("qsv").unwrap();
+        cmd.arg("explain");
+        cmd
+    }
+
+    #[test]
+    fn it_explains_the_rewritten_query_schema_and_plan() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("select name from testdata/people.csv where age > :age")
+            .arg("--param")
+            .arg("age=18");
+        cmd.assert().success().stdout(
+            "Statement: SELECT name FROM testdata/people.csv WHERE age > :age
+Rewritten: SELECT name FROM people WHERE age > :age
+Tables:
+\ttestdata/people.csv as people
+\t\tname -> text
+\t\tage -> integer
+Query plan:
+\tSEARCH people USING INDEX qsv_people_age (age>?)
+",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_statements_other_than_selects() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("create table adults as select * from testdata/people.csv where age >= 18; select count(*) from adults")
+            .arg("--no-auto-index");
+        cmd.assert()
+            .success()
+            .stdout(contains("\n\nStatement: SELECT count(*) FROM adults\n"))
+            .stdout(contains("Query plan:\n\tSCAN adults\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_sets_up_the_database_as_query_does() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("create view v as select 1")
+            .arg("--pragma")
+            .arg("query_only=true");
+        cmd.assert().failure().code(7).stderr(contains("readonly"));
+        Ok(())
+    }
 }
+
 mod analyze_subcommand {
     use std::process::Command;
 
@@ -263,6 +1037,17 @@ mod analyze_subcommand {
         Ok(())
     }
 
+    #[test]
+    fn it_reads_the_query_from_a_file() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.arg("--file").arg("testdata/adults.sql");
+        cmd.assert()
+            .success()
+            .stdout(contains("testdata/people.csv:"))
+            .stdout(contains("age -> integer"));
+        Ok(())
+    }
+
     #[test]
     fn it_will_run_a_simple_query_with_unions() -> Result<(), Box<dyn std::error::Error>> {
         let mut cmd = build_cmd();
@@ -317,3 +1102,66 @@ mod stats_subcommand {
         Ok(())
     }
 }
+
+mod shell_subcommand {
+    use std::process::Command;
+
+    use assert_cmd::prelude::*;
+    use predicates::str::contains;
+
+    fn build_cmd() -> Command {
+        let mut cmd = Command::cargo_bin("qsv").unwrap();
+        cmd.arg("shell");
+        cmd
+    }
+
+    #[test]
+    fn it_keeps_loaded_files_across_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.with_stdin()
+            .buffer(".load testdata/people.csv as people\n.tables\nselect max(age) from people;\nselect count(*)\nfrom testdata/people.csv;\n")
+            .assert()
+            .success()
+            .stdout(contains("Loaded testdata/people.csv as people"))
+            .stdout(contains("people\n"))
+            .stdout(contains("62"))
+            .stdout(contains("3"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_output_json() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.with_stdin()
+            .buffer(".mode json\nselect name, age from testdata/people.csv where age = 13;\n")
+            .assert()
+            .success()
+            .stdout(contains("[{\"name\":\"Michael\",\"age\":13}]"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_shows_the_schema() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.with_stdin()
+            .buffer(".load testdata/people.csv\n.schema people\n")
+            .assert()
+            .success()
+            .stdout(contains(
+                r#"CREATE TABLE "people" ("name" text, "age" integer)"#,
+            ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_continues_after_an_error() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = build_cmd();
+        cmd.with_stdin()
+            .buffer("select * from no_such_table;\nselect 41 + 1;\n")
+            .assert()
+            .success()
+            .stderr(contains("no such table"))
+            .stdout(contains("42"));
+        Ok(())
+    }
+}